@@ -0,0 +1,10 @@
+# Scoring profile penalizing only (near-)perfect off-targets
+# Number of nucleotides closest to the PAM considered part of the seed
+seed_size = 8
+# Maximum number of mismatches in off-targets
+max_mutations = 2
+# Maximum number of mismatches in the seed of off-targets
+max_seed_mutations = 1
+# Scores; rows (separated by ';') correspond to 0, 1, ... seed mismatches and
+# columns (separated by ',') to 0, 1, ... non-seed mismatches
+matrix = 500, 100, 10; 50, 5
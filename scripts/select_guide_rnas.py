@@ -421,7 +421,9 @@ def parse_args(argv: List[str]) -> argparse.Namespace:
         "  $ tabix -S 1 -s 3 -b 6 -e 6 candidates.tsv.gz\n"
         "  $ select_guide_rnas.py --crispyr candidates.tsv.gz ...\n\n"
         "Note that the regions in the BED file passed to CRISPyR should be unique and "
-        "non-overlapping for `tabix` to be albe to properly index the file.",
+        "non-overlapping for `tabix` to be albe to properly index the file. If a "
+        "custom scoring profile was used, then the output starts with 4 lines "
+        "containing the profile, and `-S 5` must be used instead of `-S 1`.",
     )
 
     parser.add_argument(
@@ -549,7 +551,11 @@ def main(argv: List[str]) -> int:
     printed_hits = 0
 
     with gzip.open(args.crispyr, "rt") as handle:
-        crispyr_header = handle.readline().rstrip().split("\t")
+        # Skip the scoring profile written by CRISPyR when a custom profile is used
+        line = handle.readline()
+        while line.startswith("#"):
+            line = handle.readline()
+        crispyr_header = line.rstrip().split("\t")
 
     with pysam.TabixFile(args.crispyr) as handle:
         for gene in sorted(genes, key=lambda it: it["name"].lower()):
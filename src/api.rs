@@ -0,0 +1,514 @@
+//! Functions for using CRISPyR as a library, without the command-line interface: Building
+//! indexes from sequences held in memory, finding target sites in a sequence, and scoring gRNAs
+//! and their off-targets. Nothing is written to STDOUT or STDERR.
+//!
+//! Work is performed in parallel on the current rayon thread pool; to limit the number of
+//! threads used, call these functions from within `rayon::ThreadPool::install`.
+
+use std::collections::HashSet;
+use std::ops::Range;
+
+use bio::alphabets::dna;
+use bio_types::strand::Strand;
+use rayon::prelude::*;
+
+use crate::common::{encode_dna, KMer};
+use crate::constants::{MAX_REFSEQS, MAX_SEQUENCE_LEN};
+use crate::enzyme::Enzyme;
+use crate::errors::*;
+use crate::index::{KMerIndex, KMerMap, Position};
+use crate::pam;
+use crate::provenance::{Provenance, SequenceInfo};
+use crate::score::{find_offtargets, OffTarget, Scorer};
+use crate::twobit::{TwoBitRecord, TwoBitSequences};
+
+pub(crate) type Sites = Vec<(Position, KMer)>;
+
+/// Returns true if the target site (gRNA and PAM) of the K-mer window starting at `idx`
+/// overlaps masked bases
+fn is_masked(enzyme: &Enzyme, masked: &[bool], idx: usize, window_len: usize) -> bool {
+    let len = usize::max(enzyme.grna_len, window_len);
+    let (start, end) = match enzyme.pams.position() {
+        pam::Position::Head => (idx, usize::min(masked.len(), idx + len)),
+        pam::Position::Tail => ((idx + window_len).saturating_sub(len), idx + window_len),
+    };
+
+    masked[start..end].iter().any(|&v| v)
+}
+
+/// Collects the target sites in the given windows (by starting position) of one strand
+fn collect_strand_sites(
+    enzyme: &Enzyme,
+    refseq: u32,
+    sequence: &[u8],
+    masked: Option<&[bool]>,
+    forward: bool,
+    windows: Range<usize>,
+) -> Result<Sites> {
+    let window_len = enzyme.pams.len() + enzyme.kmer_len();
+    let end = usize::min(sequence.len(), windows.end.saturating_add(window_len - 1));
+    let start = usize::min(windows.start, end);
+
+    sequence[start..end]
+        .par_windows(window_len)
+        .enumerate()
+        .filter_map(|(idx, window)| {
+            let idx = start + idx;
+            let (pam, pam_pos, kmer) = enzyme.pams.kmer(window)?;
+            if let Some(masked) = masked {
+                if is_masked(enzyme, masked, idx, window_len) {
+                    return None;
+                }
+            }
+
+            // If the cut-site is unknown, then save the starting position of the target seq
+            let pos = (idx + pam_pos) as i64 + enzyme.cutsite as i64;
+            let position = if forward {
+                Position::new(refseq, pos, true, pam)
+            } else {
+                Position::new(refseq, sequence.len() as i64 - pos, false, pam)
+            };
+
+            Some(position.map(|position| (position, kmer)))
+        })
+        .collect()
+}
+
+/// Collects the positions and K-mers of the target sites on both strands of an (upper-case)
+/// sequence, excluding sites overlapping masked bases
+pub(crate) fn collect_sites(
+    enzyme: &Enzyme,
+    refseq: u32,
+    sequence: &[u8],
+    masked: Option<&[bool]>,
+) -> Result<Sites> {
+    let reverse = dna::revcomp(sequence);
+    let reverse_masked: Option<Vec<bool>> = masked.map(|v| v.iter().rev().cloned().collect());
+    let masked = masked.zip(reverse_masked.as_deref());
+
+    collect_sites_in(
+        enzyme,
+        refseq,
+        (sequence, &reverse),
+        masked,
+        0..sequence.len(),
+    )
+}
+
+/// Collects the positions and K-mers of the target sites on both strands of an (upper-case)
+/// sequence and its reverse complement, excluding sites overlapping masked bases (given for
+/// both strands). Only sites in windows starting (on the forward strand) in the given range are
+/// collected, allowing long sequences to be processed piecemeal.
+pub(crate) fn collect_sites_in(
+    enzyme: &Enzyme,
+    refseq: u32,
+    (sequence, reverse): (&[u8], &[u8]),
+    masked: Option<(&[bool], &[bool])>,
+    windows: Range<usize>,
+) -> Result<Sites> {
+    // Windows on the reverse strand that start (on the forward strand) in the given range
+    let window_len = enzyme.pams.len() + enzyme.kmer_len();
+    let reverse_windows = (sequence.len() + 1).saturating_sub(windows.end + window_len)
+        ..(sequence.len() + 1).saturating_sub(windows.start + window_len);
+
+    let (forward, reverse) = rayon::join(
+        || {
+            let masked = masked.map(|v| v.0);
+            collect_strand_sites(enzyme, refseq, sequence, masked, true, windows)
+        },
+        || {
+            let masked = masked.map(|v| v.1);
+            collect_strand_sites(enzyme, refseq, reverse, masked, false, reverse_windows)
+        },
+    );
+
+    let mut sites = forward?;
+    sites.append(&mut reverse?);
+
+    Ok(sites)
+}
+
+/// Options for building an index using `IndexBuilder`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct IndexOptions {
+    /// Records the positions of target sites, as required to find off-targets
+    pub positions: bool,
+    /// Excludes target sites overlapping soft-masked (lower-case) sequence
+    pub exclude_masked: bool,
+    /// Embeds the (packed) sequences in the index; requires positions
+    pub embed_sequences: bool,
+}
+
+/// Builds a K-mer index from sequences held in memory. The resulting index may be used directly
+/// or written to disk.
+pub struct IndexBuilder {
+    enzyme: Enzyme,
+    options: IndexOptions,
+    refseqs: Vec<String>,
+    names: HashSet<String>,
+    sequences: Vec<SequenceInfo>,
+    packed: TwoBitSequences,
+    kmers: KMerMap,
+}
+
+impl IndexBuilder {
+    /// Creates a builder for an index without positions, using the K-mer length of the enzyme
+    pub fn new(enzyme: &Enzyme) -> IndexBuilder {
+        Self::with_options(enzyme, IndexOptions::default())
+    }
+
+    /// Creates a builder for an index using the given options and the K-mer length of the enzyme
+    pub fn with_options(enzyme: &Enzyme, options: IndexOptions) -> IndexBuilder {
+        let pams = enzyme.pams.pams().len();
+
+        IndexBuilder {
+            enzyme: enzyme.clone(),
+            refseqs: Vec::new(),
+            names: HashSet::new(),
+            sequences: Vec::new(),
+            packed: TwoBitSequences::default(),
+            kmers: KMerMap::new(pams, enzyme.kmer_len(), options.positions),
+            options,
+        }
+    }
+
+    /// Adds the target sites in a sequence to the index. Names must be unique.
+    pub fn add(&mut self, name: &str, sequence: &[u8]) -> Result<()> {
+        if self.refseqs.len() as u64 >= MAX_REFSEQS {
+            return Err(format!(
+                "too many reference sequences; at most {} are supported",
+                MAX_REFSEQS
+            )
+            .into());
+        } else if sequence.len() as u64 > MAX_SEQUENCE_LEN {
+            return Err(format!(
+                "sequence {:?} is too long ({} bp); at most {} bp are supported",
+                name,
+                sequence.len(),
+                MAX_SEQUENCE_LEN
+            )
+            .into());
+        } else if !self.names.insert(name.to_owned()) {
+            return Err(format!("duplicate reference sequence {:?}", name).into());
+        }
+
+        let refseq = self.refseqs.len() as u32;
+        let upper = sequence.to_ascii_uppercase();
+        let masked: Option<Vec<bool>> = if self.options.exclude_masked {
+            Some(sequence.iter().map(u8::is_ascii_lowercase).collect())
+        } else {
+            None
+        };
+
+        for (position, kmer) in collect_sites(&self.enzyme, refseq, &upper, masked.as_deref())? {
+            self.kmers.add(position, kmer);
+        }
+
+        self.refseqs.push(name.to_owned());
+        self.sequences.push(SequenceInfo::new(&upper));
+        if self.options.embed_sequences {
+            let (record, data) = TwoBitRecord::pack(&upper);
+            self.packed.push(record, &data);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the finished index, or an error if sequences were embedded without positions
+    pub fn build(self) -> Result<KMerIndex> {
+        if self.options.embed_sequences && !self.options.positions {
+            return Err("sequences can only be embedded in indexes with positions".into());
+        }
+
+        let provenance = Provenance {
+            sources: Vec::new(),
+            sequences: self.sequences,
+        };
+
+        let mut index = KMerIndex::new(&self.enzyme, self.refseqs, self.kmers)
+            .with_provenance(provenance)?
+            .with_masked_excluded(self.options.exclude_masked);
+        if self.options.embed_sequences {
+            index = index.with_sequences(self.packed);
+        }
+
+        Ok(index)
+    }
+}
+
+/// A target site (gRNA and PAM) found in a sequence
+#[derive(Clone, Debug, PartialEq)]
+pub struct TargetSite {
+    /// Start and end (0-based, exclusive) of the target site on the forward strand
+    pub start: isize,
+    pub end: isize,
+    /// Positions (0-based) of the nucleotides following the cut on the strand containing the
+    /// PAM and on the opposite strand, on the forward strand
+    pub cutsite: isize,
+    pub cutsite_bottom: isize,
+    pub strand: Strand,
+    /// The (upper-case) target sequence, on the strand containing the PAM
+    pub sequence: Vec<u8>,
+    /// The PAM-proximal K-mer used to look up off-targets in an index
+    pub kmer: KMer,
+    /// The PAM matched by the target site (see `PAMSet::pams`)
+    pub pam: usize,
+    /// True if the target site overlaps soft-masked (lower-case) sequence
+    pub masked: bool,
+}
+
+impl TargetSite {
+    /// Returns the target sequence with `upstream` and `downstream` flanking nucleotides, on the
+    /// strand containing the PAM, or None if the flanks extend past the ends of the sequence in
+    /// which the target site was found.
+    pub fn context(&self, sequence: &[u8], upstream: usize, downstream: usize) -> Option<Vec<u8>> {
+        let (before, after) = match self.strand {
+            Strand::Reverse => (downstream, upstream),
+            _ => (upstream, downstream),
+        };
+
+        if self.start < before as isize || self.end + after as isize > sequence.len() as isize {
+            return None;
+        }
+
+        let context =
+            sequence[self.start as usize - before..self.end as usize + after].to_ascii_uppercase();
+        match self.strand {
+            Strand::Reverse => Some(dna::revcomp(context)),
+            _ => Some(context),
+        }
+    }
+}
+
+fn find_strand_targets(enzyme: &Enzyme, sequence: &[u8], masked: &[bool]) -> Vec<TargetSite> {
+    let pams = &enzyme.pams;
+
+    sequence
+        .par_windows(enzyme.grna_len)
+        .enumerate()
+        .filter_map(|(idx, window)| {
+            let (pam, pam_pos, kmer) = pams.kmer(window)?;
+
+            Some(TargetSite {
+                start: idx as isize,
+                end: (idx + window.len()) as isize,
+                cutsite: (idx + pam_pos) as isize + enzyme.cutsite,
+                cutsite_bottom: (idx + pam_pos) as isize + enzyme.cutsite_bottom,
+                strand: Strand::Forward,
+                sequence: window.to_owned(),
+                kmer,
+                pam,
+                masked: masked[idx..idx + window.len()].iter().any(|&v| v),
+            })
+        })
+        .collect()
+}
+
+/// Finds the target sites of an enzyme on both strands of a sequence, sorted by cut-site (forward
+/// strand sites first for ties)
+pub fn find_targets(enzyme: &Enzyme, sequence: &[u8]) -> Vec<TargetSite> {
+    // Soft-masked (lower-case) bases are only used to flag targets in masked sequence
+    let upper = sequence.to_ascii_uppercase();
+    let masked: Vec<bool> = sequence.iter().map(u8::is_ascii_lowercase).collect();
+
+    let mut targets = find_strand_targets(enzyme, &upper, &masked);
+
+    let len = sequence.len() as isize;
+    let reverse = dna::revcomp(&upper);
+    let masked: Vec<bool> = masked.into_iter().rev().collect();
+    for mut site in find_strand_targets(enzyme, &reverse, &masked) {
+        let start = len - site.end;
+        let end = len - site.start;
+
+        site.start = start;
+        site.end = end;
+        site.cutsite = len - site.cutsite;
+        site.cutsite_bottom = len - site.cutsite_bottom;
+        site.strand = Strand::Reverse;
+
+        targets.push(site);
+    }
+
+    targets.sort_by_key(|v| v.cutsite);
+    targets
+}
+
+/// Returns the K-mer of a gRNA target sequence including the PAM (in either case), or None if
+/// the sequence does not match a PAM of the enzyme or contains nucleotides other than ACGT.
+pub fn guide_kmer(enzyme: &Enzyme, guide: &[u8]) -> Option<KMer> {
+    let pams = &enzyme.pams;
+    let guide = guide.to_ascii_uppercase();
+
+    if guide.len() >= pams.kmer_len() + pams.len() && pams.matches(&guide) {
+        encode_dna(pams.kmer_slice(&guide))
+    } else {
+        None
+    }
+}
+
+/// Scores a gRNA target sequence including the PAM, returning one score per model used by the
+/// scorer (see `Scorer::models`)
+pub fn score_guide(scorer: &Scorer, guide: &[u8]) -> Result<Vec<f64>> {
+    match guide_kmer(scorer.index().enzyme(), guide) {
+        Some(kmer) => Ok(scorer.scores(kmer)),
+        None => Err(format!("invalid gRNA sequence {:?}", String::from_utf8_lossy(guide)).into()),
+    }
+}
+
+/// Returns the positions of mismatches between the spacer of a gRNA target sequence and the
+/// spacer of a (potential off-)target site, or None if either sequence (including the PAM) does
+/// not have the length of the gRNAs of the enzyme. Positions are 1-based and numbered 5' to 3'
+/// along the spacer, excluding the PAM. Nucleotides other than ACGT count as mismatches.
+pub fn spacer_mismatches(enzyme: &Enzyme, guide: &[u8], site: &[u8]) -> Option<Vec<usize>> {
+    let grna_len = enzyme.grna_len;
+    if guide.len() != grna_len || site.len() != grna_len {
+        return None;
+    }
+
+    let pam_len = enzyme.pams.len();
+    let spacer = match enzyme.pams.position() {
+        pam::Position::Head => pam_len..grna_len,
+        pam::Position::Tail => 0..grna_len - pam_len,
+    };
+
+    let mismatches = guide[spacer.clone()]
+        .iter()
+        .zip(&site[spacer])
+        .enumerate()
+        .filter(|(_, (expected, observed))| {
+            let observed = observed.to_ascii_uppercase();
+
+            !matches!(observed, b'A' | b'C' | b'G' | b'T')
+                || expected.to_ascii_uppercase() != observed
+        })
+        .map(|(idx, _)| idx + 1)
+        .collect();
+
+    Some(mismatches)
+}
+
+/// A potential off-target site of a gRNA
+#[derive(Clone, Debug)]
+pub struct OffTargetSite<'a> {
+    /// Name of the reference sequence containing the site
+    pub name: &'a str,
+    /// Start and end (0-based, exclusive) of the target site (gRNA and PAM) on the forward
+    /// strand; may extend past either end of the reference sequence
+    pub start: isize,
+    pub end: isize,
+    /// Positions (0-based) of the nucleotides following the cut on the strand containing the
+    /// PAM and on the opposite strand, on the forward strand
+    pub cutsite: isize,
+    pub cutsite_bottom: isize,
+    pub strand: char,
+    /// The off-target sequence on the strand containing the PAM, if sequences are embedded in
+    /// the index; nucleotides past either end of the reference sequence are reported as N.
+    /// Only the target site (gRNA and PAM) is included, without flanking sequence
+    pub sequence: Option<Vec<u8>>,
+    /// Positions of mismatches across the full spacer, if sequences are embedded in the index
+    /// (see `spacer_mismatches`); K-mers only cover the PAM-proximal part of the spacer
+    pub spacer_mismatches: Option<Vec<usize>>,
+    /// Scores of the off-target, one per model used by the scorer (see `Scorer::models`)
+    pub scores: Vec<f64>,
+    /// The matching K-mer, mismatches, and position of the site in the index
+    pub offtarget: OffTarget<'a>,
+}
+
+/// Fetches bases from `start` to `end` (0-based, exclusive) of a sequence embedded in an index,
+/// using N for positions past either end of the sequence. Returns None if sequences are not
+/// embedded in the index.
+fn fetch_embedded(
+    index: &KMerIndex,
+    refseq: usize,
+    start: isize,
+    end: isize,
+    strand: char,
+) -> Option<Vec<u8>> {
+    let refseq_len = index.sequence_len(refseq)? as isize;
+    let fetch_start = isize::max(0, start);
+    let fetch_end = isize::min(refseq_len, end);
+
+    let mut sequence = vec![b'N'; (fetch_start - start).min(end - start) as usize];
+    if fetch_start < fetch_end {
+        let bases = index.fetch_sequence(refseq, fetch_start as u64, fetch_end as u64)?;
+        sequence.extend(bases);
+    }
+    sequence.resize((end - start) as usize, b'N');
+
+    if strand == '-' {
+        Some(dna::revcomp(sequence))
+    } else {
+        Some(sequence)
+    }
+}
+
+/// Finds potential off-targets of a gRNA target sequence including the PAM, with a CRISPy
+/// score of at least `min_score`. The index must contain positions.
+pub fn offtargets<'a>(
+    scorer: &'a Scorer<'a>,
+    guide: &[u8],
+    min_score: u64,
+) -> Result<impl Iterator<Item = OffTargetSite<'a>> + 'a> {
+    let index = scorer.index();
+    let enzyme = index.enzyme();
+    if !index.has_positions() {
+        return Err("index does not contain positions; cannot find off-targets".into());
+    }
+
+    let kmer = guide_kmer(enzyme, guide).ok_or_else(|| {
+        Error::from(format!(
+            "invalid gRNA sequence {:?}",
+            String::from_utf8_lossy(guide)
+        ))
+    })?;
+
+    // Offsets of the target site relative to the cut-site on the strand containing the PAM
+    let grna_len = enzyme.grna_len as isize;
+    let pam_len = enzyme.pams.len() as isize;
+    let cutsite = enzyme.cutsite;
+    let (offset_start, offset_end) = match enzyme.pams.position() {
+        pam::Position::Head => (-cutsite, grna_len - cutsite),
+        pam::Position::Tail => (-cutsite - grna_len + pam_len, pam_len - cutsite),
+    };
+
+    let guide = guide.to_ascii_uppercase();
+    let sites = find_offtargets(index, scorer.profile(), kmer, min_score);
+    Ok(sites.into_iter().map(move |offtarget| {
+        let position = offtarget.position;
+        let refseq = position.refseq() as usize;
+        let pos = position.pos() as isize;
+        let strand = position.strand();
+
+        let (start, end, cutsite_bottom) = if strand == '+' {
+            (
+                pos + offset_start,
+                pos + offset_end,
+                pos + enzyme.overhang(),
+            )
+        } else {
+            (
+                pos - offset_end,
+                pos - offset_start,
+                pos - enzyme.overhang(),
+            )
+        };
+
+        let sequence = fetch_embedded(index, refseq, start, end, strand);
+        let mismatches = sequence
+            .as_ref()
+            .and_then(|sequence| spacer_mismatches(enzyme, &guide, sequence));
+
+        OffTargetSite {
+            name: &index.refseqs()[refseq],
+            start,
+            end,
+            cutsite: pos,
+            cutsite_bottom,
+            strand,
+            sequence,
+            spacer_mismatches: mismatches,
+            scores: scorer.offtarget_scores(kmer, &offtarget),
+            offtarget,
+        }
+    }))
+}
@@ -3,20 +3,53 @@ use std::io::Write;
 
 use clap::{App, Arg, ArgMatches, SubCommand};
 
+use crate::constants::{DEFAULT_MAX_MISMATCHES, MAX_KMER_LEN};
 use crate::enzyme::Enzyme;
 use crate::errors::*;
+use crate::filter::SequenceFilter;
+use crate::score::{Model, ScoringProfile};
 
 #[derive(Debug)]
 pub struct IndexArgs {
     pub fasta: String,
     pub output: Option<String>,
-    pub enzyme: Enzyme,
+    pub enzymes: Vec<Enzyme>,
     pub positions: bool,
+    pub exclude_masked: bool,
+    pub embed_sequences: bool,
+    pub append: bool,
+    pub max_memory: Option<usize>,
+    pub filter: SequenceFilter,
+    pub threads: usize,
+}
+
+#[derive(Debug)]
+pub struct MergeArgs {
+    pub output: String,
+    pub indexes: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct InspectArgs {
+    pub index: String,
+    pub enzyme: Option<Enzyme>,
+    pub lookup: Vec<String>,
+    pub top: usize,
+    pub output: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct UpgradeIndexArgs {
+    pub index: String,
+    pub output: Option<String>,
 }
 
 #[derive(Debug)]
 pub struct ScoreArgs {
     pub index: String,
+    pub enzyme: Option<Enzyme>,
+    pub models: Vec<Model>,
+    pub profile: ScoringProfile,
     pub table: String,
     pub output: Option<String>,
     pub threads: usize,
@@ -25,23 +58,39 @@ pub struct ScoreArgs {
 #[derive(Debug)]
 pub struct FindArgs {
     pub index: String,
+    pub enzyme: Option<Enzyme>,
+    pub models: Vec<Model>,
+    pub profile: ScoringProfile,
     pub targets: String,
     pub output: Option<String>,
     pub bedfile: Option<String>,
+    pub verify_fasta: bool,
+    pub verify: bool,
+    pub max_mismatches: usize,
     pub threads: usize,
 }
 
 #[derive(Debug)]
 pub struct OffTargetsArgs {
     pub index: String,
+    pub enzyme: Option<Enzyme>,
+    pub models: Vec<Model>,
+    pub profile: ScoringProfile,
     pub table: String,
     pub fasta: Option<String>,
+    pub verify_fasta: bool,
     pub output: Option<String>,
     pub min_score: u64,
+    pub verify: bool,
+    pub max_mismatches: usize,
 }
 
 pub enum Args {
+    Enzymes,
     Index(IndexArgs),
+    Merge(MergeArgs),
+    Inspect(InspectArgs),
+    UpgradeIndex(UpgradeIndexArgs),
     Score(ScoreArgs),
     Find(FindArgs),
     OffTargets(OffTargetsArgs),
@@ -55,6 +104,75 @@ fn args_index<'a, 'b>() -> Arg<'a, 'b> {
         .required(true)
 }
 
+/// Command-line option for selecting the enzyme section of an index
+fn args_enzyme<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("enzyme")
+        .long("enzyme")
+        .takes_value(true)
+        .number_of_values(1)
+        .help(
+            "Select the section of the index built for this enzyme (required for \
+             indexes built for multiple enzymes); either the name of a built-in \
+             enzyme or the path to an enzyme definition file.",
+        )
+}
+
+/// Command-line option for selecting scoring models
+fn args_models<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("model")
+        .long("model")
+        .takes_value(true)
+        .multiple(true)
+        .use_delimiter(true)
+        .number_of_values(1)
+        .possible_values(Model::names())
+        .default_value("crispy")
+        .help(
+            "Scoring model(s) used; either 'crispy' (CRISPy score), 'cfd' (CFD \
+             score; Doench et al. 2016), or 'mit' (MIT score; Hsu et al. 2013). May \
+             be specified multiple times, in which case a column is written for each \
+             model.",
+        )
+}
+
+/// Command-line options for specifying the scoring profile
+fn args_profile<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("profile")
+            .long("profile")
+            .takes_value(true)
+            .number_of_values(1)
+            .help(
+                "Read scoring profile (matrix, seed size, and mismatch limits) from \
+                 file; values may be overridden using the options below.",
+            ),
+        Arg::with_name("score_matrix")
+            .long("score-matrix")
+            .takes_value(true)
+            .number_of_values(1)
+            .help(
+                "Scores for off-targets, with rows (separated by ';') corresponding \
+                 to the number of seed mismatches and columns (separated by ',') \
+                 to the number of non-seed mismatches.",
+            ),
+        Arg::with_name("seed_size")
+            .long("seed-size")
+            .takes_value(true)
+            .number_of_values(1)
+            .help("Number of nucleotides next to the PAM considered part of the seed [5]."),
+        Arg::with_name("max_mutations")
+            .long("max-mutations")
+            .takes_value(true)
+            .number_of_values(1)
+            .help("Maximum number of mismatches in off-targets (at most 5) [3]."),
+        Arg::with_name("max_seed_mutations")
+            .long("max-seed-mutations")
+            .takes_value(true)
+            .number_of_values(1)
+            .help("Maximum number of mismatches in the seed of off-targets [2]."),
+    ]
+}
+
 /// Command-line option for specifying output files
 fn args_output<'a, 'b>() -> Arg<'a, 'b> {
     Arg::with_name("output")
@@ -65,6 +183,15 @@ fn args_output<'a, 'b>() -> Arg<'a, 'b> {
         .help("Write output to file instead of STDOUT.")
 }
 
+/// Command-line option for verifying the MD5 digests of FASTA sequences against the index
+fn args_verify_fasta<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("verify_fasta").long("verify-fasta").help(
+        "Verify that the MD5 digests of the FASTA sequences match those recorded in \
+         the index, rather than just their lengths. This requires reading every \
+         sequence in the FASTA file that is also found in the index.",
+    )
+}
+
 /// Command-line option for specifying the number of threads used
 fn args_threads<'a, 'b>() -> Arg<'a, 'b> {
     Arg::with_name("threads")
@@ -85,20 +212,204 @@ fn index_command<'a, 'b>() -> App<'a, 'b> {
             Arg::with_name("enzyme")
                 .long("enzyme")
                 .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .use_delimiter(true)
                 .default_value("Cas9")
-                .help("Endonuclease enzyme used; either Cas9 or Mad7."),
+                .help(
+                    "Endonuclease enzyme used; either the name of a built-in enzyme \
+                     (see 'crispyr enzymes') or the path to an enzyme definition file. \
+                     May be specified multiple times to index several enzymes in a \
+                     single pass, storing one section per enzyme in the index.",
+                ),
+        )
+        .arg(
+            Arg::with_name("kmer_len")
+                .long("kmer-len")
+                .takes_value(true)
+                .number_of_values(1)
+                .default_value("13")
+                .help(
+                    "Length of the PAM-proximal K-mers used to index target sites; \
+                     longer K-mers are more specific, but K-mers longer than 13 bp \
+                     are stored in a (slower) sparse table.",
+                ),
         )
         .arg(
             Arg::with_name("positions")
                 .long("positions")
                 .help("Save cut-site positions (greatly increases index size)"),
         )
+        .arg(
+            Arg::with_name("exclude_masked")
+                .long("exclude-masked")
+                .help(
+                    "Exclude target sites overlapping soft-masked (lower-case) sequence, \
+                     such as repeats masked by RepeatMasker.",
+                ),
+        )
+        .arg(
+            Arg::with_name("embed_sequences")
+                .long("embed-sequences")
+                .help(
+                    "Embed a 2-bit packed copy of the indexed sequences in the index \
+                     (requires --positions), allowing the 'offtargets' command to report \
+                     off-target sequences without the FASTA file. Adds about 1 byte per \
+                     4 bp to the size of the index.",
+                ),
+        )
+        .arg(Arg::with_name("append").long("append").help(
+            "Add the sequences in the FASTA file to an existing index, instead of \
+                 creating a new index. The K-mer length, whether or not positions \
+                 are saved, whether soft-masked sites are excluded, and whether \
+                 sequences are embedded is determined by the existing index. \
+                 Sequence names must not already be present in the index.",
+        ))
+        .arg(
+            Arg::with_name("include")
+                .long("include")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .use_delimiter(true)
+                .help(
+                    "Only index sequences with these names; may be specified multiple \
+                     times or as a comma-separated list.",
+                ),
+        )
+        .arg(
+            Arg::with_name("include_regex")
+                .long("include-regex")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help(
+                    "Only index sequences whose names match this regular expression; \
+                     may be specified multiple times.",
+                ),
+        )
+        .arg(
+            Arg::with_name("exclude")
+                .long("exclude")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .use_delimiter(true)
+                .help(
+                    "Do not index sequences with these names; may be specified multiple \
+                     times or as a comma-separated list.",
+                ),
+        )
+        .arg(
+            Arg::with_name("exclude_regex")
+                .long("exclude-regex")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help(
+                    "Do not index sequences whose names match this regular expression \
+                     (e.g. '_alt$'); may be specified multiple times.",
+                ),
+        )
+        .arg(
+            Arg::with_name("regions")
+                .long("regions")
+                .takes_value(true)
+                .number_of_values(1)
+                .help(
+                    "Only index target sites located entirely within the regions in \
+                     this BED file; sequences without regions are not indexed.",
+                ),
+        )
+        .arg(
+            Arg::with_name("max_memory")
+                .long("max-memory")
+                .takes_value(true)
+                .number_of_values(1)
+                .help(
+                    "Maximum amount of memory used to collect positions (requires \
+                     --positions), e.g. '4G' or '500M'. Positions exceeding this limit \
+                     are sorted and written to temporary files next to the index, which \
+                     are merged once all sequences have been processed. Sequences are \
+                     processed whole, and each must fit within the limit.",
+                ),
+        )
+        .arg(args_threads())
+}
+
+fn merge_command<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("merge")
+        .about("Merge indexes built using the same enzyme")
+        .arg(
+            Arg::with_name("output")
+                .help("Path to the merged CRISPyR index file.")
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("indexes")
+                .help(
+                    "Two or more CRISPyR index files. Sequence names must be unique \
+                     across indexes.",
+                )
+                .multiple(true)
+                .min_values(2)
+                .required(true),
+        )
+}
+
+fn inspect_command<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("inspect")
+        .about("Summarize the contents of a CRISPyR index")
+        .arg(args_index())
+        .arg(args_enzyme())
+        .arg(
+            Arg::with_name("lookup")
+                .long("lookup")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help(
+                    "Print the number of sites (and positions, if saved) for a K-mer, \
+                     a spacer, or a gRNA target sequence including the PAM, instead of \
+                     summarizing the index. May be specified multiple times.",
+                ),
+        )
+        .arg(
+            Arg::with_name("top")
+                .long("top")
+                .takes_value(true)
+                .number_of_values(1)
+                .default_value("10")
+                .help("Number of most repeated K-mers to report."),
+        )
+        .arg(args_output())
+}
+
+fn upgrade_index_command<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("upgrade-index")
+        .about("Convert an index built by an older version of CRISPyR to the current format")
+        .arg(args_index())
+        .arg(
+            Arg::with_name("output")
+                .long("output")
+                .short("o")
+                .takes_value(true)
+                .number_of_values(1)
+                .help("Write the upgraded index to this file instead of replacing the index."),
+        )
+}
+
+fn enzymes_command<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("enzymes").about("List built-in endonuclease enzymes")
 }
 
 fn score_command<'a, 'b>() -> App<'a, 'b> {
     SubCommand::with_name("score")
         .about("Score table of gRNA targets using indexed genome")
         .arg(args_index())
+        .arg(args_enzyme())
+        .arg(args_models())
+        .args(&args_profile())
         .arg(
             Arg::with_name("table")
                 .help("Table containing target sequences.")
@@ -112,6 +423,9 @@ fn find_command<'a, 'b>() -> App<'a, 'b> {
     SubCommand::with_name("find")
         .about("Find and score gRNA targets in FASTA sequence(s)")
         .arg(args_index())
+        .arg(args_enzyme())
+        .arg(args_models())
+        .args(&args_profile())
         .arg(
             Arg::with_name("targets")
                 .help("FASTA file containing one or more sequences.")
@@ -130,6 +444,25 @@ fn find_command<'a, 'b>() -> App<'a, 'b> {
                      overlapping regions are not merged.",
                 ),
         )
+        .arg(args_verify_fasta())
+        .arg(Arg::with_name("verify").long("verify").help(
+            "Compare the full spacer of each gRNA with the sequence at each \
+                     off-target found using the index, and report the 'VerifiedScore' \
+                     (the CRISPy score of off-targets with at most --max-mismatches \
+                     mismatches) and the number of off-targets with 0, 1, 2, etc. \
+                     mismatches. Requires an index with embedded sequences.",
+        ))
+        .arg(
+            Arg::with_name("max_mismatches")
+                .long("max-mismatches")
+                .takes_value(true)
+                .number_of_values(1)
+                .requires("verify")
+                .help(
+                    "Maximum number of mismatches across the full spacer of verified \
+                     off-targets [4].",
+                ),
+        )
         .arg(args_output())
         .arg(args_threads())
 }
@@ -138,6 +471,9 @@ fn off_targets_command<'a, 'b>() -> App<'a, 'b> {
     SubCommand::with_name("offtargets")
         .about("Print table of off targets for each target sequence")
         .arg(args_index())
+        .arg(args_enzyme())
+        .arg(args_models())
+        .args(&args_profile())
         .arg(
             Arg::with_name("table")
                 .help("Table containing target sequences.")
@@ -147,10 +483,12 @@ fn off_targets_command<'a, 'b>() -> App<'a, 'b> {
             Arg::with_name("fasta")
                 .help(
                     "FASTA file for index; if not specified, CRISPyR will \
-                     try using the index filename without the extension.",
+                     try using the FASTA file recorded in the index, or the \
+                     index filename without the extension.",
                 )
                 .required(false),
         )
+        .arg(args_verify_fasta())
         .arg(args_output())
         .arg(
             Arg::with_name("min_score")
@@ -160,6 +498,24 @@ fn off_targets_command<'a, 'b>() -> App<'a, 'b> {
                 .default_value("0")
                 .help("Minimum score of off-target (500 for exact kmer matches)"),
         )
+        .arg(Arg::with_name("verify").long("verify").help(
+            "Compare the full spacer of each gRNA with the sequence at each \
+                     off-target, and report the number ('Mismatches') and positions \
+                     ('MismatchPositions'; 1-based, 5' to 3' along the spacer) of \
+                     mismatches. Requires sequences, either embedded in the index or \
+                     from a FASTA file.",
+        ))
+        .arg(
+            Arg::with_name("max_mismatches")
+                .long("max-mismatches")
+                .takes_value(true)
+                .number_of_values(1)
+                .requires("verify")
+                .help(
+                    "Only report off-targets with at most this many mismatches across \
+                     the full spacer [4].",
+                ),
+        )
         .alias("off_targets")
 }
 
@@ -174,10 +530,160 @@ fn get_string(matches: &ArgMatches, key: &str) -> Result<String> {
     get_str(matches, key).map(|v| v.into())
 }
 
+fn parse_enzyme(matches: &ArgMatches) -> Result<Option<Enzyme>> {
+    match matches.value_of("enzyme") {
+        Some(value) => Enzyme::load(value).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Parses the enzymes indexed, each using the K-mer length specified
+fn parse_enzymes(matches: &ArgMatches) -> Result<Vec<Enzyme>> {
+    let kmer_len = parse_kmer_len(matches)?;
+
+    let mut enzymes: Vec<Enzyme> = Vec::new();
+    for value in matches.values_of("enzyme").into_iter().flatten() {
+        let enzyme = Enzyme::load(value)?.with_kmer_len(kmer_len);
+        enzyme.validate()?;
+
+        if enzymes.iter().any(|v| v.name == enzyme.name) {
+            return Err(format!("enzyme {:?} specified multiple times", enzyme.name).into());
+        }
+
+        enzymes.push(enzyme);
+    }
+
+    Ok(enzymes)
+}
+
+fn parse_kmer_len(matches: &ArgMatches) -> Result<usize> {
+    let s = get_str(matches, "kmer_len")?;
+
+    match s.parse::<usize>() {
+        Ok(v) if v > 0 && v <= MAX_KMER_LEN => Ok(v),
+        Ok(_) => Err(format!(
+            "Invalid --kmer-len ({:?}); must be 1 to {}",
+            s, MAX_KMER_LEN
+        )
+        .into()),
+        Err(err) => Err(format!("Invalid --kmer-len ({:?}) value: {}", s, err).into()),
+    }
+}
+
+/// Parses a size in bytes, optionally using the suffix K, M, or G (powers of 1024)
+fn parse_optional_size(matches: &ArgMatches, key: &str, option: &str) -> Result<Option<usize>> {
+    let s = match matches.value_of(key) {
+        Some(s) => s,
+        None => return Ok(None),
+    };
+
+    let (value, multiplier) = match s.to_ascii_uppercase().chars().last() {
+        Some('K') => (&s[..s.len() - 1], 1 << 10),
+        Some('M') => (&s[..s.len() - 1], 1 << 20),
+        Some('G') => (&s[..s.len() - 1], 1 << 30),
+        _ => (s, 1),
+    };
+
+    match value.parse::<usize>() {
+        Ok(v) if v > 0 => match v.checked_mul(multiplier) {
+            Some(v) => Ok(Some(v)),
+            None => Err(format!("Invalid {} ({:?}) value: too large", option, s).into()),
+        },
+        Ok(_) => Err(format!("Invalid {} ({:?}); must be greater than 0", option, s).into()),
+        Err(err) => Err(format!("Invalid {} ({:?}) value: {}", option, s, err).into()),
+    }
+}
+
+fn parse_filter(matches: &ArgMatches) -> Result<SequenceFilter> {
+    let values = |key| -> Vec<String> {
+        matches
+            .values_of(key)
+            .map(|values| values.map(|s| s.to_string()).collect())
+            .unwrap_or_default()
+    };
+    let patterns = |key| -> Result<Vec<_>> {
+        values(key)
+            .iter()
+            .map(|v| SequenceFilter::regex(v))
+            .collect()
+    };
+
+    let filter = SequenceFilter {
+        include: values("include"),
+        exclude: values("exclude"),
+        include_regex: patterns("include_regex")?,
+        exclude_regex: patterns("exclude_regex")?,
+        ..Default::default()
+    };
+
+    match matches.value_of("regions") {
+        Some(filename) => filter.with_bed(filename),
+        None => Ok(filter),
+    }
+}
+
+fn parse_models(matches: &ArgMatches) -> Result<Vec<Model>> {
+    let mut models = Vec::new();
+    if let Some(values) = matches.values_of("model") {
+        for value in values {
+            let model = Model::parse(value)?;
+            if !models.contains(&model) {
+                models.push(model);
+            }
+        }
+    }
+
+    Ok(models)
+}
+
+fn parse_optional_usize(matches: &ArgMatches, key: &str, option: &str) -> Result<Option<usize>> {
+    match matches.value_of(key) {
+        Some(s) => match s.parse::<usize>() {
+            Ok(v) => Ok(Some(v)),
+            Err(err) => Err(format!("Invalid {} ({:?}) value: {}", option, s, err).into()),
+        },
+        None => Ok(None),
+    }
+}
+
+fn parse_max_mismatches(matches: &ArgMatches) -> Result<usize> {
+    let max_mismatches = parse_optional_usize(matches, "max_mismatches", "--max-mismatches")?;
+
+    Ok(max_mismatches.unwrap_or(DEFAULT_MAX_MISMATCHES))
+}
+
+fn parse_profile(matches: &ArgMatches) -> Result<ScoringProfile> {
+    let mut profile = match matches.value_of("profile") {
+        Some(filename) => ScoringProfile::from_file(&filename)?,
+        None => ScoringProfile::default(),
+    };
+
+    if let Some(value) = matches.value_of("score_matrix") {
+        profile.matrix = ScoringProfile::parse_matrix(value)
+            .chain_err(|| format!("Invalid --score-matrix ({:?}) value", value))?;
+    }
+
+    if let Some(value) = parse_optional_usize(matches, "seed_size", "--seed-size")? {
+        profile.seed_size = value;
+    }
+    if let Some(value) = parse_optional_usize(matches, "max_mutations", "--max-mutations")? {
+        profile.max_mutations = value;
+    }
+    if let Some(value) =
+        parse_optional_usize(matches, "max_seed_mutations", "--max-seed-mutations")?
+    {
+        profile.max_seed_mutations = value;
+    }
+
+    profile.validate()?;
+
+    Ok(profile)
+}
+
 fn parse_threads(matches: &ArgMatches) -> Result<usize> {
     let s = get_str(matches, "threads")?;
 
-    match usize::from_str_radix(s, 10) {
+    match s.parse::<usize>() {
         Ok(v) => Ok(v),
         Err(err) => Err(format!("Invalid --threads ({:?}) value: {}", s, err).into()),
     }
@@ -186,17 +692,30 @@ fn parse_threads(matches: &ArgMatches) -> Result<usize> {
 fn parse_min_score(matches: &ArgMatches) -> Result<u64> {
     let s = get_str(matches, "min_score")?;
 
-    match u64::from_str_radix(s, 10) {
+    match s.parse::<u64>() {
         Ok(v) => Ok(v),
         Err(err) => Err(format!("Invalid --min-score ({:?}) value: {}", s, err).into()),
     }
 }
 
+fn parse_top(matches: &ArgMatches) -> Result<usize> {
+    let s = get_str(matches, "top")?;
+
+    match s.parse::<usize>() {
+        Ok(v) => Ok(v),
+        Err(err) => Err(format!("Invalid --top ({:?}) value: {}", s, err).into()),
+    }
+}
+
 fn new_parser<'a, 'b>() -> App<'a, 'b> {
     App::new("CRISPyR")
         .version("0.2.0")
         .author("Mikkel Schubert")
+        .subcommand(enzymes_command())
         .subcommand(index_command())
+        .subcommand(merge_command())
+        .subcommand(inspect_command())
+        .subcommand(upgrade_index_command())
         .subcommand(score_command())
         .subcommand(find_command())
         .subcommand(off_targets_command())
@@ -205,22 +724,51 @@ fn new_parser<'a, 'b>() -> App<'a, 'b> {
 pub fn parse_args() -> Result<Args> {
     let matches = new_parser().get_matches();
 
-    if let Some(matches) = matches.subcommand_matches("index") {
-        let enzyme_str = get_str(matches, "enzyme")?;
-        let enzyme = match Enzyme::get(enzyme_str) {
-            Some(enzyme) => enzyme,
-            None => return Err(format!("Unknown enzyme {:?}", enzyme_str).into()),
-        };
-
+    if matches.subcommand_matches("enzymes").is_some() {
+        Ok(Args::Enzymes)
+    } else if let Some(matches) = matches.subcommand_matches("index") {
         Ok(Args::Index(IndexArgs {
             fasta: get_string(matches, "fasta")?,
             output: matches.value_of("output").map(|s| s.to_string()),
-            enzyme,
+            enzymes: parse_enzymes(matches)?,
             positions: matches.is_present("positions"),
+            exclude_masked: matches.is_present("exclude_masked"),
+            embed_sequences: matches.is_present("embed_sequences"),
+            append: matches.is_present("append"),
+            max_memory: parse_optional_size(matches, "max_memory", "--max-memory")?,
+            filter: parse_filter(matches)?,
+            threads: parse_threads(matches)?,
+        }))
+    } else if let Some(matches) = matches.subcommand_matches("merge") {
+        Ok(Args::Merge(MergeArgs {
+            output: get_string(matches, "output")?,
+            indexes: matches
+                .values_of("indexes")
+                .map(|values| values.map(|s| s.to_string()).collect())
+                .unwrap_or_default(),
+        }))
+    } else if let Some(matches) = matches.subcommand_matches("inspect") {
+        Ok(Args::Inspect(InspectArgs {
+            index: get_string(matches, "index")?,
+            enzyme: parse_enzyme(matches)?,
+            lookup: matches
+                .values_of("lookup")
+                .map(|values| values.map(|s| s.to_string()).collect())
+                .unwrap_or_default(),
+            top: parse_top(matches)?,
+            output: matches.value_of("output").map(|s| s.to_string()),
+        }))
+    } else if let Some(matches) = matches.subcommand_matches("upgrade-index") {
+        Ok(Args::UpgradeIndex(UpgradeIndexArgs {
+            index: get_string(matches, "index")?,
+            output: matches.value_of("output").map(|s| s.to_string()),
         }))
     } else if let Some(matches) = matches.subcommand_matches("score") {
         Ok(Args::Score(ScoreArgs {
             index: get_string(matches, "index")?,
+            enzyme: parse_enzyme(matches)?,
+            models: parse_models(matches)?,
+            profile: parse_profile(matches)?,
             table: get_string(matches, "table")?,
             output: matches.value_of("output").map(|s| s.to_string()),
             threads: parse_threads(matches)?,
@@ -228,16 +776,28 @@ pub fn parse_args() -> Result<Args> {
     } else if let Some(matches) = matches.subcommand_matches("offtargets") {
         Ok(Args::OffTargets(OffTargetsArgs {
             index: get_string(matches, "index")?,
+            enzyme: parse_enzyme(matches)?,
+            models: parse_models(matches)?,
+            profile: parse_profile(matches)?,
             table: get_string(matches, "table")?,
             fasta: matches.value_of("fasta").map(|s| s.to_string()),
+            verify_fasta: matches.is_present("verify_fasta"),
             output: matches.value_of("output").map(|s| s.to_string()),
             min_score: parse_min_score(matches)?,
+            verify: matches.is_present("verify"),
+            max_mismatches: parse_max_mismatches(matches)?,
         }))
     } else if let Some(matches) = matches.subcommand_matches("find") {
         Ok(Args::Find(FindArgs {
             index: get_string(matches, "index")?,
+            enzyme: parse_enzyme(matches)?,
+            models: parse_models(matches)?,
+            profile: parse_profile(matches)?,
             targets: get_string(matches, "targets")?,
             bedfile: matches.value_of("bedfile").map(|s| s.to_string()),
+            verify_fasta: matches.is_present("verify_fasta"),
+            verify: matches.is_present("verify"),
+            max_mismatches: parse_max_mismatches(matches)?,
             output: matches.value_of("output").map(|s| s.to_string()),
             threads: parse_threads(matches)?,
         }))
@@ -0,0 +1,158 @@
+use crate::common::KMer;
+use crate::enzyme::Enzyme;
+use crate::errors::*;
+use crate::iupac;
+use crate::pam::Position;
+
+// Cutting Frequency Determination (CFD) scores from Doench et al. 2016 (doi:10.1038/nbt.3437).
+//
+// Mismatch scores are indexed by the nucleotide of the gRNA (rA, rC, rG, rU), the nucleotide of
+// the target DNA strand (dA, dC, dG, dT), and the position in the 20 bp spacer (1 being the
+// PAM-distal nucleotide). Pairs that are complementary (e.g. rA:dT) are matches and score 1.
+const CFD_SPACER_LEN: usize = 20;
+
+#[rustfmt::skip]
+static CFD_MISMATCH_SCORES: [[[f64; CFD_SPACER_LEN]; 4]; 4] = [
+    [
+        // rA:dA
+        [1.0, 0.727272727, 0.705882353, 0.636363636, 0.363636364, 0.714285714, 0.4375, 0.428571429, 0.6, 0.882352941,
+         0.307692308, 0.333333333, 0.3, 0.533333333, 0.2, 0.0, 0.133333333, 0.5, 0.538461538, 0.6],
+        // rA:dC
+        [1.0, 0.8, 0.611111111, 0.625, 0.72, 0.705882353, 0.7, 0.733333333, 0.666666667, 0.555555556,
+         0.65, 0.722222222, 0.652173913, 0.46875, 0.466666667, 0.3, 0.65, 0.631578947, 0.5, 0.764705882],
+        // rA:dG
+        [0.857142857, 0.785714286, 0.428571429, 0.352941176, 0.5, 0.454545455, 0.4375, 0.428571429, 0.6, 0.333333333,
+         0.4, 0.263157895, 0.210526316, 0.214285714, 0.272727273, 0.0, 0.176470588, 0.19047619, 0.206896552, 0.227272727],
+        // rA:dT (match)
+        [1.0; CFD_SPACER_LEN],
+    ],
+    [
+        // rC:dA
+        [1.0, 0.909090909, 0.6875, 0.8, 0.636363636, 0.928571429, 0.8125, 0.875, 0.875, 0.941176471,
+         0.307692308, 0.4, 0.260869565, 0.277777778, 0.214285714, 0.0, 0.2, 0.3, 0.307692308, 0.3],
+        // rC:dC
+        [0.913043478, 0.695652174, 0.5, 0.5, 0.6, 0.5, 0.470588235, 0.642857143, 0.619047619, 0.390243902,
+         0.32, 0.277777778, 0.0, 0.0, 0.05, 0.0, 0.026315789, 0.0, 0.0, 0.0],
+        // rC:dG (match)
+        [1.0; CFD_SPACER_LEN],
+        // rC:dT
+        [1.0, 0.727272727, 0.866666667, 0.842105263, 0.571428571, 0.941176471, 0.5, 0.533333333, 0.6875, 0.388888889,
+         0.25, 0.65, 0.384615385, 0.47826087, 0.2, 0.0, 0.142857143, 0.3, 0.666666667, 0.5],
+    ],
+    [
+        // rG:dA
+        [0.857142857, 0.692307692, 0.3125, 0.5, 0.3, 0.214285714, 0.375, 0.666666667, 0.384615385, 0.25,
+         0.1, 0.263157895, 0.3, 0.071428571, 0.2, 0.0, 0.133333333, 0.384615385, 0.230769231, 0.176470588],
+        // rG:dC (match)
+        [1.0; CFD_SPACER_LEN],
+        // rG:dG
+        [0.714285714, 0.692307692, 0.384615385, 0.529411765, 0.4375, 0.428571429, 0.333333333, 0.6, 0.529411765, 0.3125,
+         0.4, 0.176470588, 0.235294118, 0.1875, 0.0, 0.0, 0.0, 0.1, 0.117647059, 0.166666667],
+        // rG:dT
+        [0.9, 0.857142857, 0.6875, 0.785714286, 0.75, 0.714285714, 0.866666667, 0.928571429, 0.8, 0.9375,
+         0.733333333, 0.8, 0.642857143, 0.75, 0.416666667, 0.75, 0.652173913, 0.714285714, 0.875, 0.5],
+    ],
+    [
+        // rU:dA (match)
+        [1.0; CFD_SPACER_LEN],
+        // rU:dC
+        [0.956521739, 0.84, 0.5, 0.619047619, 0.5625, 0.555555556, 0.6, 0.5, 0.538461538, 0.411764706,
+         0.2, 0.333333333, 0.230769231, 0.238095238, 0.0, 0.0, 0.0, 0.130434783, 0.178571429, 0.041666667],
+        // rU:dG
+        [0.9, 0.846153846, 0.75, 0.9, 0.75, 0.88, 0.6, 1.0, 0.857142857, 0.857142857,
+         0.75, 0.714285714, 0.384615385, 0.35, 0.222222222, 1.0, 0.476190476, 0.538461538, 0.428571429, 0.5],
+        // rU:dT
+        [1.0, 0.846153846, 0.714285714, 0.476190476, 0.866666667, 0.8125, 0.875, 0.8, 0.666666667, 0.6875,
+         0.6, 0.571428571, 0.4, 0.210526316, 0.0, 0.2, 0.052631579, 0.052631579, 0.3, 0.3],
+    ],
+];
+
+// PAM scores indexed by the 2nd and 3rd nucleotide of the (3 bp) PAM
+#[rustfmt::skip]
+static CFD_PAM_SCORES: [[f64; 4]; 4] = [
+    // AA   AC   AG           AT
+    [0.0, 0.0, 0.259259259, 0.0],
+    // CA   CC   CG           CT
+    [0.0, 0.0, 0.107142857, 0.0],
+    // GA           GC           GG   GT
+    [0.069444444, 0.022222222, 1.0, 0.016129032],
+    // TA   TC   TG           TT
+    [0.0, 0.0, 0.038961039, 0.0],
+];
+
+/// CFD scoring of off-targets for SpCas9-like enzymes (3 bp 3' PAMs). Since only the K-mer
+/// adjacent to the PAM is indexed, mismatches outside the K-mer are not taken into account.
+#[derive(Clone, Debug)]
+pub struct CFD {
+    kmer_len: usize,
+    pam_scores: Vec<f64>,
+}
+
+impl CFD {
+    /// Returns CFD scoring for an enzyme, or an error if CFD scores are not defined for it.
+    pub fn new(enzyme: &Enzyme) -> Result<CFD> {
+        let pams = &enzyme.pams;
+        if pams.position() != Position::Tail || pams.len() != 3 {
+            return Err(format!(
+                "CFD scores are only defined for enzymes with 3 bp 3' PAMs, not {}",
+                enzyme.name
+            )
+            .into());
+        } else if pams.kmer_len() > CFD_SPACER_LEN {
+            return Err(format!(
+                "CFD scores are only defined for K-mers up to {} bp",
+                CFD_SPACER_LEN
+            )
+            .into());
+        }
+
+        let pam_scores = pams
+            .pams()
+            .iter()
+            .map(|pam| Self::max_pam_score(&pam.to_string().as_bytes()[1..]))
+            .collect();
+
+        Ok(CFD {
+            kmer_len: pams.kmer_len(),
+            pam_scores,
+        })
+    }
+
+    /// Returns the highest PAM score for any sequence matching a degenerate PAM
+    fn max_pam_score(pam: &[u8]) -> f64 {
+        let mut score: f64 = 0.0;
+        for (first, scores) in b"ACGT".iter().zip(CFD_PAM_SCORES.iter()) {
+            for (second, value) in b"ACGT".iter().zip(scores.iter()) {
+                if iupac::matches(pam[0], *first) && iupac::matches(pam[1], *second) {
+                    score = score.max(*value);
+                }
+            }
+        }
+
+        score
+    }
+
+    /// Returns the PAM score for the given PAM (index into the enzyme's list of PAMs).
+    pub fn pam_score(&self, pam: usize) -> f64 {
+        self.pam_scores[pam]
+    }
+
+    /// Calculates the CFD score for an off-target K-mer with the given PAM.
+    pub fn score(&self, query: KMer, offtarget: KMer, pam: usize) -> f64 {
+        let mut score = self.pam_score(pam);
+
+        // K-mers are encoded with the nucleotide closest to the PAM in the lowest bits
+        for idx in 0..self.kmer_len {
+            let rna = (query.0 >> (2 * idx)) & 3;
+            let dna = (offtarget.0 >> (2 * idx)) & 3;
+
+            if rna != dna {
+                // The target DNA strand is complementary to the off-target sequence
+                score *=
+                    CFD_MISMATCH_SCORES[rna as usize][3 - dna as usize][CFD_SPACER_LEN - 1 - idx];
+            }
+        }
+
+        score
+    }
+}
@@ -0,0 +1,44 @@
+use std::io::Write;
+
+use crate::enzyme::Enzyme;
+use crate::errors::*;
+use crate::pam::Position;
+
+pub fn main() -> Result<()> {
+    eprintln!("Built-in enzymes; use the name with the --enzyme option of the 'index' command.");
+    eprintln!("  PAM: PAM sequence using IUPAC nucleotide codes");
+    eprintln!("  Position: 5' for PAMs upstream, 3' for PAMs downstream of the protospacer");
+    eprintln!("  gRNALen: Length of target sequences, including the PAM");
+    eprintln!("  SpacerLen: Length of target sequences, excluding the PAM");
+    eprintln!("  Cutsite: Position of the cut relative to the first nucleotide of the PAM");
+    eprintln!("  BottomCutsite: Position of the cut on the strand opposite the PAM");
+    eprintln!("  Overhang: Length of 5' (positive) or 3' (negative) overhangs");
+
+    let mut out = std::io::stdout();
+    writeln!(
+        out,
+        "Name\tPAM\tPosition\tgRNALen\tSpacerLen\tCutsite\tBottomCutsite\tOverhang"
+    )
+    .chain_err(|| "failed to write output header")?;
+
+    for enzyme in Enzyme::catalogue() {
+        writeln!(
+            out,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            enzyme.name,
+            enzyme.pams,
+            match enzyme.pams.position() {
+                Position::Head => "5'",
+                Position::Tail => "3'",
+            },
+            enzyme.grna_len,
+            enzyme.grna_len - enzyme.pams.len(),
+            enzyme.cutsite,
+            enzyme.cutsite_bottom,
+            enzyme.overhang(),
+        )
+        .chain_err(|| "failed to write output row")?;
+    }
+
+    Ok(())
+}
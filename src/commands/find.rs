@@ -1,91 +1,52 @@
-use bio::alphabets::dna;
 use bio::io::bed;
 use bio::io::fasta;
-use bio_types::strand::Strand;
 use rayon::prelude::*;
 use std::collections::HashMap;
 
+use crate::api::{find_targets, offtargets, TargetSite};
 use crate::args::FindArgs;
 use crate::common::open_file_or_stdout;
 use crate::errors::*;
+use crate::fasta::{open, open_indexed};
 use crate::index::KMerIndex;
 use crate::pam::Position;
 use crate::progress;
-use crate::progress::ProgressBar;
-use crate::score::calculate_score;
-
-#[derive(Debug)]
-struct TargetSite {
-    start: isize,
-    end: isize,
-    cutsite: isize,
-    strand: Strand,
-    sequence: Vec<u8>,
-    score: u64,
-    depth: usize,
-}
-
-type TargetSites = Vec<TargetSite>;
-
-fn collect_forward_targets(sequence: &[u8], index: &KMerIndex, pg: &ProgressBar) -> TargetSites {
-    let enzyme = index.enzyme();
-    let pam = &enzyme.pam;
-    let cutsite = enzyme.cutsite;
-
-    sequence
-        .par_windows(enzyme.grna_len)
-        .enumerate()
-        .filter_map(|(idx, window)| {
-            let result = if let Some((pam_pos, kmer)) = pam.kmer(window) {
-                Some(TargetSite {
-                    start: idx as isize,
-                    end: idx as isize + window.len() as isize,
-                    cutsite: (idx + pam_pos) as isize + cutsite,
-                    strand: Strand::Forward,
-                    sequence: window.to_owned(),
-                    score: calculate_score(index, kmer),
-                    depth: 0,
-                })
-            } else {
-                None
-            };
-
-            pg.inc(1);
-            result
-        })
-        .collect()
-}
-
-fn collect_reverse_targets(sequence: &[u8], index: &KMerIndex, pg: &ProgressBar) -> TargetSites {
-    let sequence = dna::revcomp(sequence);
-    let mut sites = collect_forward_targets(&sequence, index, pg);
-
-    for site in &mut sites {
-        let start = sequence.len() as isize - site.end;
-        let end = sequence.len() as isize - site.start;
-
-        site.start = start;
-        site.end = end;
-        site.cutsite = sequence.len() as isize - site.cutsite;
-        site.strand = Strand::Reverse;
+use crate::score::Scorer;
+
+/// Verifies the off-targets of a target site by comparing the full spacer with the sequences
+/// embedded in the index. Returns the CRISPy score of off-targets with at most `max_mismatches`
+/// mismatches, and the number of off-targets with 0, 1, 2, etc. mismatches; as for the CRISPy
+/// score, the target site itself is included. Each value is preceded by a tab.
+pub fn verify_offtargets(
+    scorer: &Scorer,
+    site: &TargetSite,
+    max_mismatches: usize,
+) -> Result<String> {
+    let mut score = 0;
+    let mut counts = vec![0; max_mismatches + 1];
+    for offtarget in offtargets(scorer, &site.sequence, 0)? {
+        let mismatches = offtarget
+            .spacer_mismatches
+            .expect("embedded sequences")
+            .len();
+
+        if mismatches <= max_mismatches {
+            score += offtarget.offtarget.score;
+            counts[mismatches] += 1;
+        }
     }
 
-    sites
-}
-
-fn collect_targets(index: &KMerIndex, sequence: &[u8], pg: &ProgressBar) -> TargetSites {
-    let mut targets = Vec::new();
-    targets.append(&mut collect_forward_targets(&sequence, index, pg));
-    targets.append(&mut collect_reverse_targets(&sequence, index, pg));
-    targets.sort_unstable_by_key(|v| v.cutsite);
+    let counts: Vec<String> = counts.iter().map(|v| v.to_string()).collect();
 
-    targets
+    Ok(format!("\t{}\t{}", score, counts.join(",")))
 }
 
 fn print_target(
+    index: &KMerIndex,
     record: &bed::Record,
     site: &TargetSite,
     sequence: &[u8],
+    (scores, verified): &(String, String),
     offset: isize,
     out: &mut Box<dyn std::io::Write>,
 ) -> Result<()> {
@@ -95,64 +56,108 @@ fn print_target(
 
     writeln!(
         out,
-        "{}\t{}\t{}\t{}\t{}\t{}\t{}",
-        String::from_utf8_lossy(&sequence),
+        "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}{}\t{}",
+        String::from_utf8_lossy(sequence),
         record.chrom(),
         record.start() as isize + site.start - offset + 1,
         record.start() as isize + site.end - offset,
         record.start() as isize + site.cutsite - offset + 1,
+        record.start() as isize + site.cutsite_bottom - offset + 1,
+        index.enzyme().overhang(),
         site.strand.strand_symbol(),
-        site.score,
+        scores,
+        verified,
+        if site.masked { "yes" } else { "no" },
     )
     .chain_err(|| "failed to write output row")
 }
 
 fn print_targets(
-    index: &KMerIndex,
+    scorer: &Scorer,
+    verify: Option<usize>,
     idx: usize,
     record: &bed::Record,
     sequence: &[u8],
     out: &mut Box<dyn std::io::Write>,
 ) -> Result<()> {
+    let index = scorer.index();
     let enzyme = index.enzyme();
-    let grna_len = enzyme.grna_len;
-    let pam_len = enzyme.pam.len();
-    let pam_offset = match enzyme.pam.position() {
+    let pam_len = enzyme.pams.len();
+    let pam_offset = match enzyme.pams.position() {
         Position::Head => 0,
         Position::Tail => enzyme.grna_len - pam_len,
     };
 
-    let num_sites = usize::max(sequence.len(), grna_len - 1) - grna_len - 1;
+    let padding = enzyme.grna_len as u64;
+    let min_cutsite = u64::min(padding, record.start()) as isize;
+    let max_cutsite = min_cutsite + (record.end() - record.start()) as isize;
+
+    let sites: Vec<TargetSite> = find_targets(enzyme, sequence)
+        .into_iter()
+        .filter(|site| site.cutsite >= min_cutsite && site.cutsite < max_cutsite)
+        .collect();
+
     let prefix = format!("{}. {}: ", idx + 1, record.name().unwrap_or(record.chrom()));
-    let progress = progress::with_prefix(num_sites * 2, &prefix);
+    let progress = progress::with_prefix(sites.len(), &prefix);
+    let scores: Vec<(String, String)> = sites
+        .par_iter()
+        .map(|site| {
+            let scores = scorer.score(site.kmer);
+            let verified = match verify {
+                Some(max_mismatches) => verify_offtargets(scorer, site, max_mismatches)?,
+                None => String::new(),
+            };
+            progress.inc(1);
 
-    let min_cutsite = u64::min(index.enzyme().grna_len as u64, record.start()) as isize;
-    let max_cutsite = min_cutsite + (record.end() - record.start()) as isize;
+            Ok((scores, verified))
+        })
+        .collect::<Result<_>>()?;
 
-    for site in collect_targets(index, sequence, &progress) {
-        if site.cutsite >= min_cutsite && site.cutsite < max_cutsite {
-            let mut target_seq = site.sequence.clone();
-            for nuc in &mut target_seq[pam_offset..pam_offset + pam_len] {
-                *nuc = nuc.to_ascii_lowercase();
-            }
+    progress.finish();
 
-            print_target(record, &site, &target_seq, min_cutsite, out)?;
+    for (site, scores) in sites.iter().zip(&scores) {
+        let mut target_seq = site.sequence.clone();
+        for nuc in &mut target_seq[pam_offset..pam_offset + pam_len] {
+            *nuc = nuc.to_ascii_lowercase();
         }
-    }
 
-    progress.finish();
+        print_target(index, record, site, &target_seq, scores, min_cutsite, out)?;
+    }
 
     Ok(())
 }
 
-fn collect_bed_targets(args: &FindArgs, index: &KMerIndex, bedfile: &str) -> Result<()> {
+/// Returns the header columns written by `verify_offtargets`, including the leading tab
+fn verify_header(verify: Option<usize>) -> &'static str {
+    match verify {
+        Some(_) => "\tVerifiedScore\tOffTargetMismatches",
+        None => "",
+    }
+}
+
+/// Returns the maximum number of mismatches for verified off-targets, or None if off-targets
+/// are not verified
+fn verify_mismatches(args: &FindArgs) -> Option<usize> {
+    if args.verify {
+        Some(args.max_mismatches)
+    } else {
+        None
+    }
+}
+
+fn collect_bed_targets(args: &FindArgs, scorer: &Scorer, bedfile: &str) -> Result<()> {
+    let index = scorer.index();
     // File handles are opened individually for better error reporting
     eprintln!("Finding target sites in {:?}", &args.targets);
-    let fai = fasta::Index::with_fasta_file(&args.targets)
-        .chain_err(|| format!("failed to open FASTA index file for {:?}", args.targets))?;
-    let fasta = std::fs::File::open(&args.targets)
-        .chain_err(|| format!("failed to open FASTA file {:?}", args.targets))?;
-    let mut reader = fasta::IndexedReader::with_index(fasta, fai);
+    let mut reader = open_indexed(&args.targets)?;
+
+    let verified = index.provenance().verify(
+        index.refseqs(),
+        &mut reader,
+        &args.targets,
+        args.verify_fasta,
+    )?;
+    eprintln!("  verified {} sequences against index", verified);
 
     let refseqs: HashMap<String, u64> = reader
         .index
@@ -166,9 +171,13 @@ fn collect_bed_targets(args: &FindArgs, index: &KMerIndex, bedfile: &str) -> Res
         .chain_err(|| format!("failed to open BED file {:?}", bedfile))?;
 
     let mut out = open_file_or_stdout(&args.output)?;
+    write!(out, "{}", scorer.profile().to_header())
+        .chain_err(|| "failed to write output header")?;
     writeln!(
         out,
-        "Region\tSequence\tContig\tStart\tEnd\tCutsite\tStrand\tScore"
+        "Region\tSequence\tContig\tStart\tEnd\tCutsite\tBottomCutsite\tOverhang\tStrand\t{}{}\tMasked",
+        scorer.header(),
+        verify_header(verify_mismatches(args)),
     )
     .chain_err(|| "failed to write output header")?;
 
@@ -179,7 +188,6 @@ fn collect_bed_targets(args: &FindArgs, index: &KMerIndex, bedfile: &str) -> Res
             None => return Err("foo".into()),
         };
 
-        // Padding needed to find all cut-sites overlapping the target region
         let padding = index.enzyme().grna_len as isize;
         let start = isize::max(0, record.start() as isize - padding) as u64;
         let end = u64::min(refseq_len, record.end() + padding as u64);
@@ -193,32 +201,51 @@ fn collect_bed_targets(args: &FindArgs, index: &KMerIndex, bedfile: &str) -> Res
             .read(&mut sequence)
             .chain_err(|| format!("failed to read {:?}", record))?;
 
-        sequence.make_ascii_uppercase();
-        print_targets(index, idx, &record, &sequence, &mut out)?;
+        print_targets(
+            scorer,
+            verify_mismatches(args),
+            idx,
+            &record,
+            &sequence,
+            &mut out,
+        )?;
     }
 
     Ok(())
 }
 
-fn collect_all_targets(args: &FindArgs, index: &KMerIndex) -> Result<()> {
+fn collect_all_targets(args: &FindArgs, scorer: &Scorer) -> Result<()> {
     eprintln!("Finding target sites in {:?}", &args.targets);
-    let reader = fasta::Reader::from_file(&args.targets)
-        .chain_err(|| format!("failed to open FASTA file {:?}", args.targets))?;
+    let reader = fasta::Reader::new(open(&args.targets)?);
 
     let mut out = open_file_or_stdout(&args.output)?;
-    writeln!(out, "Sequence\tContig\tStart\tEnd\tCutsite\tStrand\tScore")
+    write!(out, "{}", scorer.profile().to_header())
         .chain_err(|| "failed to write output header")?;
+    writeln!(
+        out,
+        "Sequence\tContig\tStart\tEnd\tCutsite\tBottomCutsite\tOverhang\tStrand\t{}{}\tMasked",
+        scorer.header(),
+        verify_header(verify_mismatches(args)),
+    )
+    .chain_err(|| "failed to write output header")?;
 
     for (idx, target) in reader.records().enumerate() {
         let target = target.chain_err(|| "failed to read sequence")?;
         target.check().map_err(|v| ErrorKind::Msg(v.into()))?;
 
-        let sequence = target.seq().to_ascii_uppercase();
+        let sequence = target.seq();
         let mut record = bed::Record::new();
         record.set_chrom(target.id());
         record.set_end(sequence.len() as u64);
 
-        print_targets(index, idx, &record, &sequence, &mut out)?;
+        print_targets(
+            scorer,
+            verify_mismatches(args),
+            idx,
+            &record,
+            sequence,
+            &mut out,
+        )?;
     }
 
     Ok(())
@@ -231,13 +258,28 @@ pub fn main(args: &FindArgs) -> Result<()> {
         .chain_err(|| "failed to build thread pool")?;
 
     eprintln!("\nReading K-mers from {:?}", args.index);
-    let index = KMerIndex::read(&args.index)
+    let index = KMerIndex::read_section(&args.index, args.enzyme.as_ref())
         .chain_err(|| format!("failed to read K-mer index {:?}", &args.index))?;
     eprintln!("  {}", index.summarize());
+    for line in index.summarize_filters() {
+        eprintln!("  {}", line);
+    }
+
+    let scorer = Scorer::new(&index, &args.profile, &args.models)?;
+
+    if args.verify && !(index.has_positions() && index.has_sequences()) {
+        return Err("--verify requires an index with positions and embedded sequences".into());
+    } else if args.verify && args.max_mismatches > index.enzyme().spacer_len() {
+        return Err(format!(
+            "--max-mismatches must be at most {} (the length of the spacer)",
+            index.enzyme().spacer_len()
+        )
+        .into());
+    }
 
     if let Some(bedfile) = &args.bedfile {
-        collect_bed_targets(args, &index, bedfile)
+        collect_bed_targets(args, &scorer, bedfile)
     } else {
-        collect_all_targets(args, &index)
+        collect_all_targets(args, &scorer)
     }
 }
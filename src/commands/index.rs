@@ -1,96 +1,493 @@
-use std::fmt::Debug;
 use std::io::prelude::*;
-use std::path::Path;
+use std::time::Instant;
 
 use bio::alphabets::dna;
 use bio::io::fasta::Reader;
+use rayon::prelude::*;
 
+use crate::api::{collect_sites_in, Sites};
 use crate::args::IndexArgs;
-use crate::constants::*;
+use crate::common::KMer;
+use crate::constants::{MAX_REFSEQS, MAX_SEQUENCE_LEN};
 use crate::enzyme::Enzyme;
 use crate::errors::*;
+use crate::external::{ExternalPositions, ENTRY_SIZE};
+use crate::fasta;
+use crate::filter::SequenceFilter;
 use crate::index::{KMerIndex, KMerMap, Position};
+use crate::provenance::{Provenance, SequenceInfo, Source};
+use crate::twobit::{TwoBitRecord, TwoBitSequences};
 
-fn collect_hashes<P: AsRef<Path> + Debug>(
-    filename: &P,
-    enzyme: &Enzyme,
+/// Minimum number of bp read before target sites are collected. Records in a batch are processed
+/// in parallel, as are (both strands of) each record.
+const BATCH_SIZE: usize = 64 * 1024 * 1024;
+
+/// A sequence to be indexed, along with a mask of bases that target sites may not overlap, i.e.
+/// soft-masked (lower-case) bases if these are excluded and bases outside the regions indexed
+type Record = (u32, Vec<u8>, Option<Vec<bool>>);
+
+/// A sequence packed for embedding in the index, if sequences are to be embedded
+type Packed = Option<(TwoBitRecord, Vec<u8>)>;
+
+/// The reverse complement of a sequence to be indexed, along with the reversed mask, if any
+type Reversed = (Vec<u8>, Option<Vec<bool>>);
+
+/// Target sites collected in memory, or positions collected in bounded memory
+enum Collector {
+    Memory(KMerMap),
+    External(ExternalPositions),
+}
+
+impl Collector {
+    fn add(&mut self, position: Position, kmer: KMer) -> Result<()> {
+        match self {
+            Collector::Memory(kmers) => kmers.add(position, kmer),
+            Collector::External(positions) => positions.add(position, kmer)?,
+        }
+
+        Ok(())
+    }
+}
+
+/// Collects target sites for each enzyme and sequence checksums in a batch of records in
+/// parallel, and adds them to the corresponding collector and to the list of checksums.
+/// Sequences are packed and added to `packed`, if set. Target sites are collected in chunks of
+/// (roughly) `chunk_len` bp at a time, which may span multiple records, limiting the number of
+/// sites held in memory before they are added to the collectors.
+fn collect_batch(
+    enzymes: &[Enzyme],
+    batch: &mut Vec<Record>,
+    chunk_len: usize,
+    hashes: &mut [Collector],
+    sequences: &mut Vec<SequenceInfo>,
+    packed: &mut Option<TwoBitSequences>,
+) -> Result<()> {
+    let embed = packed.is_some();
+    let prepared: Vec<((SequenceInfo, Packed), Reversed)> = batch
+        .par_iter()
+        .map(|(_, sequence, masked)| {
+            rayon::join(
+                || {
+                    rayon::join(
+                        || SequenceInfo::new(sequence),
+                        || {
+                            if embed {
+                                Some(TwoBitRecord::pack(sequence))
+                            } else {
+                                None
+                            }
+                        },
+                    )
+                },
+                || {
+                    let reverse = dna::revcomp(sequence);
+                    let masked = masked.as_ref().map(|v| v.iter().rev().cloned().collect());
+
+                    (reverse, masked)
+                },
+            )
+        })
+        .collect();
+
+    // Records are split into chunks, which are then grouped, such that each group spans about
+    // `chunk_len` bp; the sites in a group are collected in parallel
+    let mut chunks = Vec::new();
+    for (idx, (_, sequence, _)) in batch.iter().enumerate() {
+        for start in (0..sequence.len()).step_by(chunk_len) {
+            let end = usize::min(sequence.len(), start.saturating_add(chunk_len));
+            chunks.push((idx, start..end));
+        }
+    }
+
+    let mut group = Vec::new();
+    let mut group_len = 0;
+    for (idx, chunk) in chunks.iter().enumerate() {
+        group_len += chunk.1.len();
+        group.push(chunk);
+
+        if group_len >= chunk_len || idx + 1 == chunks.len() {
+            let sites: Vec<Vec<Sites>> = group
+                .par_iter()
+                .map(|(idx, windows)| {
+                    let (refseq, sequence, masked) = &batch[*idx];
+                    let (reverse, reverse_masked) = &prepared[*idx].1;
+                    let masked = masked.as_deref().zip(reverse_masked.as_deref());
+
+                    enzymes
+                        .iter()
+                        .map(|enzyme| {
+                            let sequence = (&sequence[..], &reverse[..]);
+                            collect_sites_in(enzyme, *refseq, sequence, masked, windows.clone())
+                        })
+                        .collect()
+                })
+                .collect::<Result<_>>()?;
+
+            for sites in sites {
+                for (sites, hashes) in sites.into_iter().zip(hashes.iter_mut()) {
+                    for (position, kmer) in sites {
+                        hashes.add(position, kmer)?;
+                    }
+                }
+            }
+
+            group.clear();
+            group_len = 0;
+        }
+    }
+
+    for ((info, record), _) in prepared {
+        sequences.push(info);
+        if let (Some(packed), Some((record, data))) = (packed.as_mut(), record) {
+            packed.push(record, &data);
+        }
+    }
+
+    batch.clear();
+
+    Ok(())
+}
+
+/// Adds the size of the processed batch to the running total and prints the progress so far
+fn report_progress(running_size: &mut usize, batch_size: &mut usize, timer: Instant) {
+    let seconds = timer.elapsed().as_secs() as usize;
+    *running_size += *batch_size;
+    *batch_size = 0;
+
+    eprint!(
+        "\r  Processed {} Mbp in {} seconds ({:.1} Mbp/s)",
+        *running_size / 1_000_000,
+        seconds,
+        (*running_size / 1_000_000) as f64 / ::std::cmp::max(1, seconds) as f64
+    );
+    ::std::io::stderr().flush().expect("unable to flush STDERR");
+}
+
+/// Returns the maximum number of bp in a batch of records and in a chunk of sequence for which
+/// target sites are collected at once. When memory is limited, half of the limit is used for
+/// buffering positions in the external tables (see `main`), while the remainder is split between
+/// the records in a batch (the sequence, its reverse complement, and masks for both strands; up
+/// to 4 bytes per bp) and the sites collected at once (at most one per strand per bp, for each
+/// enzyme).
+fn batch_limits(external: &[ExternalPositions], enzymes: usize) -> (usize, usize) {
+    if external.is_empty() {
+        return (BATCH_SIZE, usize::MAX);
+    }
+
+    let memory: usize = external.iter().map(|v| v.capacity() * ENTRY_SIZE).sum();
+    (
+        usize::max(1, memory / 2 / 4),
+        usize::max(1, memory / 2 / (2 * ENTRY_SIZE * enzymes)),
+    )
+}
+
+/// Builds an index for each enzyme of the target sites in the sequences of a FASTA file included
+/// by the filter, reading the FASTA file once. If external tables are given (one per enzyme),
+/// then positions are collected in those tables and the indexes are returned without positions.
+fn collect_hashes(
+    filename: &str,
+    filter: &SequenceFilter,
+    enzymes: &[Enzyme],
     positions: bool,
-) -> Result<(Vec<String>, KMerMap)> {
+    exclude_masked: bool,
+    embed_sequences: bool,
+    external: Vec<ExternalPositions>,
+) -> Result<Vec<(KMerIndex, Option<ExternalPositions>)>> {
     eprintln!("Finding target sites in {:?}", &filename);
-    let file = Reader::from_file(&filename)
-        .chain_err(|| format!("failed to open FASTA file {:?}", filename))?;
+    let file = Reader::new(fasta::open(filename)?);
 
     let mut refseqs = Vec::new();
-    let mut hashes = if positions {
-        KMerMap::Positions(vec![Vec::new(); KMER_COUNT])
+    let mut sequences = Vec::new();
+    let mut packed = if embed_sequences {
+        Some(TwoBitSequences::default())
     } else {
-        KMerMap::Counts(vec![0; KMER_COUNT])
+        None
+    };
+    let (batch_limit, chunk_len) = batch_limits(&external, enzymes.len());
+    let mut hashes: Vec<Collector> = if external.is_empty() {
+        enzymes
+            .iter()
+            .map(|enzyme| {
+                Collector::Memory(KMerMap::new(
+                    enzyme.pams.pams().len(),
+                    enzyme.kmer_len(),
+                    positions,
+                ))
+            })
+            .collect()
+    } else {
+        assert_eq!(external.len(), enzymes.len());
+        external.into_iter().map(Collector::External).collect()
     };
 
+    let mut batch = Vec::new();
+    let mut batch_size = 0;
     let mut running_size = 0;
-    let timer = ::std::time::Instant::now();
-    for (refseq, record) in file.records().enumerate() {
+    let timer = Instant::now();
+    let mut excluded = Vec::new();
+    let mut records = file
+        .records()
+        .filter(|record| match record {
+            Ok(record) if !filter.includes(record.id()) => {
+                excluded.push(record.id().to_owned());
+                false
+            }
+            _ => true,
+        })
+        .peekable();
+    while let Some(record) = records.next() {
         let record = record.chain_err(|| "failed to read FASTA sequence")?;
         record.check().map_err(|v| ErrorKind::Msg(v.into()))?;
 
-        refseqs.push(record.id().to_owned());
+        if refseqs.len() as u64 >= MAX_REFSEQS {
+            return Err(format!(
+                "too many reference sequences; at most {} are supported",
+                MAX_REFSEQS
+            )
+            .into());
+        } else if record.seq().len() as u64 > MAX_SEQUENCE_LEN {
+            return Err(format!(
+                "sequence {:?} is too long ({} bp); at most {} bp are supported",
+                record.id(),
+                record.seq().len(),
+                MAX_SEQUENCE_LEN
+            )
+            .into());
+        }
 
         let sequence = record.seq().to_ascii_uppercase();
-        for (idx, window) in sequence.windows(enzyme.pam.len() + KMER_LEN).enumerate() {
-            if let Some((pam_pos, kmer)) = enzyme.pam.kmer(window) {
-                // If the cut-site is unknown, then save the starting position of the target seq
-                let pos = ((idx + pam_pos) as isize + enzyme.cutsite) as i32;
+        let regions = filter.mask(record.id(), sequence.len());
+        let masked = if exclude_masked {
+            let soft_masked = record.seq().iter().map(u8::is_ascii_lowercase);
+            Some(match regions {
+                Some(regions) => regions
+                    .iter()
+                    .zip(soft_masked)
+                    .map(|(a, b)| *a || b)
+                    .collect(),
+                None => soft_masked.collect(),
+            })
+        } else {
+            regions
+        };
 
-                hashes.add(Position::forward(refseq as u32, pos), kmer);
-            }
+        // Records are processed whole, but batches are otherwise kept within the limit
+        if !batch.is_empty() && batch_size + sequence.len() > batch_limit {
+            collect_batch(
+                enzymes,
+                &mut batch,
+                chunk_len,
+                &mut hashes,
+                &mut sequences,
+                &mut packed,
+            )?;
+            report_progress(&mut running_size, &mut batch_size, timer);
         }
 
-        for (idx, window) in dna::revcomp(&sequence)
-            .windows(enzyme.pam.len() + KMER_LEN)
-            .enumerate()
-        {
-            if let Some((pam_pos, kmer)) = enzyme.pam.kmer(window) {
-                // If the cut-site is unknown, then save the starting position of the target seq
-                let pos =
-                    sequence.len() as i32 - ((idx + pam_pos) as isize + enzyme.cutsite) as i32;
+        batch_size += sequence.len();
+        batch.push((refseqs.len() as u32, sequence, masked));
+        refseqs.push(record.id().to_owned());
 
-                hashes.add(Position::reverse(refseq as u32, pos), kmer);
-            }
+        if batch_size >= batch_limit || records.peek().is_none() {
+            collect_batch(
+                enzymes,
+                &mut batch,
+                chunk_len,
+                &mut hashes,
+                &mut sequences,
+                &mut packed,
+            )?;
+            report_progress(&mut running_size, &mut batch_size, timer);
         }
+    }
 
-        let seconds = timer.elapsed().as_secs() as usize;
-        running_size += sequence.len();
+    eprintln!();
+    if !excluded.is_empty() {
+        eprintln!("  Excluded {} sequences", excluded.len());
+    }
 
-        print!(
-            "\r  Processed {} Mbp in {} seconds ({:.1} Mbp/s)",
-            running_size / 1_000_000,
-            seconds,
-            (running_size / 1_000_000) as f64 / ::std::cmp::max(1, seconds) as f64
-        );
-        ::std::io::stdout().flush().expect("unable to flush STDOUT");
+    for name in &filter.include {
+        if !refseqs.contains(name) && !excluded.contains(name) {
+            eprintln!("WARNING: sequence {:?} not found in {:?}", name, filename);
+        }
     }
 
-    println!();
+    let provenance = Provenance {
+        sources: vec![Source::new(filename).with_filter(filter.clone())],
+        sequences,
+    };
+
+    let mut indexes = Vec::with_capacity(enzymes.len());
+    for (enzyme, hashes) in enzymes.iter().zip(hashes) {
+        let (kmers, external) = match hashes {
+            Collector::Memory(kmers) => (kmers, None),
+            Collector::External(external) => (
+                KMerMap::new(enzyme.pams.pams().len(), enzyme.kmer_len(), true),
+                Some(external),
+            ),
+        };
+
+        let mut index = KMerIndex::new(enzyme, refseqs.clone(), kmers)
+            .with_provenance(provenance.clone())?
+            .with_masked_excluded(exclude_masked);
+        if let Some(packed) = &packed {
+            index = index.with_sequences(packed.clone());
+        }
+
+        indexes.push((index, external));
+    }
 
-    Ok((refseqs, hashes))
+    Ok(indexes)
 }
 
 pub fn main(args: &IndexArgs) -> Result<()> {
-    println!("Finding targets in {:?}", &args.fasta);
-    let (refseqs, hashes) = collect_hashes(&args.fasta, &args.enzyme, args.positions)
-        .chain_err(|| "failed to collect target sequence frequencies")?;
+    ::rayon::ThreadPoolBuilder::new()
+        .num_threads(args.threads)
+        .build_global()
+        .chain_err(|| "failed to build thread pool")?;
 
     let filename = match &args.output {
         Some(filename) => filename.clone(),
-        None => args.fasta.clone() + args.enzyme.extension,
+        None => match args.enzymes.as_slice() {
+            [enzyme] => args.fasta.clone() + &enzyme.extension,
+            enzymes => {
+                let names: Vec<String> = enzymes
+                    .iter()
+                    .map(|v| v.name.to_ascii_lowercase())
+                    .collect();
+
+                format!("{}.crispyr_{}", args.fasta, names.join("+"))
+            }
+        },
     };
 
-    let index = KMerIndex::new(&args.enzyme, refseqs, hashes);
-    println!("  Unique targets found: {}", index.kmer_count());
-    println!("  Writing list of target frequencies to {:?}", filename);
-    index
-        .write(&filename)
-        .chain_err(|| "failed to write target site frequencies")?;
+    let mut enzymes = args.enzymes.clone();
+    let mut positions = args.positions;
+    let mut exclude_masked = args.exclude_masked;
+    let mut embed_sequences = args.embed_sequences;
+    if args.append && args.max_memory.is_some() {
+        return Err(
+            "--max-memory cannot be used with --append; index the sequences separately \
+                    and combine the indexes using 'merge'"
+                .into(),
+        );
+    }
+
+    let existing = if args.append {
+        eprintln!("Reading existing index {:?}", filename);
+        let sections = KMerIndex::read_enzymes(&filename)
+            .chain_err(|| format!("failed to read K-mer index {:?}", filename))?;
+        if sections.len() != enzymes.len() {
+            return Err(format!(
+                "index contains {} enzyme(s), but {} were specified; all enzymes in the \
+                 index must be specified when appending",
+                sections.len(),
+                enzymes.len()
+            )
+            .into());
+        }
+
+        let mut indexes = Vec::with_capacity(enzymes.len());
+        for enzyme in &mut enzymes {
+            let index = KMerIndex::read_section(&filename, Some(enzyme))
+                .chain_err(|| format!("failed to read K-mer index {:?}", filename))?;
+            *enzyme = index.enzyme().clone();
+            indexes.push(index);
+        }
+
+        let index = &indexes[0];
+        positions = index.has_positions();
+        if args.positions && !positions {
+            return Err("cannot append positions to index without positions".into());
+        }
+
+        exclude_masked = index.masked_excluded();
+        if args.exclude_masked && !exclude_masked {
+            return Err(
+                "cannot exclude soft-masked sites when appending to index including them".into(),
+            );
+        }
+
+        embed_sequences = index.has_sequences();
+        if args.embed_sequences && !embed_sequences {
+            return Err("cannot embed sequences in index without embedded sequences".into());
+        }
+
+        Some(indexes)
+    } else {
+        None
+    };
+
+    if embed_sequences && !positions {
+        return Err("sequences can only be embedded in indexes with --positions".into());
+    } else if args.max_memory.is_some() && !positions {
+        return Err("--max-memory only applies to indexes with --positions".into());
+    }
+
+    // Half of the memory limit is used to buffer positions, shared between the enzymes indexed,
+    // while the remainder is used to process sequences (see `batch_limits`)
+    let external: Vec<ExternalPositions> = match args.max_memory {
+        Some(memory) if enzymes.len() == 1 => vec![ExternalPositions::new(&filename, memory / 2)],
+        Some(memory) => (0..enzymes.len())
+            .map(|idx| {
+                let prefix = format!("{}.{}", filename, idx);
+                ExternalPositions::new(prefix, memory / 2 / enzymes.len())
+            })
+            .collect(),
+        None => Vec::new(),
+    };
+
+    let sections = collect_hashes(
+        &args.fasta,
+        &args.filter,
+        &enzymes,
+        positions,
+        exclude_masked,
+        embed_sequences,
+        external,
+    )
+    .chain_err(|| "failed to collect target sequence frequencies")?;
+
+    let (mut indexes, external): (Vec<_>, Vec<_>) = sections.into_iter().unzip();
+    if let Some(existing) = existing {
+        eprintln!("  Adding targets to existing index");
+        indexes = indexes
+            .iter()
+            .zip(existing)
+            .map(|(index, existing)| KMerIndex::merge(&[&existing, index]))
+            .collect::<Result<_>>()?;
+    }
+
+    // Target sites are reported per enzyme if the index contains multiple enzymes
+    let labels: Vec<String> = enzymes
+        .iter()
+        .map(|v| match enzymes.len() {
+            1 => String::new(),
+            _ => format!("{} ", v.name),
+        })
+        .collect();
+
+    let external: Option<Vec<ExternalPositions>> = external.into_iter().collect();
+    if let Some(external) = external {
+        let runs: usize = external.iter().map(|v| v.runs()).sum();
+        eprintln!("  Merging {} sorted runs of positions", runs);
+        eprintln!("  Writing list of target frequencies to {:?}", filename);
+        let kmer_counts =
+            KMerIndex::write_sections_external(&filename, indexes.iter().zip(external).collect())
+                .chain_err(|| "failed to write target site frequencies")?;
+        for (label, kmer_count) in labels.iter().zip(kmer_counts) {
+            eprintln!("  Unique {}targets found: {}", label, kmer_count);
+        }
+    } else {
+        for (label, index) in labels.iter().zip(&indexes) {
+            eprintln!("  Unique {}targets found: {}", label, index.kmer_count());
+        }
+
+        eprintln!("  Writing list of target frequencies to {:?}", filename);
+        let indexes: Vec<&KMerIndex> = indexes.iter().collect();
+        KMerIndex::write_sections(&filename, &indexes)
+            .chain_err(|| "failed to write target site frequencies")?;
+    }
 
     Ok(())
 }
@@ -0,0 +1,295 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap};
+use std::io;
+use std::io::Write;
+
+use crate::args::InspectArgs;
+use crate::common::{decode_dna, encode_dna, open_file_or_stdout, KMer};
+use crate::enzyme::Enzyme;
+use crate::errors::*;
+use crate::index::KMerIndex;
+use crate::pam::Position;
+
+/// Returns the (inclusive) range of multiplicities in the histogram bin containing `count`;
+/// counts below 10 are reported individually, larger counts are binned by powers of 10.
+pub fn histogram_bin(count: u64) -> (u64, u64) {
+    if count < 10 {
+        (count, count)
+    } else {
+        let mut lower = 10;
+        while lower * 10 <= count {
+            lower *= 10;
+        }
+
+        (lower, lower * 10 - 1)
+    }
+}
+
+/// Writes a summary of the index and the number of sites per PAM, sequence, and K-mer.
+pub fn report(
+    index: &KMerIndex,
+    sections: &[Enzyme],
+    top: usize,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    let enzyme = index.enzyme();
+    let pams = enzyme.pams.pams();
+    let refseqs = index.refseqs();
+
+    let mut pam_sites = vec![0u64; pams.len()];
+    let mut refseq_sites = vec![(0u64, 0u64); refseqs.len()];
+    let mut histogram: BTreeMap<(u64, u64), u64> = BTreeMap::new();
+    let mut repeated = BinaryHeap::with_capacity(top + 1);
+
+    for kmer in index.iter_kmers() {
+        let mut count = 0;
+        for (pam, sites) in pam_sites.iter_mut().enumerate() {
+            let pam_count = u64::from(index.get_count(pam, kmer).unwrap_or(0));
+            *sites += pam_count;
+            count += pam_count;
+        }
+
+        if let Some(positions) = index.get_positions(kmer) {
+            for position in positions {
+                let sites = &mut refseq_sites[position.refseq() as usize];
+                if position.strand() == '+' {
+                    sites.0 += 1;
+                } else {
+                    sites.1 += 1;
+                }
+            }
+        }
+
+        *histogram.entry(histogram_bin(count)).or_insert(0) += 1;
+
+        // K-mers are visited in sorted order, so ties are resolved in favor of smaller K-mers
+        repeated.push(Reverse((count, Reverse(kmer.0))));
+        if repeated.len() > top {
+            repeated.pop();
+        }
+    }
+
+    let total_sites: u64 = pam_sites.iter().sum();
+    let (forward, reverse) = if index.has_positions() {
+        let forward: u64 = refseq_sites.iter().map(|v| v.0).sum();
+        let reverse: u64 = refseq_sites.iter().map(|v| v.1).sum();
+
+        (forward.to_string(), reverse.to_string())
+    } else {
+        ("NA".to_owned(), "NA".to_owned())
+    };
+
+    let header = [
+        ("Version", index.version().to_string()),
+        ("Enzyme", enzyme.name.clone()),
+        (
+            "Sections",
+            sections
+                .iter()
+                .map(|v| v.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", "),
+        ),
+        ("PAM", enzyme.pams.to_string()),
+        (
+            "Position",
+            match enzyme.pams.position() {
+                Position::Head => "5'".to_owned(),
+                Position::Tail => "3'".to_owned(),
+            },
+        ),
+        ("gRNALen", enzyme.grna_len.to_string()),
+        ("Cutsite", enzyme.cutsite.to_string()),
+        ("BottomCutsite", enzyme.cutsite_bottom.to_string()),
+        ("KMerLen", enzyme.kmer_len().to_string()),
+        (
+            "Positions",
+            if index.has_positions() { "yes" } else { "no" }.to_owned(),
+        ),
+        (
+            "MaskedSites",
+            if index.masked_excluded() {
+                "excluded"
+            } else {
+                "included"
+            }
+            .to_owned(),
+        ),
+        (
+            "EmbeddedSequences",
+            if index.has_sequences() { "yes" } else { "no" }.to_owned(),
+        ),
+        ("Sequences", refseqs.len().to_string()),
+        ("KMers", index.kmer_count().to_string()),
+        ("Sites", total_sites.to_string()),
+        ("ForwardSites", forward),
+        ("ReverseSites", reverse),
+    ];
+
+    writeln!(out, "# Index\nKey\tValue")?;
+    for (key, value) in header.iter() {
+        writeln!(out, "{}\t{}", key, value)?;
+    }
+
+    writeln!(out, "\n# Sources\nFASTA\tVersion\tCommand")?;
+    for source in &index.provenance().sources {
+        writeln!(
+            out,
+            "{}\t{}\t{}",
+            source.fasta, source.version, source.command
+        )?;
+    }
+
+    writeln!(out, "\n# Filters\nFASTA\tFilter")?;
+    for source in &index.provenance().sources {
+        writeln!(out, "{}\t{}", source.fasta, source.filter)?;
+    }
+
+    writeln!(out, "\n# Sites per PAM\nPAM\tSites")?;
+    for (pam, sites) in pams.iter().zip(pam_sites.iter()) {
+        writeln!(out, "{}\t{}", pam.to_string(), sites)?;
+    }
+
+    writeln!(
+        out,
+        "\n# Sites per sequence\nContig\tLength\tMD5\tSites\tForwardSites\tReverseSites"
+    )?;
+    let sequences = &index.provenance().sequences;
+    for (idx, (forward, reverse)) in refseq_sites.iter().enumerate() {
+        let (length, md5) = match sequences.get(idx) {
+            Some(info) => (info.len.to_string(), info.md5_hex()),
+            None => ("NA".to_owned(), "NA".to_owned()),
+        };
+
+        let sites = if index.has_positions() {
+            format!("{}\t{}\t{}", forward + reverse, forward, reverse)
+        } else {
+            "NA\tNA\tNA".to_owned()
+        };
+
+        writeln!(out, "{}\t{}\t{}\t{}", refseqs[idx], length, md5, sites)?;
+    }
+
+    writeln!(out, "\n# K-mer multiplicities\nMinSites\tMaxSites\tKMers")?;
+    for ((lower, upper), count) in histogram {
+        writeln!(out, "{}\t{}\t{}", lower, upper, count)?;
+    }
+
+    writeln!(out, "\n# Most repeated K-mers\nKMer\tSites")?;
+    for Reverse((count, Reverse(kmer))) in repeated.into_sorted_vec() {
+        writeln!(
+            out,
+            "{}\t{}",
+            decode_dna(KMer::new(kmer), enzyme.kmer_len()),
+            count
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Returns the K-mer for a query consisting of either a K-mer, a spacer (the target sequence
+/// excluding the PAM), or a target sequence including the PAM.
+pub fn parse_query(index: &KMerIndex, query: &str) -> Result<KMer> {
+    let enzyme = index.enzyme();
+    let pam = &enzyme.pams;
+    let query = query.to_ascii_uppercase();
+    let query = query.as_bytes();
+
+    let kmer = if query.len() == pam.kmer_len() {
+        encode_dna(query)
+    } else if query.len() == enzyme.grna_len - pam.len() {
+        // The K-mer is the part of the spacer adjacent to the PAM
+        match pam.position() {
+            Position::Head => encode_dna(&query[..pam.kmer_len()]),
+            Position::Tail => encode_dna(&query[query.len() - pam.kmer_len()..]),
+        }
+    } else if query.len() >= pam.kmer_len() + pam.len() && pam.matches(query) {
+        encode_dna(pam.kmer_slice(query))
+    } else {
+        None
+    };
+
+    kmer.ok_or_else(|| {
+        format!(
+            "invalid --lookup sequence {:?}; expected a {}-mer, a {} bp spacer, or a \
+             target sequence with a {} PAM",
+            String::from_utf8_lossy(query),
+            pam.kmer_len(),
+            enzyme.grna_len - pam.len(),
+            pam
+        )
+        .into()
+    })
+}
+
+/// Writes the sites (or, without positions, the number of sites per PAM) of each queried K-mer.
+pub fn lookup(index: &KMerIndex, queries: &[(&str, KMer)], out: &mut dyn Write) -> io::Result<()> {
+    let enzyme = index.enzyme();
+    let pams = enzyme.pams.pams();
+    let refseqs = index.refseqs();
+
+    if index.has_positions() {
+        writeln!(out, "Query\tKMer\tPAM\tContig\tCutsite\tStrand")?;
+    } else {
+        writeln!(out, "Query\tKMer\tPAM\tSites")?;
+    }
+
+    for &(query, kmer) in queries {
+        let kmer_seq = decode_dna(kmer, enzyme.kmer_len());
+
+        if index.has_positions() {
+            let positions = index.get_positions(kmer).unwrap_or(&[]);
+            for position in positions {
+                writeln!(
+                    out,
+                    "{}\t{}\t{}\t{}\t{}\t{}",
+                    query,
+                    kmer_seq,
+                    pams[position.pam()].to_string(),
+                    refseqs[position.refseq() as usize],
+                    position.pos() + 1,
+                    position.strand(),
+                )?;
+            }
+
+            if positions.is_empty() {
+                writeln!(out, "{}\t{}\tNA\tNA\tNA\tNA", query, kmer_seq)?;
+            }
+        } else {
+            for (idx, pam) in pams.iter().enumerate() {
+                writeln!(
+                    out,
+                    "{}\t{}\t{}\t{}",
+                    query,
+                    kmer_seq,
+                    pam.to_string(),
+                    index.get_count(idx, kmer).unwrap_or(0)
+                )?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub fn main(args: &InspectArgs) -> Result<()> {
+    eprintln!("Reading K-mers from {:?}", args.index);
+    let (index, sections) = KMerIndex::read_section_with_enzymes(&args.index, args.enzyme.as_ref())
+        .chain_err(|| format!("failed to read K-mer index {:?}", args.index))?;
+    eprintln!("  {}", index.summarize());
+
+    let mut queries = Vec::with_capacity(args.lookup.len());
+    for query in &args.lookup {
+        queries.push((query.as_str(), parse_query(&index, query)?));
+    }
+
+    let mut out = open_file_or_stdout(&args.output)?;
+    if queries.is_empty() {
+        eprintln!("Summarizing K-mers");
+        report(&index, &sections, args.top, &mut out)
+    } else {
+        lookup(&index, &queries, &mut out)
+    }
+    .chain_err(|| "failed to write report")
+}
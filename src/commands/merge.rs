@@ -0,0 +1,64 @@
+use crate::args::MergeArgs;
+use crate::enzyme::Enzyme;
+use crate::errors::*;
+use crate::index::KMerIndex;
+
+pub fn main(args: &MergeArgs) -> Result<()> {
+    // Each index is read once; indexes with multiple enzymes are merged section by section
+    let mut files = Vec::with_capacity(args.indexes.len());
+    for filename in &args.indexes {
+        eprintln!("Reading K-mers from {:?}", filename);
+        let sections = KMerIndex::read_sections(filename)
+            .chain_err(|| format!("failed to read K-mer index {:?}", filename))?;
+        for index in &sections {
+            eprintln!("  {}", index.summarize());
+        }
+
+        files.push(sections);
+    }
+
+    let first = files.first().ok_or("no indexes to merge")?;
+    let enzymes: Vec<&Enzyme> = first.iter().map(|v| v.enzyme()).collect();
+    for (filename, sections) in args.indexes.iter().zip(&files).skip(1) {
+        let other: Vec<&Enzyme> = sections.iter().map(|v| v.enzyme()).collect();
+        if other != enzymes {
+            let names = |enzymes: &[&Enzyme]| {
+                let names: Vec<String> = enzymes
+                    .iter()
+                    .map(|v| format!("{:?} ({}-mers)", v.name, v.kmer_len()))
+                    .collect();
+
+                names.join(", ")
+            };
+
+            return Err(format!(
+                "cannot merge indexes built for different enzymes; {:?} contains sections for \
+                 {}, but {:?} for {}",
+                args.indexes[0],
+                names(&enzymes),
+                filename,
+                names(&other)
+            )
+            .into());
+        }
+    }
+
+    let mut merged = Vec::with_capacity(enzymes.len());
+    for (section, enzyme) in enzymes.iter().enumerate() {
+        eprintln!(
+            "Merging {} K-mers from {} indexes",
+            enzyme.name,
+            files.len()
+        );
+        let indexes: Vec<&KMerIndex> = files.iter().map(|v| &v[section]).collect();
+        let index = KMerIndex::merge(&indexes)?;
+        eprintln!("  {}", index.summarize());
+
+        merged.push(index);
+    }
+
+    eprintln!("Writing merged index to {:?}", args.output);
+    let merged: Vec<&KMerIndex> = merged.iter().collect();
+    KMerIndex::write_sections(&args.output, &merged)
+        .chain_err(|| format!("failed to write K-mer index {:?}", args.output))
+}
@@ -1,4 +1,8 @@
+pub mod enzymes;
 pub mod find;
 pub mod index;
+pub mod inspect;
+pub mod merge;
 pub mod offtargets;
 pub mod score;
+pub mod upgrade_index;
@@ -1,42 +1,63 @@
 use std::collections::{HashMap, HashSet};
-use std::fs::File;
 use std::io::Write;
 use std::path::Path;
 
 use bio::alphabets::dna;
 use bio::io::fasta::IndexedReader;
 
+use crate::api::{guide_kmer, offtargets, spacer_mismatches, OffTargetSite};
 use crate::args::OffTargetsArgs;
-use crate::common::{encode_dna, open_file_or_stdout};
-use crate::constants::*;
+use crate::common::open_file_or_stdout;
 use crate::enzyme::Enzyme;
 use crate::errors::*;
+use crate::fasta::{open_indexed, FastaFile};
 use crate::index::KMerIndex;
-use crate::pam::Position;
-use crate::score::find_offtargets;
+use crate::score::Scorer;
 use crate::table;
 
+/// Fetches off-target sequences from the sequences embedded in the index or from a FASTA file
 struct OfftargetReader {
-    reader: Option<IndexedReader<File>>,
+    embedded: bool,
+    reader: Option<IndexedReader<FastaFile>>,
     // FIXME: This should be made redudant by improving IndexedReader
     refseqs: Option<HashMap<String, u64>>,
 }
 
 impl OfftargetReader {
-    fn new(index: &str, fasta: &Option<String>) -> Result<Self> {
+    fn new(
+        index: &KMerIndex,
+        index_path: &str,
+        fasta: &Option<String>,
+        verify_fasta: bool,
+    ) -> Result<Self> {
+        // Sequences embedded in the index are used unless a FASTA file is specified
+        if fasta.is_none() && index.has_sequences() {
+            eprintln!("  using sequences embedded in index");
+
+            return Ok(OfftargetReader {
+                embedded: true,
+                reader: None,
+                refseqs: None,
+            });
+        }
+
+        // Defaults to the FASTA file recorded in the index, falling back to the path of the index
+        // minus the extension (e.g. if the FASTA file has been moved along with the index)
+        let sources = &index.provenance().sources;
         let fasta_path = match fasta {
             Some(path) => path,
-            None => &index[..index.rfind('.').unwrap_or(index.len())],
+            None if sources.len() == 1 && Path::new(&sources[0].fasta).exists() => {
+                &sources[0].fasta
+            }
+            None => &index_path[..index_path.rfind('.').unwrap_or(index_path.len())],
         };
 
-        let reader = if Path::new(fasta_path).exists() {
+        let mut reader = if Path::new(fasta_path).exists() {
             let mut fai_path = fasta_path.to_owned();
             fai_path.push_str(".fai");
 
             if Path::new(&fai_path).exists() {
-                IndexedReader::from_file(&fasta_path)
-                    .map(|v| Some(v))
-                    .chain_err(|| format!("failed to read FASTA file {:?}", fasta_path))?
+                Some(open_indexed(fasta_path)?)
             } else {
                 eprintln!("{:?} not indexed; cannot fetch off-target seqs", fasta_path);
                 eprintln!("Please run: samtools faidx '{}'", fasta_path);
@@ -49,162 +70,183 @@ impl OfftargetReader {
             None
         };
 
-        let refseqs = if let Some(reader) = &reader {
-            Some(
-                reader
-                    .index
-                    .sequences()
-                    .into_iter()
-                    .map(|seq| (seq.name, seq.len))
-                    .collect(),
-            )
-        } else {
-            None
-        };
+        if let Some(reader) = &mut reader {
+            let verified =
+                index
+                    .provenance()
+                    .verify(index.refseqs(), reader, fasta_path, verify_fasta)?;
+            eprintln!("  verified {} sequences against index", verified);
+        }
 
-        Ok(OfftargetReader { reader, refseqs })
+        let refseqs = reader.as_ref().map(|reader| {
+            reader
+                .index
+                .sequences()
+                .into_iter()
+                .map(|seq| (seq.name, seq.len))
+                .collect()
+        });
+
+        Ok(OfftargetReader {
+            embedded: false,
+            reader,
+            refseqs,
+        })
     }
 
-    fn fetch(
-        &mut self,
-        refseq: &str,
-        start: isize,
-        end: isize,
-        strand: char,
-    ) -> Result<Option<Vec<u8>>> {
-        assert!(start < end);
-
-        if let Some(reader) = &mut self.reader {
-            let refseqs = self.refseqs.as_ref().unwrap();
-            let refseq_len = match refseqs.get(refseq) {
-                Some(refseq_len) => refseq_len,
-                None => return Err(format!("Unknown refseq {:?}", refseq).into()),
-            };
-
-            let len = (end - start) as usize;
-
-            if end <= 0 {
-                Ok(Some(vec![b'N'; len]))
-            } else {
-                let mut seq = Vec::with_capacity(len);
+    /// Returns true if off-target sequences are available
+    fn has_sequences(&self) -> bool {
+        self.embedded || self.reader.is_some()
+    }
 
-                // 3' PAM gRNAs may extend past the beginning of the refseq
-                if start < 0 {
-                    seq.resize(start.abs() as usize, b'N');
-                }
+    /// Fetches the sequence of an off-target, or returns None if sequences are not available
+    fn fetch(&mut self, site: &OffTargetSite) -> Result<Option<Vec<u8>>> {
+        if self.embedded {
+            return Ok(site.sequence.clone());
+        }
 
-                reader
-                    .fetch(
-                        refseq,
-                        isize::max(0, start) as u64,
-                        u64::min(*refseq_len, end as u64),
-                    )
-                    .chain_err(|| "failed to fetch refseq")?;
+        let (reader, refseqs) = match (&mut self.reader, &self.refseqs) {
+            (Some(reader), Some(refseqs)) => (reader, refseqs),
+            _ => return Ok(None),
+        };
 
-                for nuc in reader.read_iter().chain_err(|| "failed to fetch refseq")? {
-                    seq.push(nuc.chain_err(|| "error while reading refseq")?);
-                }
+        let refseq_len = match refseqs.get(site.name) {
+            Some(&len) => len,
+            None => return Err(format!("Unknown refseq {:?}", site.name).into()),
+        };
 
-                // 5' PAM gRNAs may extend past the end of the refseq
-                seq.resize(len, b'N');
+        let (start, end) = (site.start, site.end);
+        let len = (end - start) as usize;
+        if end <= 0 {
+            return Ok(Some(vec![b'N'; len]));
+        }
 
-                if strand == '-' {
-                    seq = dna::revcomp(seq);
-                }
+        let mut seq = Vec::with_capacity(len);
+
+        // 3' PAM gRNAs may extend past the beginning of the refseq
+        if start < 0 {
+            seq.resize(start.unsigned_abs(), b'N');
+        }
 
-                Ok(Some(seq))
+        let fetch_start = isize::max(0, start) as u64;
+        let fetch_end = u64::min(refseq_len, end as u64);
+        if fetch_start < fetch_end {
+            reader
+                .fetch(site.name, fetch_start, fetch_end)
+                .chain_err(|| "failed to fetch refseq")?;
+
+            for nuc in reader.read_iter().chain_err(|| "failed to fetch refseq")? {
+                seq.push(nuc.chain_err(|| "error while reading refseq")?);
             }
-        } else {
-            Ok(None)
         }
+
+        // 5' PAM gRNAs may extend past the end of the refseq
+        seq.resize(len, b'N');
+
+        if site.strand == '-' {
+            seq = dna::revcomp(seq);
+        }
+
+        Ok(Some(seq))
     }
 }
 
 /// Indicates the KMer in uppercase, rendering everything else in lowercase
 fn format_guide_rna(enzyme: &Enzyme, seq: &[u8]) -> String {
     let mut seq = seq.to_ascii_lowercase();
-    for c in enzyme.pam.kmer_slice_mut(&mut seq) {
+    for c in enzyme.pams.kmer_slice_mut(&mut seq) {
         *c = c.to_ascii_uppercase();
     }
 
     String::from_utf8_lossy(&seq).to_string()
 }
 
+/// Formats the number and positions of mismatches across the full spacer, including the
+/// leading tab, or 'NA' if the off-target sequence is not available
+fn format_mismatches(mismatches: &Option<Vec<usize>>) -> String {
+    match mismatches {
+        Some(mismatches) if mismatches.is_empty() => "\t0\t-".to_string(),
+        Some(mismatches) => {
+            let positions: Vec<String> = mismatches.iter().map(|v| v.to_string()).collect();
+
+            format!("\t{}\t{}", mismatches.len(), positions.join(","))
+        }
+        None => "\tNA\tNA".to_string(),
+    }
+}
+
 fn write_off_targets(
     out: &mut dyn Write,
     fasta: &mut OfftargetReader,
-    index: &KMerIndex,
+    scorer: &Scorer,
     query: &str,
     value: &str,
-    min_score: u64,
+    args: &OffTargetsArgs,
 ) -> Result<()> {
-    let enzyme = index.enzyme();
-    let refseqs = index.refseqs();
-    let pam = &enzyme.pam;
-
-    if query.len() >= KMER_LEN + pam.len() && pam.matches(query.as_bytes()) {
-        let query = query.as_bytes();
-        let kmer = enzyme.pam.kmer_slice(query).to_ascii_uppercase();
-        assert!(kmer.len() == KMER_LEN);
-
-        if let Some(kmer) = encode_dna(&kmer) {
-            let grna_len = enzyme.grna_len as isize;
-            let pam_len = pam.len() as isize;
-            let cutsite = enzyme.cutsite;
-
-            let (offset_start, offset_end) = match pam.position() {
-                Position::Head => (1 - cutsite, grna_len - cutsite),
-                Position::Tail => (-cutsite - grna_len + pam_len + 1, pam_len - cutsite),
-            };
-
-            for (score, position) in find_offtargets(index, kmer, min_score) {
-                let (offset_start, offset_end) = if position.strand() == '+' {
-                    (offset_start, offset_end)
-                } else {
-                    (1 - offset_end, 1 - offset_start)
-                };
-
-                let refseq = &refseqs[position.refseq() as usize];
-                let start = position.pos() as isize + offset_start;
-                let end = position.pos() as isize + offset_end;
-                // fetch uses 0-based start, 1-based end
-                let offtarget = fasta
-                    .fetch(refseq, start - 1, end, position.strand())
-                    .chain_err(|| "failed to fetch offtarget sequence")?;
-
-                writeln!(
-                    out,
-                    "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
-                    format_guide_rna(index.enzyme(), query),
-                    if let Some(seq) = offtarget {
-                        format_guide_rna(index.enzyme(), &seq)
-                    } else {
-                        "NA".to_owned()
-                    },
-                    refseq,
-                    start,
-                    end,
-                    position.pos() + 1,
-                    position.strand(),
-                    score,
-                )
-                .chain_err(|| "failed to write output row")?;
+    let enzyme = scorer.index().enzyme();
+    if guide_kmer(enzyme, query.as_bytes()).is_none() {
+        eprintln!("WARNING: Could not look up off-targets for '{:?}'", value);
+
+        return Ok(());
+    } else if args.verify && query.len() != enzyme.grna_len {
+        eprintln!(
+            "WARNING: Cannot verify off-targets for {:?}; not a {} bp gRNA target sequence \
+             (spacer and PAM)",
+            value, enzyme.grna_len
+        );
+    }
+
+    for site in offtargets(scorer, query.as_bytes(), args.min_score)? {
+        let offtarget = fasta
+            .fetch(&site)
+            .chain_err(|| "failed to fetch offtarget sequence")?;
+
+        let mismatches = if args.verify {
+            let mismatches = offtarget
+                .as_ref()
+                .and_then(|seq| spacer_mismatches(enzyme, query.as_bytes(), seq));
+
+            match &mismatches {
+                Some(mismatches) if mismatches.len() > args.max_mismatches => continue,
+                _ => format_mismatches(&mismatches),
             }
+        } else {
+            String::new()
+        };
 
-            return Ok(());
-        }
+        writeln!(
+            out,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}{}",
+            format_guide_rna(enzyme, query.as_bytes()),
+            if let Some(seq) = offtarget {
+                format_guide_rna(enzyme, &seq)
+            } else {
+                "NA".to_owned()
+            },
+            site.name,
+            site.start + 1,
+            site.end,
+            site.cutsite + 1,
+            site.cutsite_bottom + 1,
+            enzyme.overhang(),
+            site.strand,
+            Scorer::format_offtarget_scores(scorer.models(), &site.scores),
+            mismatches,
+        )
+        .chain_err(|| "failed to write output row")?;
     }
 
-    eprintln!("WARNING: Could not look up off-targets for '{:?}'", value);
-
     Ok(())
 }
 
 pub fn main(args: &OffTargetsArgs) -> Result<()> {
     eprintln!("\nReading K-mers from {:?}", args.index);
-    let index = KMerIndex::read(&args.index)
+    let index = KMerIndex::read_section(&args.index, args.enzyme.as_ref())
         .chain_err(|| format!("failed to read K-mer index {:?}", args.index))?;
     eprintln!("  {}", index.summarize());
+    for line in index.summarize_filters() {
+        eprintln!("  {}", line);
+    }
 
     if !index.has_positions() {
         return Err("fasta not indexed with --positions; cannot find off-targets ".into());
@@ -214,13 +256,31 @@ pub fn main(args: &OffTargetsArgs) -> Result<()> {
     let table = table::read(&args.table).chain_err(|| "failed to read table of target sites")?;
     eprintln!("  read {} target sites from table.", table.len());
 
-    let mut reader =
-        OfftargetReader::new(&args.index, &args.fasta).chain_err(|| "failed to open FASTA file")?;
+    let mut reader = OfftargetReader::new(&index, &args.index, &args.fasta, args.verify_fasta)
+        .chain_err(|| "failed to open FASTA file")?;
+    if args.verify && !reader.has_sequences() {
+        return Err("--verify requires sequences embedded in the index or a FASTA file".into());
+    } else if args.verify && args.max_mismatches > index.enzyme().spacer_len() {
+        return Err(format!(
+            "--max-mismatches must be at most {} (the length of the spacer)",
+            index.enzyme().spacer_len()
+        )
+        .into());
+    }
 
+    let scorer = Scorer::new(&index, &args.profile, &args.models)?;
     let mut out = open_file_or_stdout(&args.output)?;
+    write!(out, "{}", scorer.profile().to_header())
+        .chain_err(|| "failed to write output header")?;
     writeln!(
         out,
-        "Query\tOfftarget\tName\tStart\tEnd\tCutsite\tStrand\tScore"
+        "Query\tOfftarget\tName\tStart\tEnd\tCutsite\tBottomCutsite\tOverhang\tStrand\t{}{}",
+        scorer.offtarget_header(),
+        if args.verify {
+            "\tMismatches\tMismatchPositions"
+        } else {
+            ""
+        },
     )
     .chain_err(|| "failed to write output header")?;
 
@@ -230,14 +290,7 @@ pub fn main(args: &OffTargetsArgs) -> Result<()> {
         let query = value.to_ascii_uppercase();
 
         if values.insert(value.clone()) {
-            write_off_targets(
-                &mut out,
-                &mut reader,
-                &index,
-                &query,
-                &value,
-                args.min_score,
-            )?;
+            write_off_targets(&mut out, &mut reader, &scorer, &query, value, args)?;
         }
     }
 
@@ -1,37 +1,26 @@
 use rayon::prelude::*;
 
+use crate::api::guide_kmer;
 use crate::args::ScoreArgs;
-use crate::common::{encode_dna, open_file_or_stdout};
-use crate::constants::*;
+use crate::common::open_file_or_stdout;
 use crate::errors::*;
 use crate::index::KMerIndex;
 use crate::progress;
-use crate::score::calculate_score;
+use crate::score::Scorer;
 use crate::table;
 
-fn build_row(index: &KMerIndex, idx: usize, row: &mut Vec<String>) {
-    let pam = &index.enzyme().pam;
-    let value = row
-        .first()
-        .expect("unexpected empty table row")
-        .to_ascii_uppercase();
-
-    if value.len() >= KMER_LEN + pam.len() && pam.matches(value.as_bytes()) {
-        let start = value.len() - pam.len() - KMER_LEN;
-        let kmer = &value.as_bytes()[start..start + KMER_LEN];
-        assert!(kmer.len() == KMER_LEN);
-
-        if let Some(kmer) = encode_dna(kmer) {
-            row.push(calculate_score(&index, kmer).to_string());
-            return;
-        }
+fn build_row(scorer: &Scorer, idx: usize, row: &mut Vec<String>) {
+    let value = row.first().expect("unexpected empty table row");
+    if let Some(kmer) = guide_kmer(scorer.index().enzyme(), value.as_bytes()) {
+        row.push(scorer.score(kmer));
+        return;
     }
 
     // Not a valid gRNA sequence; either a header or (presumably) DNA containing Ns
     if idx == 0 {
-        row.push("Score".into());
+        row.push(scorer.header());
     } else {
-        row.push("NA".into());
+        row.push(vec!["NA"; scorer.models().len()].join("\t"));
     }
 }
 
@@ -42,9 +31,14 @@ pub fn main(args: &ScoreArgs) -> Result<()> {
         .chain_err(|| "failed to build thread pool")?;
 
     eprintln!("\nReading K-mers from {:?}", args.index);
-    let index = KMerIndex::read(&args.index)
+    let index = KMerIndex::read_section(&args.index, args.enzyme.as_ref())
         .chain_err(|| format!("failed to read K-mer index {:?}", args.index))?;
     eprintln!("  {}", index.summarize());
+    for line in index.summarize_filters() {
+        eprintln!("  {}", line);
+    }
+
+    let scorer = Scorer::new(&index, &args.profile, &args.models)?;
 
     eprintln!("Reading target sites from {:?}", args.table);
     let mut table =
@@ -53,7 +47,7 @@ pub fn main(args: &ScoreArgs) -> Result<()> {
 
     let progress = progress::default(table.len());
     table.par_iter_mut().enumerate().for_each(|(idx, row)| {
-        build_row(&index, idx, row);
+        build_row(&scorer, idx, row);
 
         progress.inc(1);
     });
@@ -61,6 +55,8 @@ pub fn main(args: &ScoreArgs) -> Result<()> {
     progress.finish();
 
     let mut out = open_file_or_stdout(&args.output)?;
+    write!(out, "{}", scorer.profile().to_header())
+        .chain_err(|| "failed to write output header")?;
     for row in table {
         writeln!(out, "{}", row.join("\t")).chain_err(|| "failed to write output row")?;
     }
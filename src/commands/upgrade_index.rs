@@ -0,0 +1,36 @@
+use crate::args::UpgradeIndexArgs;
+use crate::constants::INDEX_VERSION;
+use crate::errors::*;
+use crate::index::KMerIndex;
+
+pub fn main(args: &UpgradeIndexArgs) -> Result<()> {
+    eprintln!("Reading K-mers from {:?}", args.index);
+    let enzymes = KMerIndex::read_enzymes(&args.index)
+        .chain_err(|| format!("failed to read K-mer index {:?}", args.index))?;
+
+    let mut indexes = Vec::with_capacity(enzymes.len());
+    for enzyme in &enzymes {
+        let index = KMerIndex::read_section(&args.index, Some(enzyme))
+            .chain_err(|| format!("failed to read K-mer index {:?}", args.index))?;
+        eprintln!("  {}", index.summarize());
+
+        indexes.push(index);
+    }
+
+    let index = &indexes[0];
+    if index.version() == INDEX_VERSION {
+        eprintln!("Index is already up to date (version {})", INDEX_VERSION);
+        return Ok(());
+    }
+
+    let output = args.output.as_ref().unwrap_or(&args.index);
+    eprintln!(
+        "Upgrading index from version {} to version {}",
+        index.version(),
+        INDEX_VERSION
+    );
+    eprintln!("Writing upgraded index to {:?}", output);
+    let indexes: Vec<&KMerIndex> = indexes.iter().collect();
+    KMerIndex::write_sections(output, &indexes)
+        .chain_err(|| format!("failed to write K-mer index {:?}", output))
+}
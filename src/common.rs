@@ -2,14 +2,14 @@ use std::fs::File;
 use std::io;
 use std::io::Write;
 
-use crate::constants::KMER_LEN;
+use crate::constants::MAX_KMER_LEN;
 use crate::errors::*;
 
 #[derive(Hash, PartialEq, Eq, Debug, Clone, Copy)]
-pub struct KMer(pub u32);
+pub struct KMer(pub u64);
 
 impl KMer {
-    pub fn new(kmer: u32) -> KMer {
+    pub fn new(kmer: u64) -> KMer {
         KMer(kmer)
     }
 
@@ -18,8 +18,10 @@ impl KMer {
     }
 }
 
+/// Encodes a sequence of up to `MAX_KMER_LEN` nucleotides using 2 bits per nucleotide; returns
+/// None if the sequence contains nucleotides other than A, C, G, and T.
 pub fn encode_dna(seq: &[u8]) -> Option<KMer> {
-    assert!(seq.len() == KMER_LEN);
+    assert!(seq.len() <= MAX_KMER_LEN);
 
     let mut encoded_dna = 0;
     for nuc in seq {
@@ -36,6 +38,21 @@ pub fn encode_dna(seq: &[u8]) -> Option<KMer> {
     Some(KMer::new(encoded_dna))
 }
 
+/// Decodes a K-mer of the given length encoded using `encode_dna`.
+pub fn decode_dna(kmer: KMer, len: usize) -> String {
+    assert!(len <= MAX_KMER_LEN);
+
+    (0..len)
+        .rev()
+        .map(|idx| match (kmer.0 >> (2 * idx)) & 3 {
+            0 => 'A',
+            1 => 'C',
+            2 => 'G',
+            _ => 'T',
+        })
+        .collect()
+}
+
 pub fn open_file_or_stdout(file: &Option<String>) -> Result<Box<dyn Write>> {
     if let Some(path) = file {
         let handle =
@@ -1,9 +1,44 @@
-pub const KMER_LEN: usize = 13;
+// Default maximum number of mismatches across the full spacer of verified off-targets
+pub const DEFAULT_MAX_MISMATCHES: usize = 4;
+// Default length of the PAM-proximal K-mers (seeds) used to index target sites
+pub const DEFAULT_KMER_LEN: usize = 13;
+// Maximum K-mer length; limited by the number of bits used to encode K-mers
+pub const MAX_KMER_LEN: usize = 32;
+// Maximum K-mer length for which dense tables (4^K entries per table) are used
+pub const MAX_DENSE_KMER_LEN: usize = 13;
 
-pub const KMER_COUNT: usize = 2 << (2 * KMER_LEN - 1);
+// Maximum number of PAMs per enzyme; limited by the number of bits available in positions
+pub const MAX_PAMS: usize = 8;
+// Maximum number of reference sequences; limited by the number of bits available in positions
+pub const MAX_REFSEQS: u64 = 1 << 32;
+// Maximum length of reference sequences; limited by the number of bits available in positions,
+// leaving room for cut-sites outside the sequence
+pub const MAX_SEQUENCE_LEN: u64 = 1 << 58;
 
 pub const INDEX_HEADER: &[u8] = b"CRISPyR";
-pub const INDEX_VERSION: u8 = 4;
+pub const INDEX_VERSION: u8 = 17;
+// Oldest version of the current index format that can be read
+pub const MIN_INDEX_VERSION: u8 = 12;
+// First version of the index format recording the sequence filters used
+pub const FILTER_INDEX_VERSION: u8 = 13;
+// First version of the index format supporting sections for multiple enzymes
+pub const MULTI_ENZYME_INDEX_VERSION: u8 = 14;
+// First version of the index format storing positions in a single u64 (using 24 bit reference
+// sequences); positions in older versions are converted when the index is read
+pub const PACKED_POSITIONS_INDEX_VERSION: u8 = 15;
+// First version of the index format recording the MD5 digest of BED files used to filter
+// regions, instead of the regions themselves
+pub const BED_MD5_INDEX_VERSION: u8 = 16;
+// First version of the index format storing positions using a 32 bit reference sequence and a
+// u64 containing the strand, PAM, and position; positions in older versions are converted when
+// the index is read
+pub const WIDE_POSITIONS_INDEX_VERSION: u8 = 17;
+// Version of the index format used by CRISPyR v0.2.x; may be read and upgraded
+pub const LEGACY_INDEX_VERSION: u8 = 4;
 
 // Index flag indicating if PAM positions have been saved
 pub const FLAG_POSITIONS: u64 = 0b1;
+// Index flag indicating if target sites overlapping soft-masked sequence have been excluded
+pub const FLAG_EXCLUDE_MASKED: u64 = 0b10;
+// Index flag indicating if (2-bit packed) reference sequences have been embedded
+pub const FLAG_SEQUENCES: u64 = 0b100;
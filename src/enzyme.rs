@@ -1,48 +1,315 @@
-use crate::pam::PAM;
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+
+use crate::constants::{MAX_KMER_LEN, MAX_PAMS};
+use crate::errors::*;
+use crate::iupac;
+use crate::pam::{PAMSet, Position, PAM};
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct Enzyme {
-    pub name: &'static str,
-    pub extension: &'static str,
+    pub name: String,
+    pub extension: String,
 
     pub grna_len: usize,
 
-    pub pam: PAM,
+    pub pams: PAMSet,
+    /// Cut-site on the top strand (the strand containing the PAM), relative to the PAM
     pub cutsite: isize,
+    /// Cut-site on the bottom strand, relative to the PAM on the top strand
+    pub cutsite_bottom: isize,
 }
 
 impl Enzyme {
+    pub fn new<P: Into<PAMSet>>(name: &str, pams: P, grna_len: usize, cutsite: isize) -> Enzyme {
+        Enzyme {
+            name: name.to_owned(),
+            extension: format!(".crispyr_{}", name.to_ascii_lowercase()),
+
+            grna_len,
+
+            pams: pams.into(),
+            cutsite,
+            cutsite_bottom: cutsite,
+        }
+    }
+
+    /// Sets the cut-site on the bottom strand for enzymes producing staggered cuts; by default
+    /// both strands are cut at the same position (blunt ends).
+    pub fn with_bottom_cutsite(mut self, cutsite: isize) -> Enzyme {
+        self.cutsite_bottom = cutsite;
+        self
+    }
+
+    /// Length of the overhang produced by the enzyme; positive values indicate 5' overhangs and
+    /// negative values indicate 3' overhangs.
+    pub fn overhang(&self) -> isize {
+        self.cutsite_bottom - self.cutsite
+    }
+
+    /// Sets the length of the PAM-proximal K-mers used to index target sites.
+    pub fn with_kmer_len(mut self, kmer_len: usize) -> Enzyme {
+        self.pams = self.pams.with_kmer_len(kmer_len);
+        self
+    }
+
+    pub fn kmer_len(&self) -> usize {
+        self.pams.kmer_len()
+    }
+
+    /// Length of the spacer, i.e. the gRNA target sequence excluding the PAM
+    pub fn spacer_len(&self) -> usize {
+        self.grna_len - self.pams.len()
+    }
+
     pub fn get(name: &str) -> Option<Enzyme> {
-        match name.to_ascii_lowercase().as_ref() {
-            "cas9" => Some(Self::cas9()),
-            "mad7" => Some(Self::mad7()),
-            _ => None,
+        let name = name.to_ascii_lowercase();
+        let name = match name.as_ref() {
+            "spcas9" => "cas9",
+            name => name,
+        };
+
+        Self::catalogue()
+            .into_iter()
+            .find(|enzyme| enzyme.name.to_ascii_lowercase() == name)
+    }
+
+    /// Returns all built-in enzymes.
+    pub fn catalogue() -> Vec<Enzyme> {
+        vec![
+            Self::cas9(),
+            Self::cas9_nc(),
+            Self::sacas9(),
+            Self::spcas9_ng(),
+            Self::xcas9(),
+            Self::spry(),
+            Self::mad7(),
+            Self::ascas12a(),
+            Self::lbcas12a(),
+            Self::enascas12a(),
+            Self::cas12b(),
+            Self::casx(),
+        ]
+    }
+
+    /// Returns the named built-in enzyme or, failing that, the enzyme defined in the file with
+    /// that name.
+    pub fn load(name_or_path: &str) -> Result<Enzyme> {
+        if let Some(enzyme) = Self::get(name_or_path) {
+            Ok(enzyme)
+        } else if Path::new(name_or_path).is_file() {
+            Self::from_file(&name_or_path)
+        } else {
+            Err(format!("Unknown enzyme {:?}", name_or_path).into())
         }
     }
 
-    pub fn cas9() -> Enzyme {
-        Enzyme {
-            name: "Cas9",
-            extension: ".crispyr_cas9",
+    /// Reads an enzyme definition from a file; see `Enzyme::parse` for the format.
+    pub fn from_file<P: AsRef<Path> + Debug>(filename: &P) -> Result<Enzyme> {
+        let mut text = String::new();
+        File::open(filename)
+            .and_then(|mut file| file.read_to_string(&mut text))
+            .chain_err(|| format!("failed to read enzyme definition {:?}", filename))?;
+
+        Self::parse(&text).chain_err(|| format!("invalid enzyme definition in {:?}", filename))
+    }
 
-            grna_len: 23,
+    /// Parses an enzyme definition consisting of `key = value` lines, with the keys `name`,
+    /// `pam` (IUPAC sequence, optionally a list of weighted PAMs; see `PAMSet::parse`),
+    /// `pam_position` (`head` for 5' PAMs, `tail` for 3' PAMs),
+    /// `grna_len` (including the PAM), `cutsite` (offset relative to the start of the PAM), and
+    /// optionally `cutsite_bottom` (cut-site on the opposite strand; defaults to `cutsite`).
+    /// Empty lines and lines starting with '#' are ignored.
+    pub fn parse(text: &str) -> Result<Enzyme> {
+        let mut name = None;
+        let mut pam = None;
+        let mut position = None;
+        let mut grna_len = None;
+        let mut cutsite = None;
+        let mut cutsite_bottom = None;
 
-            pam: PAM::tail(b"NGG"),
-            cutsite: -3,
+        for (linenum, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (key, value) = match line.find('=') {
+                Some(idx) => (line[..idx].trim(), line[idx + 1..].trim()),
+                None => {
+                    return Err(format!("expected 'key = value' at line {}", linenum + 1).into())
+                }
+            };
+
+            match key.to_ascii_lowercase().as_ref() {
+                "name" => name = Some(value.to_owned()),
+                "pam" => pam = Some(value.to_ascii_uppercase()),
+                "pam_position" => {
+                    position = match value.to_ascii_lowercase().as_ref() {
+                        "head" | "5'" => Some(Position::Head),
+                        "tail" | "3'" => Some(Position::Tail),
+                        _ => return Err(format!("invalid pam_position {:?}", value).into()),
+                    }
+                }
+                "grna_len" => match value.parse::<usize>() {
+                    Ok(value) => grna_len = Some(value),
+                    Err(err) => return Err(format!("invalid grna_len {:?}: {}", value, err).into()),
+                },
+                "cutsite" => match value.parse::<isize>() {
+                    Ok(value) => cutsite = Some(value),
+                    Err(err) => return Err(format!("invalid cutsite {:?}: {}", value, err).into()),
+                },
+                "cutsite_bottom" => match value.parse::<isize>() {
+                    Ok(value) => cutsite_bottom = Some(value),
+                    Err(err) => {
+                        return Err(format!("invalid cutsite_bottom {:?}: {}", value, err).into())
+                    }
+                },
+                _ => return Err(format!("unknown key {:?} at line {}", key, linenum + 1).into()),
+            }
         }
+
+        let name = name.ok_or("enzyme name not specified")?;
+        let pam = pam.ok_or("PAM sequence not specified")?;
+        let position = position.ok_or("PAM position not specified")?;
+        let grna_len = grna_len.ok_or("gRNA length not specified")?;
+        let cutsite = cutsite.ok_or("cut-site not specified")?;
+
+        let pams = PAMSet::parse(&pam, position)?;
+        let enzyme = Enzyme::new(&name, pams, grna_len, cutsite)
+            .with_bottom_cutsite(cutsite_bottom.unwrap_or(cutsite));
+        enzyme.validate()?;
+
+        Ok(enzyme)
     }
 
-    pub fn mad7() -> Enzyme {
-        Enzyme {
-            name: "Mad7",
-            extension: ".crispyr_mad7",
+    /// Checks that the enzyme can be represented in and used with a CRISPyR index.
+    pub fn validate(&self) -> Result<()> {
+        if self.name.is_empty() || self.name.len() > 255 {
+            return Err("enzyme name must be 1 to 255 characters long".into());
+        } else if !self
+            .name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+        {
+            return Err(format!("invalid characters in enzyme name {:?}", self.name).into());
+        }
 
-            grna_len: 25,
+        let pam_len = self.pams.len();
+        let kmer_len = self.kmer_len();
+        if self.pams.pams().len() > MAX_PAMS {
+            return Err(format!("at most {} PAMs may be specified", MAX_PAMS).into());
+        }
+
+        for (pam, weight) in self.pams.pams().iter().zip(self.pams.weights()) {
+            let seq = pam.to_string();
+            if seq.is_empty() || seq.len() > 255 {
+                return Err("PAM must be 1 to 255 nucleotides long".into());
+            } else if !seq.bytes().all(iupac::is_valid) {
+                return Err(format!("PAM {:?} is not a valid IUPAC sequence", seq).into());
+            } else if pam.len() != pam_len || pam.position() != self.pams.position() {
+                return Err("all PAMs must have the same length and position".into());
+            } else if pam.kmer_len() != kmer_len {
+                return Err("all PAMs must have the same K-mer length".into());
+            } else if !(*weight > 0.0 && *weight <= 1.0) {
+                return Err(format!("weight of PAM {:?} must be in the range (0, 1]", seq).into());
+            }
+        }
 
-            pam: PAM::head(b"YTTN"),
-            // Cut-site according to
-            // https://horizondiscovery.com/en/products/gene-editing/gene-editing-reagents/mad7
-            cutsite: 19,
+        if kmer_len == 0 || kmer_len > MAX_KMER_LEN {
+            return Err(format!("K-mer length must be in the range 1 to {}", MAX_KMER_LEN).into());
+        } else if self.grna_len < pam_len + kmer_len {
+            return Err(format!(
+                "gRNA length must be at least {} (PAM + {} bp K-mer)",
+                pam_len + kmer_len,
+                kmer_len
+            )
+            .into());
         }
+
+        Ok(())
+    }
+
+    pub fn cas9() -> Enzyme {
+        Enzyme::new("Cas9", PAM::tail(b"NGG"), 23, -3)
+    }
+
+    /// SpCas9 including the non-canonical NAG and NGA PAMs, weighted using the PAM scores from
+    /// Doench et al. 2016 (doi:10.1038/nbt.3437)
+    pub fn cas9_nc() -> Enzyme {
+        let pams = PAMSet::new(PAM::tail(b"NGG"))
+            .with_pam(PAM::tail(b"NAG"), 0.26)
+            .with_pam(PAM::tail(b"NGA"), 0.07);
+
+        Enzyme::new("Cas9-nc", pams, 23, -3)
+    }
+
+    /// Staphylococcus aureus Cas9
+    pub fn sacas9() -> Enzyme {
+        Enzyme::new("SaCas9", PAM::tail(b"NNGRRT"), 27, -3)
+    }
+
+    /// SpCas9 variant with relaxed PAM; Nishimasu et al. 2018
+    pub fn spcas9_ng() -> Enzyme {
+        Enzyme::new("SpCas9-NG", PAM::tail(b"NG"), 22, -3)
+    }
+
+    /// SpCas9 variant with relaxed PAM (NG, GAA, and GAT); Hu et al. 2018. PAMs must have the
+    /// same length, so NG is represented as NGN, and the PAMs are not weighted.
+    pub fn xcas9() -> Enzyme {
+        let pams = PAMSet::new(PAM::tail(b"NGN"))
+            .with_pam(PAM::tail(b"GAA"), 1.0)
+            .with_pam(PAM::tail(b"GAT"), 1.0);
+
+        Enzyme::new("xCas9", pams, 23, -3)
+    }
+
+    /// Near-PAMless SpCas9 variant (NRN and, less efficiently, NYN); Walton et al. 2020
+    pub fn spry() -> Enzyme {
+        let pams = PAMSet::new(PAM::tail(b"NRN")).with_pam(PAM::tail(b"NYN"), 0.5);
+
+        Enzyme::new("SpRY", pams, 23, -3)
+    }
+
+    pub fn mad7() -> Enzyme {
+        // Cut-site according to
+        // https://horizondiscovery.com/en/products/gene-editing/gene-editing-reagents/mad7
+        // The cut-site on the bottom strand is not documented, and defaults to the same position
+        Enzyme::new("Mad7", PAM::head(b"YTTN"), 25, 19)
+    }
+
+    /// Acidaminococcus sp. Cas12a (Cpf1); cuts after the 18th (top) and 23rd (bottom) nucleotide
+    /// of the protospacer
+    pub fn ascas12a() -> Enzyme {
+        Enzyme::new("AsCas12a", PAM::head(b"TTTV"), 27, 22).with_bottom_cutsite(27)
+    }
+
+    /// Lachnospiraceae bacterium Cas12a (Cpf1); cuts after the 18th (top) and 23rd (bottom)
+    /// nucleotide of the protospacer
+    pub fn lbcas12a() -> Enzyme {
+        Enzyme::new("LbCas12a", PAM::head(b"TTTV"), 27, 22).with_bottom_cutsite(27)
+    }
+
+    /// AsCas12a variant with relaxed PAM; Kleinstiver et al. 2019
+    pub fn enascas12a() -> Enzyme {
+        let pams = PAMSet::new(PAM::head(b"TTYN"))
+            .with_pam(PAM::head(b"VTTV"), 1.0)
+            .with_pam(PAM::head(b"TRTV"), 1.0);
+
+        Enzyme::new("enAsCas12a", pams, 27, 22).with_bottom_cutsite(27)
+    }
+
+    /// Alicyclobacillus acidoterrestris Cas12b (C2c1); cuts after the 17th (top) and 24th
+    /// (bottom) nucleotide of the protospacer
+    pub fn cas12b() -> Enzyme {
+        Enzyme::new("Cas12b", PAM::head(b"TTN"), 23, 20).with_bottom_cutsite(27)
+    }
+
+    /// Deltaproteobacteria CasX (Cas12e); cuts after the 12th (top) and 22nd (bottom) nucleotide
+    /// of the protospacer
+    pub fn casx() -> Enzyme {
+        Enzyme::new("CasX", PAM::head(b"TTCN"), 24, 16).with_bottom_cutsite(26)
     }
 }
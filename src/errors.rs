@@ -1,3 +1,6 @@
+// error_chain checks for a cfg that newer versions of rustc do not know about
+#![allow(unknown_lints, unexpected_cfgs)]
+
 error_chain! {
     types {
         Error, ErrorKind, ResultExt, Result;
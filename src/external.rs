@@ -0,0 +1,301 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use rayon::prelude::*;
+
+use crate::common::KMer;
+use crate::errors::*;
+use crate::index::{write_position, write_position_padding, Position};
+
+/// Size of a (K-mer, position) pair in memory
+pub const ENTRY_SIZE: usize = std::mem::size_of::<(KMer, Position)>();
+/// Maximum number of runs merged at once, limiting the number of open files
+const MAX_MERGED_RUNS: usize = 128;
+
+/// Positions of target sites collected in a bounded amount of memory. Positions are buffered
+/// until the buffer is full, at which point they are sorted and written to a temporary file (a
+/// run). The runs are merged when the positions are written to an index. Temporary files are
+/// named using the given prefix, and are removed once merged or when the table is dropped.
+pub struct ExternalPositions {
+    prefix: OsString,
+    capacity: usize,
+    pending: Vec<(KMer, Position)>,
+    runs: Vec<PathBuf>,
+    temp_files: usize,
+}
+
+impl ExternalPositions {
+    /// Creates a table buffering up to `memory` bytes of positions before they are written to
+    /// temporary files named using `prefix`.
+    pub fn new<P: AsRef<Path>>(prefix: P, memory: usize) -> ExternalPositions {
+        ExternalPositions {
+            prefix: prefix.as_ref().as_os_str().to_owned(),
+            capacity: usize::max(1, memory / ENTRY_SIZE),
+            pending: Vec::new(),
+            runs: Vec::new(),
+            temp_files: 0,
+        }
+    }
+
+    pub fn add(&mut self, position: Position, kmer: KMer) -> Result<()> {
+        self.pending.push((kmer, position));
+        if self.pending.len() >= self.capacity {
+            self.spill()?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the number of positions buffered before they are written to a temporary file
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the number of runs written to temporary files
+    pub fn runs(&self) -> usize {
+        self.runs.len()
+    }
+
+    /// Merges the collected positions and writes them in the format used by indexes: The number
+    /// of K-mers, the sorted K-mers, offsets into the array of positions for each K-mer, and the
+    /// positions. Returns the number of K-mers written.
+    pub fn write<W: Write>(mut self, writer: &mut W) -> Result<usize> {
+        self.spill()?;
+
+        while self.runs.len() > MAX_MERGED_RUNS {
+            let runs: Vec<_> = self.runs.drain(..MAX_MERGED_RUNS).collect();
+            let path = self.temp_path();
+            let mut run = BufWriter::new(File::create(&path).chain_err(|| "failed to create run")?);
+            for entry in RunMerger::open(&runs)? {
+                write_entry(&mut run, entry?)?;
+            }
+            run.flush().chain_err(|| "failed to write run")?;
+
+            self.runs.push(path);
+            remove_files(&runs)?;
+        }
+
+        // The index lists the K-mers and offsets before the positions, so the K-mers (and the
+        // number of positions per K-mer) and the positions are first merged into separate files
+        let kmers_path = self.temp_path();
+        let positions_path = self.temp_path();
+        let mut kmers =
+            BufWriter::new(File::create(&kmers_path).chain_err(|| "failed to create file")?);
+        let mut positions =
+            BufWriter::new(File::create(&positions_path).chain_err(|| "failed to create file")?);
+
+        let mut kmer_count = 0;
+        let mut last: Option<(KMer, u64)> = None;
+        for entry in RunMerger::open(&self.runs)? {
+            let (kmer, position) = entry?;
+            match &mut last {
+                Some((last_kmer, count)) if *last_kmer == kmer => *count += 1,
+                _ => {
+                    if let Some((last_kmer, count)) = last {
+                        write_kmer_count(&mut kmers, last_kmer, count)?;
+                    }
+
+                    kmer_count += 1;
+                    last = Some((kmer, 1));
+                }
+            }
+
+            write_position(&mut positions, position)?;
+        }
+
+        if let Some((kmer, count)) = last {
+            write_kmer_count(&mut kmers, kmer, count)?;
+        }
+
+        kmers.flush().chain_err(|| "failed to write K-mers")?;
+        positions
+            .flush()
+            .chain_err(|| "failed to write positions")?;
+        remove_files(&self.runs)?;
+        self.runs.clear();
+
+        writer
+            .write_u64::<LittleEndian>(kmer_count as u64)
+            .chain_err(|| "failed to write number of unique kmers")?;
+
+        let mut reader = open_temp(&kmers_path)?;
+        for _ in 0..kmer_count {
+            let (kmer, _) = read_kmer_count(&mut reader)?;
+            writer
+                .write_u64::<LittleEndian>(kmer.0)
+                .chain_err(|| "failed to write kmer")?;
+        }
+
+        let mut offset = 0;
+        let mut reader = open_temp(&kmers_path)?;
+        for _ in 0..kmer_count {
+            writer
+                .write_u64::<LittleEndian>(offset)
+                .chain_err(|| "failed to write kmer offset")?;
+            offset += read_kmer_count(&mut reader)?.1;
+        }
+        writer
+            .write_u64::<LittleEndian>(offset)
+            .chain_err(|| "failed to write kmer offset")?;
+
+        std::io::copy(&mut open_temp(&positions_path)?, writer)
+            .chain_err(|| "failed to write kmer positions")?;
+        write_position_padding(writer, offset as usize)?;
+
+        remove_files(&[kmers_path, positions_path])?;
+
+        Ok(kmer_count)
+    }
+
+    /// Sorts the buffered positions and writes them to a new run
+    fn spill(&mut self) -> Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+
+        self.pending
+            .par_sort_unstable_by_key(|(kmer, position)| (kmer.0, position.sort_key()));
+
+        let path = self.temp_path();
+        let file = File::create(&path).chain_err(|| format!("failed to create run {:?}", path))?;
+        let mut writer = BufWriter::new(file);
+        for entry in self.pending.drain(..) {
+            write_entry(&mut writer, entry)?;
+        }
+        writer.flush().chain_err(|| "failed to write run")?;
+        self.runs.push(path);
+
+        Ok(())
+    }
+
+    fn temp_path(&mut self) -> PathBuf {
+        let mut path = self.prefix.clone();
+        path.push(format!(".tmp.{}", self.temp_files));
+        self.temp_files += 1;
+
+        path.into()
+    }
+}
+
+impl Drop for ExternalPositions {
+    fn drop(&mut self) {
+        // Removes any temporary files left behind, e.g. if an error occurred
+        for idx in 0..self.temp_files {
+            let mut path = self.prefix.clone();
+            path.push(format!(".tmp.{}", idx));
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// The sort key (K-mer and position key) of an entry, the raw position, and the index of its run
+type HeapEntry = (u64, (u32, i64, bool, usize), (u32, u64), usize);
+
+/// Merges sorted runs, yielding (K-mer, position) pairs in sorted order
+struct RunMerger {
+    readers: Vec<BufReader<File>>,
+    heap: BinaryHeap<Reverse<HeapEntry>>,
+}
+
+impl RunMerger {
+    fn open(runs: &[PathBuf]) -> Result<RunMerger> {
+        let mut merger = RunMerger {
+            readers: Vec::with_capacity(runs.len()),
+            heap: BinaryHeap::with_capacity(runs.len()),
+        };
+
+        for (idx, path) in runs.iter().enumerate() {
+            merger.readers.push(open_temp(path)?);
+            merger.advance(idx)?;
+        }
+
+        Ok(merger)
+    }
+
+    /// Reads the next entry of a run into the heap, if any
+    fn advance(&mut self, idx: usize) -> Result<()> {
+        if let Some((kmer, position)) = read_entry(&mut self.readers[idx])? {
+            let key = (kmer.0, position.sort_key(), position.to_raw(), idx);
+            self.heap.push(Reverse(key));
+        }
+
+        Ok(())
+    }
+}
+
+impl Iterator for RunMerger {
+    type Item = Result<(KMer, Position)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Reverse((kmer, _, raw, idx)) = self.heap.pop()?;
+        if let Err(err) = self.advance(idx) {
+            return Some(Err(err));
+        }
+
+        Some(Ok((KMer::new(kmer), Position::from_raw(raw.0, raw.1))))
+    }
+}
+
+fn open_temp(path: &Path) -> Result<BufReader<File>> {
+    let file = File::open(path).chain_err(|| format!("failed to open {:?}", path))?;
+
+    Ok(BufReader::new(file))
+}
+
+fn remove_files(paths: &[PathBuf]) -> Result<()> {
+    for path in paths {
+        std::fs::remove_file(path).chain_err(|| format!("failed to remove {:?}", path))?;
+    }
+
+    Ok(())
+}
+
+fn write_entry<W: Write>(writer: &mut W, (kmer, position): (KMer, Position)) -> Result<()> {
+    writer
+        .write_u64::<LittleEndian>(kmer.0)
+        .chain_err(|| "failed to write K-mer")?;
+    write_position(writer, position)
+}
+
+fn read_entry<R: Read>(reader: &mut R) -> Result<Option<(KMer, Position)>> {
+    let kmer = match reader.read_u64::<LittleEndian>() {
+        Ok(kmer) => kmer,
+        Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err).chain_err(|| "failed to read K-mer"),
+    };
+
+    let refseq = reader
+        .read_u32::<LittleEndian>()
+        .chain_err(|| "failed to read position")?;
+    let value = reader
+        .read_u64::<LittleEndian>()
+        .chain_err(|| "failed to read position")?;
+
+    Ok(Some((KMer::new(kmer), Position::from_raw(refseq, value))))
+}
+
+fn write_kmer_count<W: Write>(writer: &mut W, kmer: KMer, count: u64) -> Result<()> {
+    writer
+        .write_u64::<LittleEndian>(kmer.0)
+        .chain_err(|| "failed to write K-mer")?;
+    writer
+        .write_u64::<LittleEndian>(count)
+        .chain_err(|| "failed to write number of positions")
+}
+
+fn read_kmer_count<R: Read>(reader: &mut R) -> Result<(KMer, u64)> {
+    let kmer = reader
+        .read_u64::<LittleEndian>()
+        .chain_err(|| "failed to read K-mer")?;
+    let count = reader
+        .read_u64::<LittleEndian>()
+        .chain_err(|| "failed to read number of positions")?;
+
+    Ok((KMer::new(kmer), count))
+}
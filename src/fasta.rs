@@ -0,0 +1,271 @@
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::{BufReader, SeekFrom};
+
+use bio::io::fasta::{Index, IndexedReader};
+use byteorder::{LittleEndian, ReadBytesExt};
+use flate2::bufread::MultiGzDecoder;
+use flate2::read::GzDecoder;
+
+use crate::errors::*;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+
+/// Opens a FASTA file for sequential reading. Gzip and bgzip compressed files are decompressed
+/// transparently.
+pub fn open(path: &str) -> Result<Box<dyn Read>> {
+    let file = File::open(path).chain_err(|| format!("failed to open FASTA file {:?}", path))?;
+    let mut reader = BufReader::new(file);
+    let magic = reader
+        .fill_buf()
+        .chain_err(|| format!("failed to read FASTA file {:?}", path))?;
+
+    if magic.starts_with(GZIP_MAGIC) {
+        Ok(Box::new(MultiGzDecoder::new(reader)))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+/// Opens a FASTA file for random access, using the FASTA index ('.fai') and, for bgzip
+/// compressed files, the bgzip index ('.gzi') produced by 'samtools faidx'.
+pub fn open_indexed(path: &str) -> Result<IndexedReader<FastaFile>> {
+    let index = Index::with_fasta_file(&path)
+        .chain_err(|| format!("failed to open FASTA index file for {:?}", path))?;
+
+    let mut file =
+        File::open(path).chain_err(|| format!("failed to open FASTA file {:?}", path))?;
+    let header = read_block_header(&mut file);
+    file.seek(SeekFrom::Start(0))
+        .chain_err(|| format!("failed to read FASTA file {:?}", path))?;
+
+    let file = match header {
+        Ok(Some(_)) => FastaFile::Bgzf(BgzfReader::new(file, &format!("{}.gzi", path))?),
+        Ok(None) => {
+            return Err(format!(
+                "{:?} is compressed using gzip; random access requires bgzip compression",
+                path
+            )
+            .into())
+        }
+        Err(_) => FastaFile::Plain(file),
+    };
+
+    Ok(IndexedReader::with_index(file, index))
+}
+
+/// An uncompressed or bgzip compressed FASTA file
+pub enum FastaFile {
+    Plain(File),
+    Bgzf(BgzfReader<File>),
+}
+
+impl Read for FastaFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            FastaFile::Plain(file) => file.read(buf),
+            FastaFile::Bgzf(file) => file.read(buf),
+        }
+    }
+}
+
+impl Seek for FastaFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            FastaFile::Plain(file) => file.seek(pos),
+            FastaFile::Bgzf(file) => file.seek(pos),
+        }
+    }
+}
+
+/// Reads the header of a gzip member and returns the total size of the member if it is a BGZF
+/// block, None if it is a regular gzip member, and an error if it is not gzip compressed. The
+/// header (including the extra field) is consumed.
+fn read_block_header<R: Read>(reader: &mut R) -> io::Result<Option<(Vec<u8>, usize)>> {
+    let mut header = vec![0; 12];
+    reader.read_exact(&mut header)?;
+    if !header.starts_with(GZIP_MAGIC) || header[2] != 8 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not gzip data"));
+    } else if header[3] & 4 == 0 {
+        // No extra field, and therefore no BGZF block size
+        return Ok(None);
+    }
+
+    let xlen = u16::from_le_bytes([header[10], header[11]]) as usize;
+    header.resize(12 + xlen, 0);
+    reader.read_exact(&mut header[12..])?;
+
+    let mut extra = &header[12..];
+    while extra.len() >= 4 {
+        let slen = u16::from_le_bytes([extra[2], extra[3]]) as usize;
+        if extra[..2] == b"BC"[..] && slen == 2 && extra.len() >= 6 {
+            let bsize = u16::from_le_bytes([extra[4], extra[5]]) as usize;
+
+            return Ok(Some((header, bsize + 1)));
+        }
+
+        extra = &extra[usize::min(extra.len(), 4 + slen)..];
+    }
+
+    Ok(None)
+}
+
+/// Seekable reader for bgzip compressed files, using the index of blocks ('.gzi') produced by
+/// 'bgzip -i' or 'samtools faidx'. Positions are given in uncompressed coordinates.
+pub struct BgzfReader<R: Read + Seek> {
+    reader: R,
+    /// Compressed and uncompressed offsets of blocks, sorted by offset
+    blocks: Vec<(u64, u64)>,
+    /// The current, decompressed block
+    block: Vec<u8>,
+    /// The uncompressed position of the start of the current block
+    block_start: u64,
+    /// The position in the current block
+    block_pos: usize,
+    /// The position in the uncompressed file
+    pos: u64,
+}
+
+impl<R: Read + Seek> BgzfReader<R> {
+    pub fn new(reader: R, gzi: &str) -> Result<BgzfReader<R>> {
+        let file = File::open(gzi).chain_err(|| {
+            format!(
+                "failed to open bgzip index {:?}; please run 'samtools faidx'",
+                gzi
+            )
+        })?;
+        let mut index = BufReader::new(file);
+
+        let count = index
+            .read_u64::<LittleEndian>()
+            .chain_err(|| "failed to read number of blocks in bgzip index")?;
+        let mut blocks = Vec::with_capacity(count as usize + 1);
+        blocks.push((0, 0));
+        for _ in 0..count {
+            let compressed = index
+                .read_u64::<LittleEndian>()
+                .chain_err(|| "failed to read compressed offset in bgzip index")?;
+            let uncompressed = index
+                .read_u64::<LittleEndian>()
+                .chain_err(|| "failed to read uncompressed offset in bgzip index")?;
+
+            blocks.push((compressed, uncompressed));
+        }
+
+        Ok(BgzfReader {
+            reader,
+            blocks,
+            block: Vec::new(),
+            block_start: 0,
+            block_pos: 0,
+            pos: 0,
+        })
+    }
+
+    /// Reads and decompresses the next block; returns false if the end of the file was reached.
+    fn read_block(&mut self) -> io::Result<bool> {
+        self.block_start += self.block.len() as u64;
+        self.block.clear();
+        self.block_pos = 0;
+
+        let (mut data, size) = match read_block_header(&mut self.reader) {
+            Ok(Some(value)) => value,
+            Ok(None) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "gzip member is not a BGZF block",
+                ))
+            }
+            Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(false),
+            Err(err) => return Err(err),
+        };
+
+        let header_len = data.len();
+        if size < header_len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid BGZF block size",
+            ));
+        }
+
+        data.resize(size, 0);
+        self.reader.read_exact(&mut data[header_len..])?;
+        GzDecoder::new(&data[..]).read_to_end(&mut self.block)?;
+
+        Ok(true)
+    }
+}
+
+impl<R: Read + Seek> Read for BgzfReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.block_pos >= self.block.len() {
+            if !self.read_block()? {
+                return Ok(0);
+            }
+        }
+
+        let len = usize::min(buf.len(), self.block.len() - self.block_pos);
+        buf[..len].copy_from_slice(&self.block[self.block_pos..self.block_pos + len]);
+        self.block_pos += len;
+        self.pos += len as u64;
+
+        Ok(len)
+    }
+}
+
+impl<R: Read + Seek> Seek for BgzfReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::Current(offset) if offset < 0 => self.pos.checked_sub(offset.unsigned_abs()),
+            SeekFrom::Current(offset) => self.pos.checked_add(offset as u64),
+            SeekFrom::End(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "seeking from the end of bgzip files is not supported",
+                ))
+            }
+        };
+
+        let pos = pos.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+
+        // Seeks within the current block do not require the block to be read again
+        if pos >= self.block_start && pos - self.block_start < self.block.len() as u64 {
+            self.block_pos = (pos - self.block_start) as usize;
+            self.pos = pos;
+
+            return Ok(pos);
+        }
+
+        let idx = match self.blocks.binary_search_by_key(&pos, |block| block.1) {
+            Ok(idx) => idx,
+            Err(idx) => idx - 1,
+        };
+
+        let (compressed, uncompressed) = self.blocks[idx];
+        self.reader.seek(SeekFrom::Start(compressed))?;
+        self.block.clear();
+        self.block_start = uncompressed;
+        self.block_pos = 0;
+
+        // Skip to the position, which should be located in the first block read
+        let mut offset = (pos - uncompressed) as usize;
+        while offset > 0 {
+            if !self.read_block()? {
+                break;
+            }
+
+            self.block_pos = usize::min(offset, self.block.len());
+            offset -= self.block_pos;
+        }
+
+        self.pos = pos - offset as u64;
+        Ok(self.pos)
+    }
+}
@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use bio::io::bed;
+use regex::Regex;
+
+use crate::errors::*;
+
+/// Restricts indexing to a subset of the sequences in a FASTA file, and optionally to regions of
+/// those sequences. A sequence is included if it is named by `include`, matches an
+/// `include_regex`, or if no such names or patterns are given, unless it is named by `exclude`
+/// or matches an `exclude_regex`. If regions are given, then only target sites located entirely
+/// within these regions are included, and sequences without regions are excluded.
+#[derive(Clone, Debug, Default)]
+pub struct SequenceFilter {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    pub include_regex: Vec<Regex>,
+    pub exclude_regex: Vec<Regex>,
+    /// The BED file from which regions were read, if any
+    pub bed: Option<String>,
+    /// The MD5 digest of the BED file, if known
+    pub bed_md5: Option<[u8; 16]>,
+    /// Regions (0-based, half-open) by sequence name, as in BED files. Only the path and digest
+    /// of the BED file are recorded in indexes, so regions are not available for filters read
+    /// from an index.
+    pub regions: HashMap<String, Vec<(u64, u64)>>,
+}
+
+impl SequenceFilter {
+    /// Compiles a regular expression used to match sequence names
+    pub fn regex(pattern: &str) -> Result<Regex> {
+        Regex::new(pattern).chain_err(|| format!("invalid regular expression {:?}", pattern))
+    }
+
+    /// Restricts the filter to the regions in a BED file
+    pub fn with_bed(mut self, filename: &str) -> Result<SequenceFilter> {
+        let data = std::fs::read(filename)
+            .chain_err(|| format!("failed to read BED file {:?}", filename))?;
+        let mut reader = bed::Reader::new(&data[..]);
+
+        self.regions.clear();
+        for record in reader.records() {
+            let record = record.chain_err(|| "failed to read BED record")?;
+            if record.start() >= record.end() {
+                return Err(format!(
+                    "invalid BED record {}:{}-{}; start must be less than end",
+                    record.chrom(),
+                    record.start(),
+                    record.end()
+                )
+                .into());
+            }
+
+            self.regions
+                .entry(record.chrom().to_owned())
+                .or_default()
+                .push((record.start(), record.end()));
+        }
+
+        self.bed = Some(filename.to_owned());
+        self.bed_md5 = Some(md5::compute(&data).0);
+
+        Ok(self)
+    }
+
+    /// Returns true if the filter does not exclude any sequences or regions
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty()
+            && self.exclude.is_empty()
+            && self.include_regex.is_empty()
+            && self.exclude_regex.is_empty()
+            && self.bed.is_none()
+    }
+
+    /// Returns true if a sequence is included by the filter
+    pub fn includes(&self, name: &str) -> bool {
+        let included = (self.include.is_empty() && self.include_regex.is_empty())
+            || self.include.iter().any(|v| v == name)
+            || self.include_regex.iter().any(|v| v.is_match(name));
+        let excluded = self.exclude.iter().any(|v| v == name)
+            || self.exclude_regex.iter().any(|v| v.is_match(name));
+        let has_regions = self.bed.is_none() || self.regions.contains_key(name);
+
+        included && !excluded && has_regions
+    }
+
+    /// Returns a mask of the bases outside the regions of a sequence, or None if the filter is
+    /// not restricted to regions.
+    pub fn mask(&self, name: &str, len: usize) -> Option<Vec<bool>> {
+        self.bed.as_ref()?;
+
+        let mut mask = vec![true; len];
+        for &(start, end) in self.regions.get(name).into_iter().flatten() {
+            let start = usize::min(len, start as usize);
+            let end = usize::min(len, end as usize);
+            for value in &mut mask[start..end] {
+                *value = false;
+            }
+        }
+
+        Some(mask)
+    }
+}
+
+/// Filters are compared by their BED files (path and digest), since regions are not recorded in
+/// indexes
+impl PartialEq for SequenceFilter {
+    fn eq(&self, other: &SequenceFilter) -> bool {
+        let patterns = |v: &[Regex]| v.iter().map(|v| v.as_str().to_owned()).collect::<Vec<_>>();
+
+        self.include == other.include
+            && self.exclude == other.exclude
+            && patterns(&self.include_regex) == patterns(&other.include_regex)
+            && patterns(&self.exclude_regex) == patterns(&other.exclude_regex)
+            && self.bed == other.bed
+            && self.bed_md5 == other.bed_md5
+    }
+}
+
+impl fmt::Display for SequenceFilter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts = Vec::new();
+        if !self.include.is_empty() {
+            parts.push(format!("including sequences {}", self.include.join(", ")));
+        }
+        for regex in &self.include_regex {
+            parts.push(format!("including sequences matching {:?}", regex.as_str()));
+        }
+        if !self.exclude.is_empty() {
+            parts.push(format!("excluding sequences {}", self.exclude.join(", ")));
+        }
+        for regex in &self.exclude_regex {
+            parts.push(format!("excluding sequences matching {:?}", regex.as_str()));
+        }
+        if let Some(bed) = &self.bed {
+            let mut part = if self.regions.is_empty() {
+                format!("restricted to regions in {:?}", bed)
+            } else {
+                let regions: usize = self.regions.values().map(|v| v.len()).sum();
+                let bp: u64 = self.regions.values().flatten().map(|(s, e)| e - s).sum();
+
+                format!(
+                    "restricted to {} regions in {} sequences ({} bp) in {:?}",
+                    regions,
+                    self.regions.len(),
+                    bp,
+                    bed
+                )
+            };
+
+            if let Some(md5) = self.bed_md5 {
+                part.push_str(&format!(" (MD5 {:x})", md5::Digest(md5)));
+            }
+
+            parts.push(part);
+        }
+
+        if parts.is_empty() {
+            write!(f, "all sequences")
+        } else {
+            write!(f, "{}", parts.join("; "))
+        }
+    }
+}
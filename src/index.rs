@@ -1,84 +1,148 @@
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
 use std::fmt::Debug;
 use std::fs::File;
 use std::io::prelude::*;
-use std::io::{BufReader, BufWriter};
+use std::io::BufWriter;
 use std::path::Path;
+use std::sync::Arc;
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use memmap::Mmap;
+use rayon::prelude::*;
 
 use crate::common::KMer;
 use crate::constants::*;
 use crate::enzyme::Enzyme;
 use crate::errors::*;
+use crate::external::ExternalPositions;
+use crate::filter::SequenceFilter;
 use crate::pam;
-use crate::progress;
+use crate::pam::{PAMSet, PAM};
+use crate::provenance::{Provenance, SequenceInfo, Source};
+use crate::twobit::{packed_len, TwoBitRecord, TwoBitSequences};
 
 const FORWARD_STRAND: u64 = 1 << 63;
 const REVERSE_STRAND: u64 = 0;
-
-#[derive(Debug, PartialEq, Clone)]
-pub struct Position {
-    refseq: u32,
-    pos: i32,
-    forward: bool,
-}
+const PAM_SHIFT: u64 = 60;
+const PAM_MASK: u64 = 0x7;
+const POS_BITS: u32 = 60;
+const POS_MASK: u64 = (1 << POS_BITS) - 1;
+// Layout of positions packed into a single u64 prior to `WIDE_POSITIONS_INDEX_VERSION`
+const PACKED_REFSEQ_SHIFT: u64 = 36;
+const PACKED_REFSEQ_MASK: u64 = 0xFF_FFFF;
+const PACKED_POS_BITS: u32 = 36;
+
+/// Position of a target site, consisting of the reference sequence, the position, the strand,
+/// and the PAM (index into the enzyme's list of PAMs). Positions are stored as three
+/// little-endian u32s: the 32 bit reference sequence, followed by a u64 containing the strand
+/// bit, the 3 bit PAM, and the 60 bit signed position. This allows positions to be read
+/// directly from memory-mapped index files.
+#[derive(PartialEq, Clone, Copy)]
+#[repr(transparent)]
+pub struct Position([u32; 3]);
 
 impl Position {
-    pub fn from_u64(raw: u64) -> Position {
-        let forward = raw & FORWARD_STRAND != 0;
-        let refseq = ((raw >> 32) & 0x7FFF_FFFF) as u32;
-        let pos = (raw & 0xFFFF_FFFF) as i32;
-
-        Position {
-            refseq,
-            pos,
-            forward,
+    /// Returns a new position, or an error if the PAM or the position cannot be represented
+    pub fn new(refseq: u32, pos: i64, forward: bool, pam: usize) -> Result<Position> {
+        if pam as u64 > PAM_MASK {
+            return Err(format!("cannot represent PAM {} using 3 bits", pam).into());
+        } else if !Self::valid_pos(pos) {
+            return Err(
+                format!("cannot represent position {} using {} bits", pos, POS_BITS).into(),
+            );
         }
-    }
-
-    pub fn to_u64(&self) -> u64 {
-        assert!(
-            self.refseq & 0x8000_0000 == 0,
-            "cannot represent refseq using 31 bits"
-        );
 
-        let strand = if self.forward {
+        let strand = if forward {
             FORWARD_STRAND
         } else {
             REVERSE_STRAND
         };
-        let refseq = u64::from(self.refseq) << 32;
-        let pos = u64::from(self.pos as u32);
+        let pam = (pam as u64) << PAM_SHIFT;
 
-        strand | refseq | pos
+        Ok(Position::from_raw(
+            refseq,
+            strand | pam | (pos as u64 & POS_MASK),
+        ))
     }
 
-    pub fn forward(refseq: u32, pos: i32) -> Position {
-        Position {
-            refseq,
-            pos,
-            forward: true,
-        }
+    /// Returns true if the (signed) position can be represented using 60 bits
+    fn valid_pos(pos: i64) -> bool {
+        (-(1 << (POS_BITS - 1))..(1 << (POS_BITS - 1))).contains(&pos)
     }
 
-    pub fn reverse(refseq: u32, pos: i32) -> Position {
-        Position {
-            refseq,
-            pos,
-            forward: false,
-        }
+    /// Returns the position with the given reference sequence and u64 containing the strand,
+    /// PAM, and position
+    pub fn from_raw(refseq: u32, value: u64) -> Position {
+        Position([
+            refseq.to_le(),
+            (value as u32).to_le(),
+            ((value >> 32) as u32).to_le(),
+        ])
+    }
+
+    /// Returns the reference sequence and the u64 containing the strand, PAM, and position
+    pub fn to_raw(self) -> (u32, u64) {
+        let [refseq, low, high] = self.0;
+        let value = u64::from(u32::from_le(low)) | (u64::from(u32::from_le(high)) << 32);
+
+        (u32::from_le(refseq), value)
+    }
+
+    /// Converts a position packed into a single u64 (strand bit, 3 bit PAM, 24 bit reference
+    /// sequence, and 36 bit signed position) prior to `WIDE_POSITIONS_INDEX_VERSION`
+    fn from_packed(packed: u64) -> Result<Position> {
+        let refseq = ((packed >> PACKED_REFSEQ_SHIFT) & PACKED_REFSEQ_MASK) as u32;
+        // Sign-extends the 36 bit position
+        let pos = ((packed << (64 - PACKED_POS_BITS)) as i64) >> (64 - PACKED_POS_BITS);
+        let pam = ((packed >> PAM_SHIFT) & PAM_MASK) as usize;
+
+        Position::new(refseq, pos, packed & FORWARD_STRAND != 0, pam)
+    }
+
+    pub fn forward(refseq: u32, pos: i64) -> Result<Position> {
+        Position::new(refseq, pos, true, 0)
+    }
+
+    pub fn reverse(refseq: u32, pos: i64) -> Result<Position> {
+        Position::new(refseq, pos, false, 0)
+    }
+
+    /// Returns a copy of the position for a site with the given PAM (index into the enzyme's
+    /// list of PAMs).
+    pub fn with_pam(self, pam: usize) -> Result<Position> {
+        Position::new(self.refseq(), self.pos(), self.is_forward(), pam)
     }
 
     pub fn refseq(&self) -> u32 {
-        self.refseq
+        self.to_raw().0
+    }
+
+    pub fn pos(&self) -> i64 {
+        // Sign-extends the 60 bit position
+        ((self.to_raw().1 << (64 - POS_BITS)) as i64) >> (64 - POS_BITS)
     }
 
-    pub fn pos(&self) -> i32 {
-        self.pos
+    pub fn pam(&self) -> usize {
+        ((self.to_raw().1 >> PAM_SHIFT) & PAM_MASK) as usize
+    }
+
+    /// Returns a copy of the position on the given reference sequence
+    pub fn with_refseq(self, refseq: u32) -> Position {
+        Position::from_raw(refseq, self.to_raw().1)
+    }
+
+    /// Returns the key by which positions are sorted (sequence, position, strand, and PAM)
+    pub(crate) fn sort_key(self) -> (u32, i64, bool, usize) {
+        (self.refseq(), self.pos(), !self.is_forward(), self.pam())
+    }
+
+    fn is_forward(&self) -> bool {
+        self.to_raw().1 & FORWARD_STRAND != 0
     }
 
     pub fn strand(&self) -> char {
-        if self.forward {
+        if self.is_forward() {
             '+'
         } else {
             '-'
@@ -86,243 +150,1411 @@ impl Position {
     }
 }
 
+impl Debug for Position {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Position")
+            .field("refseq", &self.refseq())
+            .field("pos", &self.pos())
+            .field("strand", &self.strand())
+            .field("pam", &self.pam())
+            .finish()
+    }
+}
+
+/// Table of values indexed by K-mer. Dense tables (4^K entries) are used for short K-mers, while
+/// sparse tables are used for K-mers for which a dense table would be infeasibly large. Default
+/// values (zero counts or empty lists of positions) are treated as missing values.
+#[derive(Clone, Debug)]
+pub enum KMerTable<T> {
+    Dense(Vec<T>),
+    Sparse(HashMap<KMer, T>),
+}
+
+impl<T: Clone + Default + PartialEq> KMerTable<T> {
+    pub fn new(kmer_len: usize) -> KMerTable<T> {
+        if kmer_len <= MAX_DENSE_KMER_LEN {
+            KMerTable::Dense(vec![T::default(); 1 << (2 * kmer_len)])
+        } else {
+            KMerTable::Sparse(HashMap::new())
+        }
+    }
+
+    pub fn get(&self, kmer: KMer) -> Option<&T> {
+        let value = match self {
+            KMerTable::Dense(table) => table.get(kmer.key()),
+            KMerTable::Sparse(table) => table.get(&kmer),
+        };
+
+        value.filter(|v| **v != T::default())
+    }
+
+    pub fn get_mut(&mut self, kmer: KMer) -> &mut T {
+        match self {
+            KMerTable::Dense(table) => &mut table[kmer.key()],
+            KMerTable::Sparse(table) => table.entry(kmer).or_default(),
+        }
+    }
+
+    pub fn contains(&self, kmer: KMer) -> bool {
+        self.get(kmer).is_some()
+    }
+
+    /// Returns the number of K-mers with non-default values.
+    pub fn len(&self) -> usize {
+        match self {
+            KMerTable::Dense(table) => table.iter().filter(|v| **v != T::default()).count(),
+            KMerTable::Sparse(table) => table.values().filter(|v| **v != T::default()).count(),
+        }
+    }
+
+    /// Returns true if no K-mers have non-default values.
+    pub fn is_empty(&self) -> bool {
+        match self {
+            KMerTable::Dense(table) => table.iter().all(|v| *v == T::default()),
+            KMerTable::Sparse(table) => table.values().all(|v| *v == T::default()),
+        }
+    }
+
+    /// Returns the K-mers with non-default values, sorted by K-mer.
+    pub fn iter(&self) -> Vec<(KMer, &T)> {
+        let mut values: Vec<_> = match self {
+            KMerTable::Dense(table) => table
+                .iter()
+                .enumerate()
+                .map(|(kmer, value)| (KMer::new(kmer as u64), value))
+                .filter(|(_, value)| **value != T::default())
+                .collect(),
+            KMerTable::Sparse(table) => table
+                .iter()
+                .map(|(kmer, value)| (*kmer, value))
+                .filter(|(_, value)| **value != T::default())
+                .collect(),
+        };
+
+        values.sort_by_key(|(kmer, _)| kmer.0);
+        values
+    }
+}
+
+/// Positions of target sites stored in compressed sparse row (CSR) format: Sorted K-mers, offsets
+/// into a flat array of positions for each K-mer, and positions sorted by reference sequence and
+/// position. Positions are collected using `add` and are stored in the table by `finish`.
+#[derive(Clone, Debug)]
+pub struct PositionTable {
+    kmers: Vec<KMer>,
+    offsets: Vec<u64>,
+    positions: Vec<Position>,
+    pending: Vec<(KMer, Position)>,
+}
+
+impl PositionTable {
+    pub fn new() -> PositionTable {
+        PositionTable {
+            kmers: Vec::new(),
+            offsets: vec![0],
+            positions: Vec::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, position: Position, kmer: KMer) {
+        self.pending.push((kmer, position));
+    }
+
+    /// Merges positions collected using `add` into the table.
+    pub fn finish(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+
+        let mut entries = std::mem::take(&mut self.pending);
+        entries.reserve(self.positions.len());
+        for (kmer, positions) in self.iter() {
+            entries.extend(positions.iter().map(|position| (kmer, *position)));
+        }
+
+        entries.par_sort_unstable_by_key(|(kmer, position)| (kmer.0, position.sort_key()));
+
+        self.kmers.clear();
+        self.offsets.clear();
+        self.positions.clear();
+        self.positions.reserve_exact(entries.len());
+        for (kmer, position) in entries {
+            if self.kmers.last() != Some(&kmer) {
+                self.kmers.push(kmer);
+                self.offsets.push(self.positions.len() as u64);
+            }
+
+            self.positions.push(position);
+        }
+
+        self.offsets.push(self.positions.len() as u64);
+    }
+
+    /// Returns the positions of a K-mer; positions not yet merged using `finish` are ignored.
+    pub fn get(&self, kmer: KMer) -> Option<&[Position]> {
+        let idx = self.kmers.binary_search_by_key(&kmer.0, |v| v.0).ok()?;
+
+        Some(&self.positions[self.offsets[idx] as usize..self.offsets[idx + 1] as usize])
+    }
+
+    /// Returns the number of K-mers in the table
+    pub fn len(&self) -> usize {
+        self.kmers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.kmers.is_empty()
+    }
+
+    /// Returns the K-mers and their positions, sorted by K-mer.
+    pub fn iter(&self) -> impl Iterator<Item = (KMer, &[Position])> {
+        self.kmers
+            .iter()
+            .zip(self.offsets.windows(2))
+            .map(move |(kmer, offsets)| {
+                (
+                    *kmer,
+                    &self.positions[offsets[0] as usize..offsets[1] as usize],
+                )
+            })
+    }
+}
+
+impl Default for PositionTable {
+    fn default() -> PositionTable {
+        PositionTable::new()
+    }
+}
+
+/// Map of K-mers to counts or positions. Counts are recorded in a table per PAM, while positions
+/// record the PAM.
 pub enum KMerMap {
-    Counts(Vec<u32>),
-    Positions(Vec<Vec<Position>>),
+    Counts(Vec<KMerTable<u32>>),
+    Positions(PositionTable),
+}
+
+impl KMerMap {
+    pub fn new(pams: usize, kmer_len: usize, positions: bool) -> KMerMap {
+        if positions {
+            KMerMap::Positions(PositionTable::new())
+        } else {
+            KMerMap::Counts(vec![KMerTable::new(kmer_len); pams])
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            // Fast path for the common case of enzymes with a single PAM
+            KMerMap::Counts(tables) if tables.len() == 1 => tables[0].len(),
+            KMerMap::Counts(tables) => {
+                let mut count = 0;
+                for (idx, table) in tables.iter().enumerate() {
+                    count += table
+                        .iter()
+                        .into_iter()
+                        .filter(|(kmer, _)| !tables[..idx].iter().any(|t| t.contains(*kmer)))
+                        .count();
+                }
+
+                count
+            }
+            KMerMap::Positions(table) => table.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        match self {
+            KMerMap::Counts(tables) => tables.iter().all(KMerTable::is_empty),
+            KMerMap::Positions(table) => table.is_empty(),
+        }
+    }
+
+    pub fn add(&mut self, position: Position, kmer: KMer) {
+        match self {
+            KMerMap::Counts(tables) => {
+                *tables[position.pam()].get_mut(kmer) += 1;
+            }
+            KMerMap::Positions(table) => {
+                table.add(position, kmer);
+            }
+        }
+    }
+
+    /// Finalizes the map once all K-mers have been added
+    pub fn finish(&mut self) {
+        if let KMerMap::Positions(table) = self {
+            table.finish();
+        }
+    }
+}
+
+/// K-mers stored in a memory-mapped index file. The sorted K-mers are followed by either a table
+/// of counts per PAM, or by a table of offsets into a flat array of positions. Values are read
+/// directly from the mapped file, so that only the pages containing the K-mers looked up are read
+/// from disk, and so that these pages are shared between processes using the same index. The
+/// mapping is shared between the sections of an index.
+struct MappedKMers {
+    mmap: Arc<Mmap>,
+    kmer_count: usize,
+    /// Byte offset of sorted K-mers (u64 x kmer_count)
+    kmers: usize,
+    values: MappedValues,
 }
 
-impl KMerMap {
-    pub fn len(&self) -> usize {
-        match self {
-            KMerMap::Counts(map) => map.iter().filter(|v| **v > 0).count(),
-            KMerMap::Positions(map) => map.iter().filter(|v| !v.is_empty()).count(),
-        }
+enum MappedValues {
+    /// Byte offset of counts (u32 x kmer_count) for each PAM
+    Counts(Vec<usize>),
+    /// Byte offsets of offsets (u64 x kmer_count + 1) and of positions (3 x u32 x
+    /// offsets[kmer_count], u64 prior to `WIDE_POSITIONS_INDEX_VERSION`, and 2 x u64 prior to
+    /// `PACKED_POSITIONS_INDEX_VERSION`), and the total number of positions (offsets[kmer_count])
+    Positions(usize, usize, u64),
+}
+
+impl MappedKMers {
+    fn map(
+        mmap: Arc<Mmap>,
+        offset: usize,
+        enzyme: &Enzyme,
+        positions: bool,
+        position_size: usize,
+    ) -> Result<MappedKMers> {
+        let (kmer_count, values, _) =
+            Self::layout(&mmap, offset, enzyme, positions, position_size)?;
+
+        let mapped = MappedKMers {
+            mmap,
+            kmer_count,
+            kmers: offset + 8,
+            values,
+        };
+        mapped.validate_offsets()?;
+
+        Ok(mapped)
+    }
+
+    /// Checks that the offsets of the positions of each K-mer are increasing, and therefore
+    /// bounded by the total number of positions (the last offset), so that looking up positions
+    /// cannot fail. This reads the table of offsets once, which is cheap compared to scoring.
+    fn validate_offsets(&self) -> Result<()> {
+        let offsets = self.offsets();
+        if offsets
+            .windows(2)
+            .any(|v| u64::from_le(v[0]) > u64::from_le(v[1]))
+        {
+            return Err("index file is corrupt: invalid K-mer offsets".into());
+        }
+
+        Ok(())
+    }
+
+    /// Determines the layout of the K-mers starting at the given byte offset, returning the
+    /// number of K-mers, the byte offsets of their values, and the byte offset following them.
+    /// Positions take up `position_size` bytes each.
+    fn layout(
+        data: &[u8],
+        offset: usize,
+        enzyme: &Enzyme,
+        positions: bool,
+        position_size: usize,
+    ) -> Result<(usize, MappedValues, usize)> {
+        let mut reader = data.get(offset..).ok_or("index file is truncated")?;
+        let kmer_count = reader
+            .read_u64::<LittleEndian>()
+            .chain_err(|| "failed to read number of unique K-mers")?
+            as usize;
+
+        let truncated = || Error::from("index file is truncated or corrupt");
+        let kmers = offset + 8;
+        let kmer_bytes = kmer_count.checked_mul(8).ok_or_else(truncated)?;
+        let mut end = kmers.checked_add(kmer_bytes).ok_or_else(truncated)?;
+        let values = if positions {
+            let offsets = end;
+            end = end
+                .checked_add(kmer_bytes)
+                .and_then(|v| v.checked_add(8))
+                .ok_or_else(truncated)?;
+
+            // The last offset is the total number of positions
+            let mut reader = data.get(end - 8..end).ok_or_else(truncated)?;
+            let position_count = reader
+                .read_u64::<LittleEndian>()
+                .chain_err(|| "failed to read number of positions")?;
+
+            let positions = end;
+            end = usize::try_from(position_count)
+                .ok()
+                .and_then(|v| v.checked_mul(position_size))
+                .and_then(|v| v.checked_add(positions))
+                .ok_or_else(truncated)?;
+
+            MappedValues::Positions(offsets, positions, position_count)
+        } else {
+            let count_bytes = kmer_count.checked_mul(4).ok_or_else(truncated)?;
+            let mut counts = Vec::new();
+            for _ in enzyme.pams.pams() {
+                counts.push(end);
+                end = end.checked_add(align(count_bytes)).ok_or_else(truncated)?;
+            }
+
+            MappedValues::Counts(counts)
+        };
+
+        if end > data.len() {
+            return Err(truncated());
+        }
+
+        Ok((kmer_count, values, end))
+    }
+
+    /// Returns a slice of `len` values of type T starting at the given byte offset
+    fn slice<T>(&self, offset: usize, len: usize) -> &[T] {
+        assert_eq!(offset % std::mem::align_of::<T>(), 0);
+        assert!(offset + len * std::mem::size_of::<T>() <= self.mmap.len());
+
+        // Safety: The range is checked above and the map is page-aligned, while the types used
+        // (u32, u64, and Position) are valid for any bit-pattern.
+        unsafe { std::slice::from_raw_parts(self.mmap.as_ptr().add(offset) as *const T, len) }
+    }
+
+    fn offsets(&self) -> &[u64] {
+        match self.values {
+            MappedValues::Counts(_) => &[],
+            MappedValues::Positions(offsets, ..) => self.slice(offsets, self.kmer_count + 1),
+        }
+    }
+
+    fn kmers(&self) -> &[u64] {
+        self.slice(self.kmers, self.kmer_count)
+    }
+
+    fn find(&self, kmer: KMer) -> Option<usize> {
+        self.kmers()
+            .binary_search_by_key(&kmer.0, |v| u64::from_le(*v))
+            .ok()
+    }
+
+    fn get_count(&self, pam: usize, kmer: KMer) -> u32 {
+        match (&self.values, self.find(kmer)) {
+            (MappedValues::Counts(counts), Some(idx)) => {
+                let counts: &[u32] = self.slice(counts[pam], self.kmer_count);
+
+                u32::from_le(counts[idx])
+            }
+            (MappedValues::Positions(..), Some(_)) => self
+                .get_positions(kmer)
+                .iter()
+                .filter(|v| v.pam() == pam)
+                .count() as u32,
+            (_, None) => 0,
+        }
+    }
+
+    fn get_positions(&self, kmer: KMer) -> &[Position] {
+        match (&self.values, self.find(kmer)) {
+            (MappedValues::Positions(_, positions, _), Some(idx)) => {
+                // Offsets are validated when the index is mapped
+                let offsets = self.offsets();
+                let start = u64::from_le(offsets[idx]) as usize;
+                let end = u64::from_le(offsets[idx + 1]) as usize;
+
+                &self.slice(*positions, end)[start..]
+            }
+            _ => &[],
+        }
+    }
+}
+
+impl MappedKMers {
+    /// Reads positions stored using the layout of a previous version of the index format into
+    /// memory, since these cannot be used directly. Positions consist of two u64s (the strand,
+    /// PAM, and reference sequence, and the signed position) prior to
+    /// `PACKED_POSITIONS_INDEX_VERSION`, and of a single u64 (see `Position::from_packed`) prior
+    /// to `WIDE_POSITIONS_INDEX_VERSION`.
+    fn read_previous_positions(&self, version: u8) -> Result<KMerMap> {
+        let mut table = PositionTable::new();
+        let offsets = self.offsets();
+        for (idx, kmer) in self.kmers().iter().enumerate() {
+            let start = u64::from_le(offsets[idx]) as usize;
+            let end = u64::from_le(offsets[idx + 1]) as usize;
+            let kmer = KMer::new(u64::from_le(*kmer));
+
+            if version < PACKED_POSITIONS_INDEX_VERSION {
+                for value in self.previous_positions::<[u64; 2]>(start..end)? {
+                    let (first, pos) = (u64::from_le(value[0]), u64::from_le(value[1]) as i64);
+                    let refseq = (first & 0xFFFF_FFFF) as u32;
+                    let forward = first & FORWARD_STRAND != 0;
+                    let pam = ((first >> PAM_SHIFT) & PAM_MASK) as usize;
+
+                    table.add(Position::new(refseq, pos, forward, pam)?, kmer);
+                }
+            } else {
+                for value in self.previous_positions::<u64>(start..end)? {
+                    table.add(Position::from_packed(u64::from_le(*value))?, kmer);
+                }
+            }
+        }
+
+        table.finish();
+        Ok(KMerMap::Positions(table))
+    }
+
+    /// Returns the given range of positions stored using a previous layout (see
+    /// `read_previous_positions`)
+    fn previous_positions<T>(&self, range: std::ops::Range<usize>) -> Result<&[T]> {
+        let positions: &[T] = match self.values {
+            MappedValues::Positions(_, positions, count) => self.slice(positions, count as usize),
+            MappedValues::Counts(_) => return Err("index does not contain positions".into()),
+        };
+
+        positions
+            .get(range)
+            .ok_or_else(|| "index file is corrupt".into())
+    }
+}
+
+/// Rounds a number of bytes up to a multiple of 8, the alignment of values in index files
+fn align(len: usize) -> usize {
+    (len + 7) & !7
+}
+
+/// Writes a position using the layout of `Position` (see `Position::to_raw`)
+pub(crate) fn write_position<W: Write>(writer: &mut W, position: Position) -> Result<()> {
+    let (refseq, value) = position.to_raw();
+    writer
+        .write_u32::<LittleEndian>(refseq)
+        .chain_err(|| "failed to write kmer position")?;
+    writer
+        .write_u64::<LittleEndian>(value)
+        .chain_err(|| "failed to write kmer position")
+}
+
+/// Pads a table of `count` positions to a multiple of 8 bytes, so that sections stay aligned
+pub(crate) fn write_position_padding<W: Write>(writer: &mut W, count: usize) -> Result<()> {
+    let len = count * std::mem::size_of::<Position>();
+
+    writer
+        .write_all(&vec![0; align(len) - len])
+        .chain_err(|| "failed to write padding")
+}
+
+enum KMerStore {
+    Memory(KMerMap),
+    Mapped(MappedKMers),
+}
+
+/// Block of packed sequences embedded in an index
+#[derive(Clone)]
+enum SequenceData {
+    Memory(Vec<u8>),
+    /// Byte offset and length of the block in the memory-mapped index file
+    Mapped(usize, usize),
+}
+
+/// Sequences embedded in an index, one for each reference sequence
+#[derive(Clone)]
+struct EmbeddedSequences {
+    records: Vec<TwoBitRecord>,
+    data: SequenceData,
+}
+
+pub struct KMerIndex {
+    version: u8,
+    enzyme: Enzyme,
+    refseqs: Vec<String>,
+    provenance: Provenance,
+    masked_excluded: bool,
+    sequences: Option<EmbeddedSequences>,
+    kmers: KMerStore,
+}
+
+impl KMerIndex {
+    pub fn new(enzyme: &Enzyme, refseqs: Vec<String>, mut kmers: KMerMap) -> KMerIndex {
+        kmers.finish();
+
+        KMerIndex {
+            version: INDEX_VERSION,
+            enzyme: enzyme.clone(),
+            refseqs,
+            provenance: Provenance::default(),
+            masked_excluded: false,
+            sequences: None,
+            kmers: KMerStore::Memory(kmers),
+        }
+    }
+
+    /// Sets the provenance of the index; sequence information must be missing or available for
+    /// every sequence in the index.
+    pub fn with_provenance(mut self, provenance: Provenance) -> Result<KMerIndex> {
+        if !provenance.sequences.is_empty() && provenance.sequences.len() != self.refseqs.len() {
+            return Err(format!(
+                "provenance has information for {} sequences, but index contains {}",
+                provenance.sequences.len(),
+                self.refseqs.len()
+            )
+            .into());
+        }
+
+        self.provenance = provenance;
+        Ok(self)
+    }
+
+    pub fn provenance(&self) -> &Provenance {
+        &self.provenance
+    }
+
+    /// Marks the index as excluding target sites that overlap soft-masked sequence
+    pub fn with_masked_excluded(mut self, excluded: bool) -> KMerIndex {
+        self.masked_excluded = excluded;
+        self
+    }
+
+    /// Returns true if target sites overlapping soft-masked sequence were excluded
+    pub fn masked_excluded(&self) -> bool {
+        self.masked_excluded
+    }
+
+    /// Embeds the (packed) reference sequences in the index; a sequence must be provided for
+    /// every sequence in the index.
+    pub fn with_sequences(mut self, sequences: TwoBitSequences) -> KMerIndex {
+        assert_eq!(sequences.records.len(), self.refseqs.len());
+
+        self.sequences = Some(EmbeddedSequences {
+            records: sequences.records,
+            data: SequenceData::Memory(sequences.data),
+        });
+        self
+    }
+
+    /// Returns true if the reference sequences are embedded in the index
+    pub fn has_sequences(&self) -> bool {
+        self.sequences.is_some()
+    }
+
+    /// Returns the records and block of packed bases of the embedded sequences, if any
+    fn sequence_data(&self) -> Option<(&[TwoBitRecord], &[u8])> {
+        let sequences = self.sequences.as_ref()?;
+        let data = match (&sequences.data, &self.kmers) {
+            (SequenceData::Memory(data), _) => &data[..],
+            (SequenceData::Mapped(offset, len), KMerStore::Mapped(kmers)) => {
+                &kmers.mmap[*offset..*offset + *len]
+            }
+            (SequenceData::Mapped(..), KMerStore::Memory(_)) => unreachable!(),
+        };
+
+        Some((&sequences.records, data))
+    }
+
+    /// Returns the length of an embedded reference sequence
+    pub fn sequence_len(&self, refseq: usize) -> Option<u64> {
+        let (records, _) = self.sequence_data()?;
+
+        Some(records[refseq].len)
+    }
+
+    /// Returns the bases from `start` to `end` (0-based, exclusive) of an embedded reference
+    /// sequence, or None if sequences are not embedded in the index.
+    pub fn fetch_sequence(&self, refseq: usize, start: u64, end: u64) -> Option<Vec<u8>> {
+        let (records, data) = self.sequence_data()?;
+
+        Some(records[refseq].fetch(data, start, end))
+    }
+
+    /// Returns the version of the index file format
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    pub fn refseqs(&self) -> &[String] {
+        &self.refseqs
+    }
+
+    pub fn enzyme(&self) -> &Enzyme {
+        &self.enzyme
+    }
+
+    pub fn kmer_count(&self) -> usize {
+        match &self.kmers {
+            KMerStore::Memory(kmers) => kmers.len(),
+            KMerStore::Mapped(kmers) => kmers.kmer_count,
+        }
+    }
+
+    /// Returns the number of sites with the given K-mer and PAM (index into the enzyme's list of
+    /// PAMs).
+    pub fn get_count(&self, pam: usize, kmer: KMer) -> Option<u32> {
+        let count = match &self.kmers {
+            KMerStore::Memory(KMerMap::Counts(tables)) => {
+                tables[pam].get(kmer).copied().unwrap_or(0)
+            }
+            KMerStore::Memory(KMerMap::Positions(table)) => match table.get(kmer) {
+                Some(positions) => positions.iter().filter(|v| v.pam() == pam).count() as u32,
+                None => 0,
+            },
+            KMerStore::Mapped(kmers) => kmers.get_count(pam, kmer),
+        };
+
+        if count > 0 {
+            Some(count)
+        } else {
+            None
+        }
+    }
+
+    pub fn has_positions(&self) -> bool {
+        match &self.kmers {
+            KMerStore::Memory(KMerMap::Counts(_)) => false,
+            KMerStore::Memory(KMerMap::Positions(_)) => true,
+            KMerStore::Mapped(kmers) => match kmers.values {
+                MappedValues::Counts(_) => false,
+                MappedValues::Positions(..) => true,
+            },
+        }
+    }
+
+    pub fn get_positions(&self, kmer: KMer) -> Option<&[Position]> {
+        match &self.kmers {
+            KMerStore::Memory(KMerMap::Counts(_)) => None,
+            KMerStore::Memory(KMerMap::Positions(table)) => table.get(kmer),
+            KMerStore::Mapped(kmers) => Some(kmers.get_positions(kmer)).filter(|v| !v.is_empty()),
+        }
+    }
+
+    /// Returns the K-mers in the index, sorted by K-mer.
+    pub fn iter_kmers(&self) -> Box<dyn Iterator<Item = KMer> + '_> {
+        match &self.kmers {
+            KMerStore::Memory(KMerMap::Counts(tables)) => {
+                Box::new(Self::collect_kmers(tables).into_iter())
+            }
+            KMerStore::Memory(KMerMap::Positions(table)) => {
+                Box::new(table.iter().map(|(kmer, _)| kmer))
+            }
+            KMerStore::Mapped(kmers) => Box::new(
+                kmers
+                    .kmers()
+                    .iter()
+                    .map(|kmer| KMer::new(u64::from_le(*kmer))),
+            ),
+        }
+    }
+
+    /// Returns the sorted, unique K-mers in a set of count tables.
+    fn collect_kmers(tables: &[KMerTable<u32>]) -> Vec<KMer> {
+        let mut kmers: Vec<KMer> = tables
+            .iter()
+            .flat_map(|table| table.iter().into_iter().map(|(kmer, _)| kmer))
+            .collect();
+        kmers.sort_unstable_by_key(|kmer| kmer.0);
+        kmers.dedup();
+        kmers
+    }
+
+    /// Opens a K-mer index. The K-mers are memory-mapped rather than read into memory, so this
+    /// does not depend on the size of the index. Indexes containing sections for multiple
+    /// enzymes must be opened using `read_section`.
+    pub fn read<P: AsRef<Path> + Debug>(filename: &P) -> Result<KMerIndex> {
+        Self::read_section(filename, None)
+    }
+
+    /// Opens the section of a K-mer index built for the given enzyme, or the only section of
+    /// the index if no enzyme is specified. The K-mer length is a property of the index and is
+    /// therefore not compared.
+    pub fn read_section<P: AsRef<Path> + Debug>(
+        filename: &P,
+        enzyme: Option<&Enzyme>,
+    ) -> Result<KMerIndex> {
+        Self::read_section_with_enzymes(filename, enzyme).map(|(index, _)| index)
+    }
+
+    /// Opens the section of a K-mer index as `read_section`, and also returns the enzymes for
+    /// which the index contains sections, in the order of the sections
+    pub fn read_section_with_enzymes<P: AsRef<Path> + Debug>(
+        filename: &P,
+        enzyme: Option<&Enzyme>,
+    ) -> Result<(KMerIndex, Vec<Enzyme>)> {
+        let (mut indexes, enzymes) = Self::open(filename, |enzymes| {
+            Self::select_section(enzymes, enzyme).map(|idx| vec![idx])
+        })?;
+
+        Ok((indexes.swap_remove(0), enzymes))
+    }
+
+    /// Opens every section of a K-mer index, in the order of the sections
+    pub fn read_sections<P: AsRef<Path> + Debug>(filename: &P) -> Result<Vec<KMerIndex>> {
+        let (indexes, _) = Self::open(filename, |enzymes| Ok((0..enzymes.len()).collect()))?;
+
+        Ok(indexes)
+    }
+
+    /// Returns the enzymes for which an index contains sections, in the order of the sections
+    pub fn read_enzymes<P: AsRef<Path> + Debug>(filename: &P) -> Result<Vec<Enzyme>> {
+        let (_, enzymes) = Self::open(filename, |_| Ok(Vec::new()))?;
+
+        Ok(enzymes)
+    }
+
+    /// Returns the section built for the given enzyme, or the only section if no enzyme is
+    /// specified. The K-mer length is a property of the index and is therefore not compared.
+    fn select_section(enzymes: &[Enzyme], enzyme: Option<&Enzyme>) -> Result<usize> {
+        let names = enzymes
+            .iter()
+            .map(|v| format!("{:?}", v.name))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        match enzyme {
+            Some(enzyme) => enzymes
+                .iter()
+                .position(|v| *v == enzyme.clone().with_kmer_len(v.kmer_len()))
+                .ok_or_else(|| {
+                    if enzymes.len() == 1 {
+                        format!(
+                            "index was built for enzyme {}, not {:?}",
+                            names, enzyme.name
+                        )
+                    } else {
+                        format!(
+                            "index contains no section for enzyme {:?}; available enzymes \
+                             are {}",
+                            enzyme.name, names
+                        )
+                    }
+                    .into()
+                }),
+            None if enzymes.len() == 1 => Ok(0),
+            None => Err(format!(
+                "index contains multiple enzymes ({}); select one using --enzyme",
+                names
+            )
+            .into()),
+        }
+    }
+
+    /// Opens the sections of a K-mer index chosen by `select` from the enzymes in the index,
+    /// returning the selected sections (in the order selected) and the enzymes in the index. The
+    /// file is mapped once and shared between the sections.
+    fn open<P, F>(filename: &P, select: F) -> Result<(Vec<KMerIndex>, Vec<Enzyme>)>
+    where
+        P: AsRef<Path> + Debug,
+        F: FnOnce(&[Enzyme]) -> Result<Vec<usize>>,
+    {
+        let file = File::open(filename).chain_err(|| "failed to open index file")?;
+        let mmap = unsafe { Mmap::map(&file) }.chain_err(|| "failed to map index file")?;
+        let mmap = Arc::new(mmap);
+        let mut reader = &mmap[..];
+        let mut buffer = Vec::new();
+
+        reader
+            .by_ref()
+            .take(INDEX_HEADER.len() as u64)
+            .read_to_end(&mut buffer)
+            .chain_err(|| "failed to read index header")?;
+
+        if buffer != INDEX_HEADER {
+            return Err("file is not a valid CRISPyR index file".into());
+        }
+
+        let version = reader
+            .read_u8()
+            .chain_err(|| "failed to read index version number")?;
+        if version == LEGACY_INDEX_VERSION {
+            let index = Self::read_legacy(&mut reader)
+                .chain_err(|| format!("failed to read version {} index", version))?;
+            let enzymes = vec![index.enzyme.clone()];
+            let indexes = if select(&enzymes)?.is_empty() {
+                Vec::new()
+            } else {
+                vec![index]
+            };
+
+            return Ok((indexes, enzymes));
+        } else if version < MIN_INDEX_VERSION {
+            return Err(format!(
+                "index file format (version {}) is no longer supported; please re-index genome",
+                version
+            )
+            .into());
+        } else if version > INDEX_VERSION {
+            return Err("index generated using newer version of CRISPyR;
+                        please upgrade CRISPyR or re-index genome"
+                .into());
+        }
+
+        let enzyme_count = if version >= MULTI_ENZYME_INDEX_VERSION {
+            reader
+                .read_u8()
+                .chain_err(|| "failed to read number of enzymes")?
+        } else {
+            1
+        };
+
+        let mut enzymes = Vec::with_capacity(enzyme_count as usize);
+        for _ in 0..enzyme_count {
+            enzymes.push(Self::read_enzyme(&mut reader)?);
+        }
+
+        if enzymes.is_empty() {
+            return Err("index does not contain any enzymes".into());
+        }
+
+        let selected = select(&enzymes)?;
+
+        let flags = reader
+            .read_u64::<LittleEndian>()
+            .chain_err(|| "failed to read index flags")?;
+
+        let positions = flags & FLAG_POSITIONS != 0;
+        let masked_excluded = flags & FLAG_EXCLUDE_MASKED != 0;
+        let refseqs = Self::read_refseqs(&mut reader)?;
+        let provenance = Self::read_provenance(&mut reader, version)?;
+        if !provenance.sequences.is_empty() && provenance.sequences.len() != refseqs.len() {
+            return Err("mismatch between number of sequences and sequence checksums".into());
+        }
+
+        let position_size = if version >= WIDE_POSITIONS_INDEX_VERSION {
+            std::mem::size_of::<Position>()
+        } else if version >= PACKED_POSITIONS_INDEX_VERSION {
+            std::mem::size_of::<u64>()
+        } else {
+            2 * std::mem::size_of::<u64>()
+        };
+
+        // Sections follow each other, so the preceding sections determine the offset of each
+        // section, and all sections the offset of the embedded sequences
+        let mut offset = align(mmap.len() - reader.len());
+        let mut section_offsets = Vec::with_capacity(enzymes.len());
+        for enzyme in &enzymes {
+            section_offsets.push(offset);
+
+            let (_, _, end) = MappedKMers::layout(&mmap, offset, enzyme, positions, position_size)?;
+            offset = align(end);
+        }
+
+        let mut sequences = if flags & FLAG_SEQUENCES != 0 {
+            let sequences = Self::read_sequences(&mmap, offset)?;
+            if sequences.records.len() != refseqs.len() {
+                return Err("mismatch between number of sequences and embedded sequences".into());
+            }
+
+            Some(sequences)
+        } else {
+            None
+        };
+
+        // Embedded sequences are copied if positions are converted, as the memory-map is then
+        // not kept
+        if positions && version < WIDE_POSITIONS_INDEX_VERSION {
+            if let Some(sequences) = &mut sequences {
+                if let SequenceData::Mapped(offset, len) = sequences.data {
+                    sequences.data = SequenceData::Memory(mmap[offset..offset + len].to_vec());
+                }
+            }
+        }
+
+        let mut indexes = Vec::with_capacity(selected.len());
+        for section in selected {
+            let enzyme = enzymes[section].clone();
+            let offset = section_offsets[section];
+            let kmers = MappedKMers::map(mmap.clone(), offset, &enzyme, positions, position_size)?;
+            let kmers = if positions && version < WIDE_POSITIONS_INDEX_VERSION {
+                KMerStore::Memory(kmers.read_previous_positions(version)?)
+            } else {
+                KMerStore::Mapped(kmers)
+            };
+
+            indexes.push(KMerIndex {
+                version,
+                enzyme,
+                refseqs: refseqs.clone(),
+                provenance: provenance.clone(),
+                masked_excluded,
+                sequences: sequences.clone(),
+                kmers,
+            });
+        }
+
+        Ok((indexes, enzymes))
+    }
+
+    /// Reads the body of an index written by CRISPyR v0.2.x. These indexes identify the enzyme by
+    /// name, use 13-mers, and only record the names of sequences if positions are saved. The
+    /// index is read into memory, and may be written in the current format using `write`.
+    fn read_legacy<R: Read>(reader: &mut R) -> Result<KMerIndex> {
+        let name = Self::read_short_string(reader).chain_err(|| "failed to read enzyme name")?;
+        let enzyme = match Enzyme::get(&name) {
+            Some(enzyme) => enzyme.with_kmer_len(DEFAULT_KMER_LEN),
+            None => return Err(format!("unknown enzyme {:?}", name).into()),
+        };
+
+        let flags = reader
+            .read_u64::<LittleEndian>()
+            .chain_err(|| "failed to read index flags")?;
+        let positions = flags & FLAG_POSITIONS != 0;
+
+        let refseqs = if positions {
+            Self::read_refseqs(reader)?
+        } else {
+            Vec::new()
+        };
+
+        let kmer_count = reader
+            .read_u64::<LittleEndian>()
+            .chain_err(|| "failed to read number of unique kmers")?;
+
+        let mut kmers = KMerMap::new(1, DEFAULT_KMER_LEN, positions);
+        for _ in 0..kmer_count {
+            let kmer = reader
+                .read_u32::<LittleEndian>()
+                .chain_err(|| "failed to read K-mer")?;
+            let kmer = KMer::new(u64::from(kmer));
+            let count = reader
+                .read_u32::<LittleEndian>()
+                .chain_err(|| "failed to read K-mer frequency")?;
+
+            match &mut kmers {
+                KMerMap::Counts(tables) => *tables[0].get_mut(kmer) += count,
+                KMerMap::Positions(table) => {
+                    for _ in 0..count {
+                        let value = reader
+                            .read_u64::<LittleEndian>()
+                            .chain_err(|| "failed to read kmer position")?;
+
+                        // Positions consisted of a strand bit and a 31 bit refseq, followed by
+                        // the 32 bit position
+                        let refseq = ((value >> 32) & 0x7FFF_FFFF) as u32;
+                        let pos = i64::from((value & 0xFFFF_FFFF) as i32);
+                        let position = if value & FORWARD_STRAND != 0 {
+                            Position::forward(refseq, pos)?
+                        } else {
+                            Position::reverse(refseq, pos)?
+                        };
+
+                        table.add(position, kmer);
+                    }
+                }
+            }
+        }
+
+        let mut index = KMerIndex::new(&enzyme, refseqs, kmers);
+        index.version = LEGACY_INDEX_VERSION;
+
+        Ok(index)
+    }
+
+    pub fn write<P: AsRef<Path> + Debug>(&self, filename: P) -> Result<()> {
+        Self::write_sections(filename, &[self])
+    }
+
+    /// Writes indexes built for different enzymes as sections of a single index file. The
+    /// indexes must be built from the same sequences using the same options, as the reference
+    /// sequences, provenance, and embedded sequences are shared by the sections.
+    pub fn write_sections<P: AsRef<Path> + Debug>(
+        filename: P,
+        indexes: &[&KMerIndex],
+    ) -> Result<()> {
+        let mut sections = Vec::with_capacity(indexes.len());
+        for index in indexes {
+            match &index.kmers {
+                KMerStore::Memory(kmers) => sections.push(kmers),
+                KMerStore::Mapped(_) => return Err("cannot write memory-mapped index".into()),
+            }
+        }
+
+        Self::write_with(filename, indexes, |writer| {
+            for kmers in sections {
+                match kmers {
+                    KMerMap::Counts(tables) => Self::write_counts(writer, tables)?,
+                    KMerMap::Positions(table) => Self::write_positions(writer, table)?,
+                }
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Writes the index using positions collected in an external table rather than the
+    /// (empty) positions of this index. Returns the number of unique K-mers written.
+    pub fn write_external<P: AsRef<Path> + Debug>(
+        &self,
+        filename: P,
+        positions: ExternalPositions,
+    ) -> Result<usize> {
+        let kmer_counts = Self::write_sections_external(filename, vec![(self, positions)])?;
+
+        Ok(kmer_counts[0])
     }
 
-    pub fn add(&mut self, position: Position, kmer: KMer) {
-        match self {
-            KMerMap::Counts(map) => {
-                map[kmer.key()] += 1;
-            }
-            KMerMap::Positions(map) => {
-                map[kmer.key()].push(position);
+    /// Writes indexes as sections of a single index file (see `write_sections`), using
+    /// positions collected in external tables. Returns the number of unique K-mers written
+    /// for each section.
+    pub fn write_sections_external<P: AsRef<Path> + Debug>(
+        filename: P,
+        sections: Vec<(&KMerIndex, ExternalPositions)>,
+    ) -> Result<Vec<usize>> {
+        let (indexes, positions): (Vec<_>, Vec<_>) = sections.into_iter().unzip();
+        for index in &indexes {
+            match &index.kmers {
+                KMerStore::Memory(KMerMap::Positions(table)) if table.is_empty() => {}
+                _ => return Err("external positions require an empty index with positions".into()),
             }
         }
-    }
-}
 
-pub struct KMerIndex {
-    enzyme: Enzyme,
-    refseqs: Vec<String>,
-    kmers: KMerMap,
-}
+        let mut kmer_counts = Vec::with_capacity(positions.len());
+        Self::write_with(filename, &indexes, |writer| {
+            for positions in positions {
+                kmer_counts.push(positions.write(writer)?);
+            }
 
-impl KMerIndex {
-    pub fn new(enzyme: &Enzyme, refseqs: Vec<String>, kmers: KMerMap) -> KMerIndex {
-        KMerIndex {
-            enzyme: enzyme.clone(),
-            refseqs,
-            kmers,
-        }
-    }
+            Ok(())
+        })?;
 
-    pub fn refseqs(&self) -> &[String] {
-        &self.refseqs
+        Ok(kmer_counts)
     }
 
-    pub fn enzyme(&self) -> &Enzyme {
-        &self.enzyme
-    }
+    /// Writes the shared index header, followed by the K-mers of each section written by
+    /// `write_kmers`, followed by any embedded sequences.
+    fn write_with<P, F>(filename: P, indexes: &[&KMerIndex], write_kmers: F) -> Result<()>
+    where
+        P: AsRef<Path> + Debug,
+        F: FnOnce(&mut BufWriter<File>) -> Result<()>,
+    {
+        let first = indexes.first().ok_or("no indexes to write")?;
+        if indexes.len() > usize::from(u8::MAX) {
+            return Err(format!("too many enzymes; at most {} are supported", u8::MAX).into());
+        }
 
-    pub fn kmer_count(&self) -> usize {
-        self.kmers.len()
-    }
+        let mut names = HashSet::new();
+        for index in indexes {
+            if !names.insert(&index.enzyme.name) {
+                return Err(format!("duplicate enzyme {:?}", index.enzyme.name).into());
+            } else if index.refseqs != first.refseqs || index.provenance != first.provenance {
+                return Err("cannot combine indexes built from different sequences".into());
+            } else if index.has_positions() != first.has_positions() {
+                return Err("cannot combine indexes with and without positions".into());
+            } else if index.masked_excluded != first.masked_excluded {
+                return Err(
+                    "cannot combine indexes with and without sites in soft-masked sequence".into(),
+                );
+            } else if index.has_sequences() != first.has_sequences() {
+                return Err("cannot combine indexes with and without embedded sequences".into());
+            }
+        }
 
-    pub fn get_count(&self, kmer: KMer) -> Option<u32> {
-        let count = match &self.kmers {
-            KMerMap::Counts(map) => map[kmer.key()],
-            KMerMap::Positions(map) => map[kmer.key()].len() as u32,
-        };
+        // The header is written to a buffer to determine the padding needed to align the K-mers
+        let mut header = Vec::new();
+        header
+            .write(INDEX_HEADER)
+            .chain_err(|| "failed to write index header")?;
+        header
+            .write_u8(INDEX_VERSION)
+            .chain_err(|| "failed to write index version")?;
 
-        if count > 0 {
-            Some(count)
-        } else {
-            None
+        header
+            .write_u8(indexes.len() as u8)
+            .chain_err(|| "failed to write number of enzymes")?;
+        for index in indexes {
+            Self::write_enzyme(&mut header, &index.enzyme)
+                .chain_err(|| "failed to write enzyme definition")?;
         }
-    }
 
-    pub fn has_positions(&self) -> bool {
-        match self.kmers {
-            KMerMap::Counts(_) => false,
-            KMerMap::Positions(_) => true,
+        let mut flags = if first.has_positions() {
+            FLAG_POSITIONS
+        } else {
+            0
+        };
+        if first.masked_excluded {
+            flags |= FLAG_EXCLUDE_MASKED;
         }
-    }
+        if first.has_sequences() {
+            flags |= FLAG_SEQUENCES;
+        }
+        header
+            .write_u64::<LittleEndian>(flags)
+            .chain_err(|| "failed to write index flags")?;
 
-    pub fn get_positions(&self, kmer: KMer) -> Option<&[Position]> {
-        match &self.kmers {
-            KMerMap::Counts(_) => None,
-            KMerMap::Positions(map) => {
-                let positions = &map[kmer.key()];
+        Self::write_refseqs(&mut header, &first.refseqs)
+            .chain_err(|| "failed to write reference sequenec names")?;
+        Self::write_provenance(&mut header, &first.provenance)
+            .chain_err(|| "failed to write index provenance")?;
 
-                if positions.is_empty() {
-                    None
-                } else {
-                    Some(positions)
-                }
-            }
-        }
-    }
+        header.resize(align(header.len()), 0);
 
-    pub fn read<P: AsRef<Path> + Debug>(filename: &P) -> Result<KMerIndex> {
-        let file = File::open(filename).chain_err(|| "failed to open index file")?;
-        let mut reader = BufReader::new(file);
-        let mut buffer = Vec::new();
+        // The index is written to a temporary file, since the destination may be an index that
+        // is currently memory-mapped (e.g. when appending to or merging indexes)
+        let filename = filename.as_ref();
+        let mut temp = filename.as_os_str().to_owned();
+        temp.push(".tmp");
 
-        reader
-            .by_ref()
-            .take(INDEX_HEADER.len() as u64)
-            .read_to_end(&mut buffer)
-            .chain_err(|| "failed to read index header")?;
+        let file = File::create(&temp).chain_err(|| "failed to create index file")?;
+        let mut writer = BufWriter::new(file);
+        writer
+            .write_all(&header)
+            .chain_err(|| "failed to write index header")?;
 
-        if buffer != INDEX_HEADER {
-            return Err("file is not a valid CRISPyR index file".into());
+        // Counts and positions are padded to multiples of 8 bytes, so sections stay aligned
+        write_kmers(&mut writer).chain_err(|| "failed to write KMers")?;
+
+        if let Some((records, data)) = first.sequence_data() {
+            Self::write_sequences(&mut writer, records, data)
+                .chain_err(|| "failed to write embedded sequences")?;
         }
 
-        let version = reader
-            .read_u8()
-            .chain_err(|| "failed to read index version number")?;
-        if version < INDEX_VERSION {
-            return Err("index file is outdated; please re-index genome".into());
-        } else if version > INDEX_VERSION {
-            return Err("index generated using newer version of CRISPyR;
-                        please upgrade CRISPyR or re-index genome"
+        writer.flush().chain_err(|| "failed to write index file")?;
+        std::fs::rename(&temp, filename).chain_err(|| "failed to rename index file")
+    }
+
+    /// Merges indexes built using the same enzyme. Reference sequences are renumbered in the
+    /// order of the indexes, and names of reference sequences must be unique across indexes.
+    /// Sequence checksums are only kept if they are available for all indexes, while embedded
+    /// sequences must be available for either all or none of the indexes.
+    ///
+    /// The merged index is built in memory, and therefore requires memory proportional to the
+    /// total number of K-mers (or positions) in the indexes; this includes indexes extended using
+    /// `index --append`, for which '--max-memory' is not supported.
+    pub fn merge(indexes: &[&KMerIndex]) -> Result<KMerIndex> {
+        let first = indexes.first().ok_or("no indexes to merge")?;
+        let enzyme = first.enzyme();
+        let positions = first.has_positions();
+        let masked_excluded = first.masked_excluded();
+        let embedded = first.has_sequences();
+
+        let mut refseqs = Vec::new();
+        let mut provenance = Provenance::default();
+        let mut names = HashSet::new();
+        let mut kmers = KMerMap::new(enzyme.pams.pams().len(), enzyme.kmer_len(), positions);
+        for index in indexes {
+            if index.enzyme() != enzyme {
+                return Err(format!(
+                    "cannot merge indexes built for different enzymes ({:?} with {}-mers and \
+                     {:?} with {}-mers)",
+                    enzyme.name,
+                    enzyme.kmer_len(),
+                    index.enzyme().name,
+                    index.enzyme().kmer_len(),
+                )
                 .into());
-        }
+            } else if index.has_positions() != positions {
+                return Err("cannot merge indexes with and without positions".into());
+            } else if index.masked_excluded() != masked_excluded {
+                return Err(
+                    "cannot merge indexes with and without sites in soft-masked sequence".into(),
+                );
+            } else if index.has_sequences() != embedded {
+                return Err("cannot merge indexes with and without embedded sequences".into());
+            }
 
-        buffer.clear();
-        let enzyme_len = reader
-            .read_u8()
-            .chain_err(|| "failed to read length of enzyme name")?;
-        reader
-            .by_ref()
-            .take(u64::from(enzyme_len))
-            .read_to_end(&mut buffer)
-            .chain_err(|| "failed to read enzyme name")?;
+            for name in index.refseqs() {
+                if !names.insert(name) {
+                    return Err(format!("duplicate reference sequence {:?}", name).into());
+                }
+            }
 
-        let enzyme_name =
-            std::str::from_utf8(&buffer).chain_err(|| "failed to decode enzyme name")?;
+            let offset = refseqs.len() as u32;
+            if (refseqs.len() + index.refseqs().len()) as u64 > MAX_REFSEQS {
+                return Err(format!(
+                    "too many reference sequences; at most {} are supported",
+                    MAX_REFSEQS
+                )
+                .into());
+            }
 
-        let enzyme = match Enzyme::get(&enzyme_name) {
-            Some(value) => value,
-            None => return Err(format!("unknown enzyme {:?}", enzyme_name).into()),
-        };
+            refseqs.extend(index.refseqs().iter().cloned());
+            provenance
+                .sources
+                .extend(index.provenance.sources.iter().cloned());
+            provenance
+                .sequences
+                .extend(index.provenance.sequences.iter().cloned());
+
+            match &mut kmers {
+                KMerMap::Counts(tables) => {
+                    for kmer in index.iter_kmers() {
+                        for (pam, table) in tables.iter_mut().enumerate() {
+                            if let Some(count) = index.get_count(pam, kmer) {
+                                *table.get_mut(kmer) += count;
+                            }
+                        }
+                    }
+                }
+                KMerMap::Positions(table) => {
+                    for kmer in index.iter_kmers() {
+                        for position in index.get_positions(kmer).unwrap_or(&[]) {
+                            table.add(position.with_refseq(position.refseq() + offset), kmer);
+                        }
+                    }
+                }
+            }
+        }
 
-        let flags = reader
-            .read_u64::<LittleEndian>()
-            .chain_err(|| "failed to read index flags")?;
+        if indexes.iter().any(|v| v.provenance.sequences.is_empty()) {
+            provenance.sequences.clear();
+        }
 
-        let (refseqs, kmers) = if flags & FLAG_POSITIONS != 0 {
-            (
-                Self::read_refseqs(&mut reader)?,
-                Self::read_positions(&mut reader)?,
-            )
-        } else {
-            (Vec::new(), Self::read_counts(&mut reader)?)
-        };
+        let mut index = KMerIndex::new(enzyme, refseqs, kmers)
+            .with_provenance(provenance)?
+            .with_masked_excluded(masked_excluded);
 
-        Ok(KMerIndex {
-            enzyme,
-            refseqs,
-            kmers,
-        })
-    }
+        if embedded {
+            let mut sequences = TwoBitSequences::default();
+            for (records, data) in indexes.iter().filter_map(|v| v.sequence_data()) {
+                for record in records {
+                    sequences.push(record.clone(), record.packed(data));
+                }
+            }
 
-    pub fn write<P: AsRef<Path> + Debug>(&self, filename: P) -> Result<()> {
-        let file = File::create(filename).chain_err(|| "failed to create index file")?;
-        let mut writer = BufWriter::new(file);
+            index = index.with_sequences(sequences);
+        }
 
-        writer
-            .write(INDEX_HEADER)
-            .chain_err(|| "failed to write index header")?;
-        writer
-            .write_u8(INDEX_VERSION)
-            .chain_err(|| "failed to write index version")?;
+        Ok(index)
+    }
 
-        let name = self.enzyme.name.as_bytes();
+    fn write_enzyme<W: Write>(writer: &mut W, enzyme: &Enzyme) -> Result<()> {
+        let name = enzyme.name.as_bytes();
         writer
             .write_u8(name.len() as u8)
             .chain_err(|| "failed to write enzyme name length")?;
         writer
-            .write(&name)
+            .write(name)
             .chain_err(|| "failed to write enzyme name")?;
 
-        match &self.kmers {
-            KMerMap::Counts(map) => {
-                writer
-                    .write_u64::<LittleEndian>(0)
-                    .chain_err(|| "failed to write index flags")?;
+        writer
+            .write_u8(enzyme.pams.pams().len() as u8)
+            .chain_err(|| "failed to write number of PAMs")?;
+        for (pam, weight) in enzyme.pams.pams().iter().zip(enzyme.pams.weights()) {
+            let pam = pam.to_string();
+            writer
+                .write_u8(pam.len() as u8)
+                .chain_err(|| "failed to write PAM length")?;
+            writer
+                .write(pam.as_bytes())
+                .chain_err(|| "failed to write PAM")?;
+            writer
+                .write_f64::<LittleEndian>(*weight)
+                .chain_err(|| "failed to write PAM weight")?;
+        }
 
-                writer
-                    .write_u64::<LittleEndian>(self.kmers.len() as u64)
-                    .chain_err(|| "failed to write number of unique kmers")?;
-                Self::write_counts(&mut writer, map).chain_err(|| "failed to write KMers")
-            }
-            KMerMap::Positions(map) => {
-                writer
-                    .write_u64::<LittleEndian>(FLAG_POSITIONS)
-                    .chain_err(|| "failed to write index flags")?;
+        writer
+            .write_u8(match enzyme.pams.position() {
+                pam::Position::Head => 0,
+                pam::Position::Tail => 1,
+            })
+            .chain_err(|| "failed to write PAM position")?;
 
-                Self::write_refseqs(&mut writer, &self.refseqs)
-                    .chain_err(|| "failed to write reference sequenec names")?;
+        writer
+            .write_u32::<LittleEndian>(enzyme.grna_len as u32)
+            .chain_err(|| "failed to write gRNA length")?;
+        writer
+            .write_i32::<LittleEndian>(enzyme.cutsite as i32)
+            .chain_err(|| "failed to write cut-site")?;
+        writer
+            .write_i32::<LittleEndian>(enzyme.cutsite_bottom as i32)
+            .chain_err(|| "failed to write bottom strand cut-site")?;
+        writer
+            .write_u8(enzyme.kmer_len() as u8)
+            .chain_err(|| "failed to write K-mer length")?;
 
-                writer
-                    .write_u64::<LittleEndian>(self.kmers.len() as u64)
-                    .chain_err(|| "failed to write number of unique kmers")?;
-                Self::write_positions(&mut writer, map).chain_err(|| "failed to write KMers")
-            }
-        }
+        Ok(())
     }
 
-    fn read_counts(reader: &mut BufReader<File>) -> Result<KMerMap> {
-        let kmer_count = reader
-            .read_u64::<LittleEndian>()
-            .chain_err(|| "failed to read number of unique sites")?;
-        let mut kmers = vec![0; KMER_COUNT];
-
-        let progress = progress::default(kmer_count as usize);
-        for _ in 0..kmer_count {
-            let kmer = reader
-                .read_u32::<LittleEndian>()
-                .chain_err(|| "failed to read K-mer")?;
-            let count = reader
-                .read_u32::<LittleEndian>()
-                .chain_err(|| "failed to read K-mer frequency")?;
+    fn read_enzyme<R: Read>(reader: &mut R) -> Result<Enzyme> {
+        let name = Self::read_short_string(reader).chain_err(|| "failed to read enzyme name")?;
 
-            kmers[kmer as usize] += count;
-            progress.inc(1);
+        let pam_count = reader
+            .read_u8()
+            .chain_err(|| "failed to read number of PAMs")?;
+        let mut pams = Vec::with_capacity(pam_count as usize);
+        for _ in 0..pam_count {
+            let pam = Self::read_short_string(reader).chain_err(|| "failed to read PAM")?;
+            let weight = reader
+                .read_f64::<LittleEndian>()
+                .chain_err(|| "failed to read PAM weight")?;
+
+            pams.push((pam, weight));
         }
 
-        progress.finish();
+        let position = match reader
+            .read_u8()
+            .chain_err(|| "failed to read PAM position")?
+        {
+            0 => pam::Position::Head,
+            1 => pam::Position::Tail,
+            value => return Err(format!("invalid PAM position {}", value).into()),
+        };
+
+        let grna_len = reader
+            .read_u32::<LittleEndian>()
+            .chain_err(|| "failed to read gRNA length")?;
+        let cutsite = reader
+            .read_i32::<LittleEndian>()
+            .chain_err(|| "failed to read cut-site")?;
+        let cutsite_bottom = reader
+            .read_i32::<LittleEndian>()
+            .chain_err(|| "failed to read bottom strand cut-site")?;
+        let kmer_len = reader
+            .read_u8()
+            .chain_err(|| "failed to read K-mer length")?;
+
+        let pams = pams
+            .into_iter()
+            .map(|(pam, weight)| (PAM::new(pam.as_bytes(), position), weight))
+            .collect();
+        let pams = PAMSet::weighted(pams).ok_or("no PAMs in enzyme definition")?;
+        let enzyme = Enzyme::new(&name, pams, grna_len as usize, cutsite as isize)
+            .with_bottom_cutsite(cutsite_bottom as isize)
+            .with_kmer_len(kmer_len as usize);
+        enzyme
+            .validate()
+            .chain_err(|| "invalid enzyme definition in index")?;
+
+        Ok(enzyme)
+    }
+
+    fn read_short_string<R: Read>(reader: &mut R) -> Result<String> {
+        let len = reader.read_u8().chain_err(|| "failed to read length")?;
+        let mut buffer = vec![0; len as usize];
+        reader
+            .read_exact(&mut buffer)
+            .chain_err(|| "failed to read string")?;
 
-        Ok(KMerMap::Counts(kmers))
+        String::from_utf8(buffer).chain_err(|| "failed to decode string")
     }
 
-    fn write_counts(writer: &mut BufWriter<File>, map: &[u32]) -> Result<()> {
-        for (kmer, count) in map.iter().enumerate() {
-            if *count > 0 {
-                writer
-                    .write_u32::<LittleEndian>(kmer as u32)
-                    .chain_err(|| "failed to write kmer")?;
+    fn write_counts<W: Write>(writer: &mut W, tables: &[KMerTable<u32>]) -> Result<()> {
+        let kmers = Self::collect_kmers(tables);
+
+        Self::write_kmers(writer, &kmers)?;
+
+        let padding = align(kmers.len() * 4) - kmers.len() * 4;
+        for table in tables {
+            for kmer in &kmers {
                 writer
-                    .write_u32::<LittleEndian>(*count)
+                    .write_u32::<LittleEndian>(table.get(*kmer).copied().unwrap_or(0))
                     .chain_err(|| "failed to write kmer count")?;
             }
+
+            writer
+                .write_all(&vec![0; padding])
+                .chain_err(|| "failed to write padding")?;
+        }
+
+        Ok(())
+    }
+
+    fn write_kmers<W: Write>(writer: &mut W, kmers: &[KMer]) -> Result<()> {
+        writer
+            .write_u64::<LittleEndian>(kmers.len() as u64)
+            .chain_err(|| "failed to write number of unique kmers")?;
+
+        for kmer in kmers {
+            writer
+                .write_u64::<LittleEndian>(kmer.0)
+                .chain_err(|| "failed to write kmer")?;
         }
 
         Ok(())
     }
 
-    fn write_refseqs(writer: &mut BufWriter<File>, refseqs: &[String]) -> Result<()> {
+    fn write_refseqs<W: Write>(writer: &mut W, refseqs: &[String]) -> Result<()> {
         writer
             .write_u64::<LittleEndian>(refseqs.len() as u64)
             .chain_err(|| "failed to write number of reference sequences")?;
@@ -341,33 +1573,121 @@ impl KMerIndex {
         Ok(())
     }
 
-    fn write_positions(writer: &mut BufWriter<File>, map: &[Vec<Position>]) -> Result<()> {
-        for (kmer, positions) in map.iter().enumerate() {
-            if !positions.is_empty() {
+    fn write_positions<W: Write>(writer: &mut W, table: &PositionTable) -> Result<()> {
+        Self::write_kmers(writer, &table.kmers)?;
+
+        for offset in &table.offsets {
+            writer
+                .write_u64::<LittleEndian>(*offset)
+                .chain_err(|| "failed to write kmer offset")?;
+        }
+
+        for position in &table.positions {
+            write_position(writer, *position)?;
+        }
+
+        write_position_padding(writer, table.positions.len())
+    }
+
+    fn write_sequences<W: Write>(
+        writer: &mut W,
+        records: &[TwoBitRecord],
+        data: &[u8],
+    ) -> Result<()> {
+        writer
+            .write_u64::<LittleEndian>(records.len() as u64)
+            .chain_err(|| "failed to write number of sequences")?;
+
+        for record in records {
+            writer
+                .write_u64::<LittleEndian>(record.len)
+                .chain_err(|| "failed to write sequence length")?;
+            writer
+                .write_u64::<LittleEndian>(record.offset)
+                .chain_err(|| "failed to write sequence offset")?;
+            writer
+                .write_u64::<LittleEndian>(record.runs.len() as u64)
+                .chain_err(|| "failed to write number of N runs")?;
+
+            for (start, len) in &record.runs {
                 writer
-                    .write_u32::<LittleEndian>(kmer as u32)
-                    .chain_err(|| "failed to write kmer")?;
+                    .write_u64::<LittleEndian>(*start)
+                    .chain_err(|| "failed to write N run start")?;
                 writer
-                    .write_u32::<LittleEndian>(positions.len() as u32)
-                    .chain_err(|| "failed to write number of hits for kmer")?;
+                    .write_u64::<LittleEndian>(*len)
+                    .chain_err(|| "failed to write N run length")?;
+            }
+        }
 
-                for position in positions {
-                    writer
-                        .write_u64::<LittleEndian>(position.to_u64())
-                        .chain_err(|| "failed to write kmer position")?;
-                }
+        writer
+            .write_u64::<LittleEndian>(data.len() as u64)
+            .chain_err(|| "failed to write size of packed sequences")?;
+        writer
+            .write_all(data)
+            .chain_err(|| "failed to write packed sequences")
+    }
+
+    /// Reads the records of sequences embedded at the given byte offset (following the K-mers)
+    /// of the memory-mapped file; the packed bases are accessed directly from the file.
+    fn read_sequences(mmap: &[u8], offset: usize) -> Result<EmbeddedSequences> {
+        let mut reader = &mmap[usize::min(mmap.len(), offset)..];
+
+        let count = reader
+            .read_u64::<LittleEndian>()
+            .chain_err(|| "failed to read number of sequences")?;
+
+        let mut records = Vec::new();
+        for _ in 0..count {
+            let len = reader
+                .read_u64::<LittleEndian>()
+                .chain_err(|| "failed to read sequence length")?;
+            let offset = reader
+                .read_u64::<LittleEndian>()
+                .chain_err(|| "failed to read sequence offset")?;
+            let run_count = reader
+                .read_u64::<LittleEndian>()
+                .chain_err(|| "failed to read number of N runs")?;
+
+            let mut runs = Vec::new();
+            for _ in 0..run_count {
+                let start = reader
+                    .read_u64::<LittleEndian>()
+                    .chain_err(|| "failed to read N run start")?;
+                let len = reader
+                    .read_u64::<LittleEndian>()
+                    .chain_err(|| "failed to read N run length")?;
+
+                runs.push((start, len));
             }
+
+            records.push(TwoBitRecord { len, runs, offset });
         }
 
-        Ok(())
+        let len = reader
+            .read_u64::<LittleEndian>()
+            .chain_err(|| "failed to read size of packed sequences")? as usize;
+        let offset = mmap.len() - reader.len();
+        if len > reader.len() {
+            return Err("index file is truncated".into());
+        }
+
+        for record in &records {
+            if record.offset as usize + packed_len(record.len) > len {
+                return Err("invalid offset of embedded sequence".into());
+            }
+        }
+
+        Ok(EmbeddedSequences {
+            records,
+            data: SequenceData::Mapped(offset, len),
+        })
     }
 
-    fn read_refseqs(reader: &mut BufReader<File>) -> Result<Vec<String>> {
+    fn read_refseqs<R: Read>(reader: &mut R) -> Result<Vec<String>> {
         let refseq_count = reader
             .read_u64::<LittleEndian>()
             .chain_err(|| "failed to read number of reference sequences")?;
 
-        let progress = progress::default(refseq_count as usize);
         let mut refseqs = Vec::with_capacity(refseq_count as usize);
         for _ in 0..refseq_count {
             let refseq_len = reader
@@ -383,59 +1703,224 @@ impl KMerIndex {
                 .chain_err(|| "failed to parse reference sequence name")?;
 
             refseqs.push(name);
-            progress.inc(1);
         }
 
-        progress.finish();
-
         Ok(refseqs)
     }
 
-    fn read_positions(reader: &mut BufReader<File>) -> Result<KMerMap> {
-        let mut kmers = vec![Vec::new(); KMER_COUNT];
-        let kmer_count = reader
+    fn write_provenance<W: Write>(writer: &mut W, provenance: &Provenance) -> Result<()> {
+        writer
+            .write_u64::<LittleEndian>(provenance.sources.len() as u64)
+            .chain_err(|| "failed to write number of sources")?;
+        for source in &provenance.sources {
+            Self::write_string(writer, &source.fasta).chain_err(|| "failed to write FASTA path")?;
+            Self::write_string(writer, &source.version)
+                .chain_err(|| "failed to write CRISPyR version")?;
+            Self::write_string(writer, &source.command).chain_err(|| "failed to write command")?;
+            Self::write_filter(writer, &source.filter)
+                .chain_err(|| "failed to write sequence filter")?;
+        }
+
+        writer
+            .write_u64::<LittleEndian>(provenance.sequences.len() as u64)
+            .chain_err(|| "failed to write number of sequence checksums")?;
+        for info in &provenance.sequences {
+            writer
+                .write_u64::<LittleEndian>(info.len)
+                .chain_err(|| "failed to write sequence length")?;
+            writer
+                .write_all(&info.md5)
+                .chain_err(|| "failed to write sequence MD5 digest")?;
+        }
+
+        Ok(())
+    }
+
+    fn read_provenance<R: Read>(reader: &mut R, version: u8) -> Result<Provenance> {
+        let source_count = reader
             .read_u64::<LittleEndian>()
-            .chain_err(|| "failed to read number of reference sequences")?;
+            .chain_err(|| "failed to read number of sources")?;
+        let mut sources = Vec::with_capacity(source_count as usize);
+        for _ in 0..source_count {
+            sources.push(Source {
+                fasta: Self::read_string(reader).chain_err(|| "failed to read FASTA path")?,
+                version: Self::read_string(reader)
+                    .chain_err(|| "failed to read CRISPyR version")?,
+                command: Self::read_string(reader).chain_err(|| "failed to read command")?,
+                filter: if version >= FILTER_INDEX_VERSION {
+                    Self::read_filter(reader, version)
+                        .chain_err(|| "failed to read sequence filter")?
+                } else {
+                    SequenceFilter::default()
+                },
+            });
+        }
 
-        let progress = progress::default(kmer_count as usize);
-        for _ in 0..kmer_count {
-            let kmer = reader
-                .read_u32::<LittleEndian>()
-                .chain_err(|| "failed to read K-mer")?;
+        let sequence_count = reader
+            .read_u64::<LittleEndian>()
+            .chain_err(|| "failed to read number of sequence checksums")?;
+        let mut sequences = Vec::with_capacity(sequence_count as usize);
+        for _ in 0..sequence_count {
+            let len = reader
+                .read_u64::<LittleEndian>()
+                .chain_err(|| "failed to read sequence length")?;
+            let mut md5 = [0; 16];
+            reader
+                .read_exact(&mut md5)
+                .chain_err(|| "failed to read sequence MD5 digest")?;
+
+            sequences.push(SequenceInfo { len, md5 });
+        }
+
+        Ok(Provenance { sources, sequences })
+    }
+
+    fn write_filter<W: Write>(writer: &mut W, filter: &SequenceFilter) -> Result<()> {
+        let include_regex: Vec<String> = filter
+            .include_regex
+            .iter()
+            .map(|v| v.as_str().to_owned())
+            .collect();
+        let exclude_regex: Vec<String> = filter
+            .exclude_regex
+            .iter()
+            .map(|v| v.as_str().to_owned())
+            .collect();
+
+        for values in &[
+            &filter.include,
+            &filter.exclude,
+            &include_regex,
+            &exclude_regex,
+        ] {
+            writer
+                .write_u64::<LittleEndian>(values.len() as u64)
+                .chain_err(|| "failed to write number of names or patterns")?;
+            for value in values.iter() {
+                Self::write_string(writer, value)?;
+            }
+        }
+
+        // An empty path indicates that the filter is not restricted to regions; otherwise the
+        // path is followed by the MD5 digest of the BED file (all zeros if unknown)
+        Self::write_string(writer, filter.bed.as_deref().unwrap_or(""))
+            .chain_err(|| "failed to write BED path")?;
+        if filter.bed.is_some() {
+            writer
+                .write_all(&filter.bed_md5.unwrap_or_default())
+                .chain_err(|| "failed to write BED MD5 digest")?;
+        }
+
+        Ok(())
+    }
+
+    fn read_filter<R: Read>(reader: &mut R, version: u8) -> Result<SequenceFilter> {
+        let mut values = Vec::new();
+        for _ in 0..4 {
             let count = reader
-                .read_u32::<LittleEndian>()
-                .chain_err(|| "failed to read K-mer frequency")?;
+                .read_u64::<LittleEndian>()
+                .chain_err(|| "failed to read number of names or patterns")?;
 
-            let mut positions = Vec::new();
+            let mut strings = Vec::new();
             for _ in 0..count {
-                let refseq = reader
-                    .read_u64::<LittleEndian>()
-                    .chain_err(|| "failed to read kmer position")?;
-
-                positions.push(Position::from_u64(refseq));
+                strings.push(Self::read_string(reader)?);
             }
 
-            kmers[kmer as usize] = positions;
-            progress.inc(1);
+            values.push(strings);
+        }
+
+        let exclude_regex = values.pop().unwrap_or_default();
+        let include_regex = values.pop().unwrap_or_default();
+        let exclude = values.pop().unwrap_or_default();
+        let include = values.pop().unwrap_or_default();
+
+        let bed = Self::read_string(reader).chain_err(|| "failed to read BED path")?;
+        let mut bed_md5 = None;
+        if version >= BED_MD5_INDEX_VERSION {
+            if !bed.is_empty() {
+                let mut md5 = [0; 16];
+                reader
+                    .read_exact(&mut md5)
+                    .chain_err(|| "failed to read BED MD5 digest")?;
+                bed_md5 = Some(md5).filter(|v| v != &[0; 16]);
+            }
+        } else {
+            // Previous versions recorded the regions themselves, which are skipped
+            let region_count = reader
+                .read_u64::<LittleEndian>()
+                .chain_err(|| "failed to read number of regions")?;
+            for _ in 0..region_count {
+                Self::read_string(reader)?;
+                reader
+                    .read_u64::<LittleEndian>()
+                    .chain_err(|| "failed to read region start")?;
+                reader
+                    .read_u64::<LittleEndian>()
+                    .chain_err(|| "failed to read region end")?;
+            }
         }
 
-        progress.finish();
+        Ok(SequenceFilter {
+            include,
+            exclude,
+            include_regex: include_regex
+                .iter()
+                .map(|v| SequenceFilter::regex(v))
+                .collect::<Result<_>>()?,
+            exclude_regex: exclude_regex
+                .iter()
+                .map(|v| SequenceFilter::regex(v))
+                .collect::<Result<_>>()?,
+            bed: Some(bed).filter(|v| !v.is_empty()),
+            bed_md5,
+            regions: HashMap::new(),
+        })
+    }
+
+    fn write_string<W: Write>(writer: &mut W, value: &str) -> Result<()> {
+        writer
+            .write_u32::<LittleEndian>(value.len() as u32)
+            .chain_err(|| "failed to write length")?;
+        writer
+            .write_all(value.as_bytes())
+            .chain_err(|| "failed to write string")
+    }
+
+    fn read_string<R: Read>(reader: &mut R) -> Result<String> {
+        let len = reader
+            .read_u32::<LittleEndian>()
+            .chain_err(|| "failed to read length")?;
+        let mut buffer = vec![0; len as usize];
+        reader
+            .read_exact(&mut buffer)
+            .chain_err(|| "failed to read string")?;
 
-        Ok(KMerMap::Positions(kmers))
+        String::from_utf8(buffer).chain_err(|| "failed to decode string")
     }
 
     pub fn summarize(&self) -> String {
-        let end = match self.enzyme.pam.position() {
+        let end = match self.enzyme.pams.position() {
             pam::Position::Head => "5'",
             pam::Position::Tail => "3'",
         };
 
         format!(
-            "Index contains {} unique K-mers for {} with {} PAM sequence {}",
+            "Index contains {} unique {}-mers for {} with {} PAM sequence {}",
             self.kmer_count(),
+            self.enzyme.kmer_len(),
             self.enzyme.name,
             end,
-            self.enzyme.pam.to_string()
+            self.enzyme.pams
         )
     }
+
+    /// Describes the sequences and regions indexed for each source that used a filter
+    pub fn summarize_filters(&self) -> Vec<String> {
+        self.provenance
+            .sources
+            .iter()
+            .filter(|source| !source.filter.is_empty())
+            .map(|source| format!("Subset of {:?} indexed: {}", source.fasta, source.filter))
+            .collect()
+    }
 }
@@ -37,9 +37,14 @@ fn offset(query: u8, candidate: u8) -> usize {
 pub fn matches(query: u8, candidate: u8) -> bool {
     if query == candidate {
         true
-    } else if b'A' <= query && query <= b'Z' && b'A' <= candidate && candidate <= b'Z' {
+    } else if query.is_ascii_uppercase() && candidate.is_ascii_uppercase() {
         IUPAC[offset(query, candidate)]
     } else {
         false
     }
 }
+
+/// Returns true if the nucleotide is an uppercase IUPAC nucleotide code.
+pub fn is_valid(nuc: u8) -> bool {
+    MAPPING.iter().any(|(query, _)| *query == nuc)
+}
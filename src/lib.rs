@@ -6,15 +6,23 @@ extern crate error_chain;
 #[macro_use(lazy_static)]
 extern crate lazy_static;
 
+pub mod api;
 pub mod args;
+pub mod cfd;
 pub mod commands;
 pub mod common;
 pub mod constants;
 pub mod enzyme;
 pub mod errors;
+pub mod external;
+pub mod fasta;
+pub mod filter;
 pub mod index;
 pub mod iupac;
+pub mod mit;
 pub mod pam;
 pub mod progress;
+pub mod provenance;
 pub mod score;
 pub mod table;
+pub mod twobit;
@@ -1,23 +1,4 @@
-// error_chain macro can recurse deeply
-#![recursion_limit = "1024"]
-
-#[macro_use]
-extern crate error_chain;
-#[macro_use(lazy_static)]
-extern crate lazy_static;
-
-mod args;
-mod commands;
-mod common;
-mod constants;
-mod enzyme;
-mod errors;
-mod index;
-mod iupac;
-mod pam;
-mod progress;
-mod score;
-mod table;
+use crispyr::{args, commands, errors};
 
 fn print_err(e: &errors::Error) {
     use error_chain::ChainedError;
@@ -30,10 +11,14 @@ fn print_err(e: &errors::Error) {
 
 fn inner_main() -> errors::Result<()> {
     match args::parse_args()? {
+        args::Args::Enzymes => commands::enzymes::main(),
         args::Args::Find(args) => commands::find::main(&args),
         args::Args::Index(args) => commands::index::main(&args),
+        args::Args::Inspect(args) => commands::inspect::main(&args),
+        args::Args::Merge(args) => commands::merge::main(&args),
         args::Args::OffTargets(args) => commands::offtargets::main(&args),
         args::Args::Score(args) => commands::score::main(&args),
+        args::Args::UpgradeIndex(args) => commands::upgrade_index::main(&args),
         args::Args::None => Ok(()),
     }
 }
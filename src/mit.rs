@@ -0,0 +1,75 @@
+use crate::enzyme::Enzyme;
+use crate::errors::*;
+use crate::pam::Position;
+
+// Off-target hit score from Hsu et al. 2013 (doi:10.1038/nbt.2647), as used by the MIT CRISPR
+// design tool. Weights are given for each position in the 20 bp spacer, with 1 being the
+// PAM-distal nucleotide, and represent the effect of a mismatch at that position.
+const MIT_SPACER_LEN: usize = 20;
+
+#[rustfmt::skip]
+static MIT_WEIGHTS: [f64; MIT_SPACER_LEN] = [
+    0.0, 0.0, 0.014, 0.0, 0.0, 0.395, 0.317, 0.0, 0.389, 0.079,
+    0.445, 0.508, 0.613, 0.851, 0.732, 0.828, 0.615, 0.804, 0.685, 0.583,
+];
+
+/// MIT scoring of off-targets for enzymes with 3' PAMs. Since only the K-mer adjacent to the
+/// PAM is indexed, mismatches outside the K-mer are not taken into account.
+#[derive(Clone, Debug)]
+pub struct MIT {
+    kmer_len: usize,
+}
+
+impl MIT {
+    /// Returns MIT scoring for an enzyme, or an error if MIT scores are not defined for it.
+    pub fn new(enzyme: &Enzyme) -> Result<MIT> {
+        let pams = &enzyme.pams;
+        if pams.position() != Position::Tail {
+            return Err(format!(
+                "MIT scores are only defined for enzymes with 3' PAMs, not {}",
+                enzyme.name
+            )
+            .into());
+        } else if pams.kmer_len() > MIT_SPACER_LEN {
+            return Err(format!(
+                "MIT scores are only defined for K-mers up to {} bp",
+                MIT_SPACER_LEN
+            )
+            .into());
+        }
+
+        Ok(MIT {
+            kmer_len: pams.kmer_len(),
+        })
+    }
+
+    /// Calculates the hit score (0-100) for an off-target, given a bit-mask of mismatches in
+    /// the K-mer, with the lowest bit representing the nucleotide closest to the PAM.
+    pub fn score(&self, mismatches: u64) -> f64 {
+        let mut positions = Vec::new();
+        for idx in 0..self.kmer_len {
+            if mismatches & (1 << idx) != 0 {
+                positions.push(MIT_SPACER_LEN - 1 - idx);
+            }
+        }
+
+        let mut score = 1.0;
+        for &pos in &positions {
+            score *= 1.0 - MIT_WEIGHTS[pos];
+        }
+
+        if positions.len() > 1 {
+            let count = positions.len() as f64;
+            let min = *positions.iter().min().unwrap() as f64;
+            let max = *positions.iter().max().unwrap() as f64;
+            // Mean distance between consecutive mismatches
+            let distance = (max - min) / (count - 1.0);
+            let max_distance = (MIT_SPACER_LEN - 1) as f64;
+
+            score /= ((max_distance - distance) / max_distance) * 4.0 + 1.0;
+            score /= count * count;
+        }
+
+        score * 100.0
+    }
+}
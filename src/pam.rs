@@ -1,7 +1,9 @@
 use std::borrow::Cow;
+use std::fmt;
 
 use crate::common::{encode_dna, KMer};
-use crate::constants::KMER_LEN;
+use crate::constants::DEFAULT_KMER_LEN;
+use crate::errors::*;
 use crate::iupac;
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -14,13 +16,23 @@ pub enum Position {
 pub struct PAM {
     pam: Vec<u8>,
     position: Position,
+    kmer_len: usize,
 }
 
 impl PAM {
+    pub fn new(query: &[u8], position: Position) -> PAM {
+        PAM {
+            pam: query.to_owned(),
+            position,
+            kmer_len: DEFAULT_KMER_LEN,
+        }
+    }
+
     pub fn head(query: &[u8]) -> PAM {
         PAM {
             pam: query.to_owned(),
             position: Position::Head,
+            kmer_len: DEFAULT_KMER_LEN,
         }
     }
 
@@ -28,13 +40,24 @@ impl PAM {
         PAM {
             pam: query.to_owned(),
             position: Position::Tail,
+            kmer_len: DEFAULT_KMER_LEN,
         }
     }
 
+    /// Sets the length of the K-mer adjacent to the PAM.
+    pub fn with_kmer_len(mut self, kmer_len: usize) -> PAM {
+        self.kmer_len = kmer_len;
+        self
+    }
+
     pub fn position(&self) -> Position {
         self.position
     }
 
+    pub fn kmer_len(&self) -> usize {
+        self.kmer_len
+    }
+
     pub fn matches(&self, window: &[u8]) -> bool {
         if self.len() <= window.len() {
             let iupac_wrapper = |(&query, &candidate)| iupac::matches(query, candidate);
@@ -54,7 +77,7 @@ impl PAM {
     }
 
     pub fn kmer(&self, window: &[u8]) -> Option<(usize, KMer)> {
-        if self.pam.len() + KMER_LEN <= window.len() && self.matches(window) {
+        if self.pam.len() + self.kmer_len <= window.len() && self.matches(window) {
             let kmer = encode_dna(self.kmer_slice(window));
 
             match self.position {
@@ -71,7 +94,7 @@ impl PAM {
     pub fn kmer_slice<'a>(&self, seq: &'a [u8]) -> &'a [u8] {
         let (start, end) = self.kmer_pos(seq);
 
-        &seq[start as usize..end as usize]
+        &seq[start..end]
     }
 
     /// Returns mutable slice containing kmer. The string may be any length, but is assumed to
@@ -85,7 +108,7 @@ impl PAM {
     fn kmer_pos(&self, seq: &[u8]) -> (usize, usize) {
         let seq_len = seq.len() as isize;
         let pam_len = self.pam.len() as isize;
-        let kmer_len = KMER_LEN as isize;
+        let kmer_len = self.kmer_len as isize;
 
         match self.position {
             Position::Head => (
@@ -103,7 +126,162 @@ impl PAM {
         self.pam.len()
     }
 
-    pub fn to_string(&self) -> Cow<str> {
+    pub fn is_empty(&self) -> bool {
+        self.pam.is_empty()
+    }
+
+    pub fn to_string(&self) -> Cow<'_, str> {
         String::from_utf8_lossy(&self.pam)
     }
 }
+
+/// A set of equal-length PAMs located at the same end of the target sequence. Each PAM is
+/// associated with a weight in the range (0, 1], reflecting how efficiently the enzyme cuts
+/// target sites with that PAM relative to the canonical PAM.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PAMSet {
+    pams: Vec<PAM>,
+    weights: Vec<f64>,
+}
+
+impl PAMSet {
+    pub fn new(pam: PAM) -> PAMSet {
+        PAMSet {
+            pams: vec![pam],
+            weights: vec![1.0],
+        }
+    }
+
+    /// Adds an alternative PAM with the given weight.
+    pub fn with_pam(mut self, pam: PAM, weight: f64) -> PAMSet {
+        self.pams.push(pam.with_kmer_len(self.kmer_len()));
+        self.weights.push(weight);
+        self
+    }
+
+    /// Creates a set from a list of PAMs and weights; returns None if the list is empty.
+    pub fn weighted(pams: Vec<(PAM, f64)>) -> Option<PAMSet> {
+        if pams.is_empty() {
+            None
+        } else {
+            let (pams, weights) = pams.into_iter().unzip();
+
+            Some(PAMSet { pams, weights })
+        }
+    }
+
+    /// Parses a comma separated list of PAMs, each optionally followed by a colon and a weight,
+    /// e.g. "NGG, NAG:0.26". PAMs without an explicit weight are given the weight 1.
+    pub fn parse(text: &str, position: Position) -> Result<PAMSet> {
+        let mut pams = Vec::new();
+
+        for item in text.split(',') {
+            let item = item.trim();
+            let (pam, weight) = match item.find(':') {
+                Some(idx) => match item[idx + 1..].trim().parse::<f64>() {
+                    Ok(weight) => (item[..idx].trim(), weight),
+                    Err(err) => {
+                        return Err(format!("invalid PAM weight {:?}: {}", item, err).into())
+                    }
+                },
+                None => (item, 1.0),
+            };
+
+            pams.push((
+                PAM::new(pam.to_ascii_uppercase().as_bytes(), position),
+                weight,
+            ));
+        }
+
+        Ok(Self::weighted(pams).expect("split always yields at least one item"))
+    }
+
+    /// Sets the length of the K-mer adjacent to the PAMs.
+    pub fn with_kmer_len(mut self, kmer_len: usize) -> PAMSet {
+        self.pams = self
+            .pams
+            .into_iter()
+            .map(|pam| pam.with_kmer_len(kmer_len))
+            .collect();
+        self
+    }
+
+    pub fn pams(&self) -> &[PAM] {
+        &self.pams
+    }
+
+    pub fn weights(&self) -> &[f64] {
+        &self.weights
+    }
+
+    pub fn weight(&self, pam: usize) -> f64 {
+        self.weights[pam]
+    }
+
+    pub fn position(&self) -> Position {
+        self.pams[0].position()
+    }
+
+    pub fn len(&self) -> usize {
+        self.pams[0].len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pams[0].is_empty()
+    }
+
+    pub fn kmer_len(&self) -> usize {
+        self.pams[0].kmer_len()
+    }
+
+    /// Returns the index of the first PAM matching the window, if any.
+    pub fn find(&self, window: &[u8]) -> Option<usize> {
+        self.pams.iter().position(|pam| pam.matches(window))
+    }
+
+    pub fn matches(&self, window: &[u8]) -> bool {
+        self.find(window).is_some()
+    }
+
+    /// Returns the index of the matching PAM, the position of that PAM, and the kmer.
+    pub fn kmer(&self, window: &[u8]) -> Option<(usize, usize, KMer)> {
+        let idx = self.find(window)?;
+        let (pos, kmer) = self.pams[idx].kmer(window)?;
+
+        Some((idx, pos, kmer))
+    }
+
+    pub fn kmer_slice<'a>(&self, seq: &'a [u8]) -> &'a [u8] {
+        self.pams[0].kmer_slice(seq)
+    }
+
+    pub fn kmer_slice_mut<'a>(&self, seq: &'a mut [u8]) -> &'a mut [u8] {
+        self.pams[0].kmer_slice_mut(seq)
+    }
+}
+
+impl fmt::Display for PAMSet {
+    /// Formats the PAMs as a comma separated list, with weights for non-canonical PAMs (see
+    /// `PAMSet::parse`)
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (idx, (pam, weight)) in self.pams.iter().zip(self.weights.iter()).enumerate() {
+            if idx > 0 {
+                write!(f, ",")?;
+            }
+
+            if (*weight - 1.0).abs() < f64::EPSILON {
+                write!(f, "{}", pam.to_string())?;
+            } else {
+                write!(f, "{}:{}", pam.to_string(), weight)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl From<PAM> for PAMSet {
+    fn from(pam: PAM) -> PAMSet {
+        PAMSet::new(pam)
+    }
+}
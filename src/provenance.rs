@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+
+use bio::io::fasta::IndexedReader;
+
+use crate::errors::*;
+use crate::fasta::FastaFile;
+use crate::filter::SequenceFilter;
+
+/// The FASTA file used to build (part of) an index, along with the version of CRISPyR and the
+/// command used to build it, and the filter restricting the sequences and regions indexed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Source {
+    pub fasta: String,
+    pub version: String,
+    pub command: String,
+    pub filter: SequenceFilter,
+}
+
+impl Source {
+    /// Creates a source for the given FASTA file using the current version and command-line.
+    pub fn new(fasta: &str) -> Source {
+        let fasta = match std::fs::canonicalize(fasta) {
+            Ok(path) => path.to_string_lossy().into_owned(),
+            Err(_) => fasta.to_owned(),
+        };
+
+        Source {
+            fasta,
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            command: std::env::args().collect::<Vec<_>>().join(" "),
+            filter: SequenceFilter::default(),
+        }
+    }
+
+    pub fn with_filter(mut self, filter: SequenceFilter) -> Source {
+        self.filter = filter;
+        self
+    }
+}
+
+/// Length and MD5 digest of an indexed sequence. The digest is calculated from the upper-case
+/// sequence, as for the M5 tag in SAM headers.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SequenceInfo {
+    pub len: u64,
+    pub md5: [u8; 16],
+}
+
+impl SequenceInfo {
+    /// Calculates the length and MD5 digest of an (upper-case) sequence.
+    pub fn new(sequence: &[u8]) -> SequenceInfo {
+        SequenceInfo {
+            len: sequence.len() as u64,
+            md5: md5::compute(sequence).0,
+        }
+    }
+
+    pub fn md5_hex(&self) -> String {
+        format!("{:x}", md5::Digest(self.md5))
+    }
+}
+
+/// Record of how an index was built. Sequence information is either missing or available
+/// for every indexed sequence, in the order of the sequences in the index.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Provenance {
+    pub sources: Vec<Source>,
+    pub sequences: Vec<SequenceInfo>,
+}
+
+impl Provenance {
+    /// Verifies that sequences in an indexed FASTA file match the indexed sequences with the
+    /// same names, by comparing their lengths and, if `checksums` is set, their MD5 digests.
+    /// Sequences not found in both are ignored, but it is an error if the FASTA file contains none
+    /// of the indexed sequences. Returns the number of sequences compared.
+    pub fn verify(
+        &self,
+        refseqs: &[String],
+        reader: &mut IndexedReader<FastaFile>,
+        fasta: &str,
+        checksums: bool,
+    ) -> Result<usize> {
+        let lengths: HashMap<String, u64> = reader
+            .index
+            .sequences()
+            .into_iter()
+            .map(|seq| (seq.name, seq.len))
+            .collect();
+
+        if !refseqs.is_empty() && !refseqs.iter().any(|name| lengths.contains_key(name)) {
+            return Err(
+                format!("FASTA file {:?} shares no sequences with the index", fasta).into(),
+            );
+        }
+
+        let mut verified = 0;
+        let mut sequence = Vec::new();
+        for (name, info) in refseqs.iter().zip(self.sequences.iter()) {
+            let len = match lengths.get(name) {
+                Some(&len) => len,
+                None => continue,
+            };
+
+            let mismatch = if len != info.len {
+                Some(format!("length is {} bp, not {} bp", len, info.len))
+            } else if checksums {
+                reader
+                    .fetch_all(name)
+                    .chain_err(|| format!("failed to fetch sequence {:?}", name))?;
+                reader
+                    .read(&mut sequence)
+                    .chain_err(|| format!("failed to read sequence {:?}", name))?;
+                sequence.make_ascii_uppercase();
+
+                let observed = SequenceInfo::new(&sequence);
+                if observed.md5 != info.md5 {
+                    Some(format!(
+                        "MD5 digest is {}, not {}",
+                        observed.md5_hex(),
+                        info.md5_hex()
+                    ))
+                } else {
+                    None
+                }
+            } else {
+                None
+            };
+
+            if let Some(mismatch) = mismatch {
+                return Err(format!(
+                    "sequence {:?} in {:?} does not match the indexed sequence ({}); the FASTA \
+                     file may be from a different assembly than the index",
+                    name, fasta, mismatch
+                )
+                .into());
+            }
+
+            verified += 1;
+        }
+
+        Ok(verified)
+    }
+}
@@ -1,12 +1,23 @@
+use std::fmt::{self, Debug};
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+
+use crate::cfd::CFD;
 use crate::common::KMer;
-use crate::constants::*;
+use crate::errors::*;
 use crate::index::{KMerIndex, Position};
+use crate::mit::MIT;
 
-pub const MAX_MUTATIONS: usize = 3;
-pub const MAX_SEED_MUTATIONS: usize = 2;
-pub const SEED_SIZE: usize = 5;
+pub const DEFAULT_MAX_MUTATIONS: usize = 3;
+pub const DEFAULT_MAX_SEED_MUTATIONS: usize = 2;
+pub const DEFAULT_SEED_SIZE: usize = 5;
+/// Upper limit for `ScoringProfile::max_mutations`; the number of K-mers enumerated for each
+/// gRNA grows combinatorially with the number of mismatches (about 380,000 for 13-mers and 5
+/// mismatches, and 49 million for 32-mers)
+pub const MAX_MUTATIONS_LIMIT: usize = 5;
 
-// Mismatch offtarget scoring matrix
+// Default mismatch offtarget scoring matrix
 // seed mismatches on column and non-seed mismatches on row:
 // 		0						1						2					3				4
 // 0	0 seed and 0 non-seed	0 seed and 1 non-seed	0 seed 2 non-seed	0 seed 3 non    0 seed 4 non
@@ -23,49 +34,217 @@ pub const SEED_SIZE: usize = 5;
 // Depending on your use-case you might want to change this. E.g. you could imagine you wanted
 // to perform a test of gRNA edits at all offtargets and for that reason you wanted as few
 // potential offtargets as possible. Then you might set the score of all situations to 1 and
-// then just pick the gRNA with the lowest score.
+// then just pick the gRNA with the lowest score. See `ScoringProfile`.
 static SCORE_MATRIX: [[u64; 5]; 3] = [[500, 100, 50, 20, 3], [80, 30, 15, 2, 0], [20, 5, 1, 0, 0]];
 
+/// Scoring matrix, seed size, and mismatch limits used to enumerate and score off-targets.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScoringProfile {
+    /// Scores indexed by the number of seed mismatches and then by the number of non-seed
+    /// mismatches
+    pub matrix: Vec<Vec<u64>>,
+    /// Number of nucleotides closest to the PAM considered part of the seed
+    pub seed_size: usize,
+    /// Maximum number of mismatches (seed and non-seed) in off-targets
+    pub max_mutations: usize,
+    /// Maximum number of seed mismatches in off-targets
+    pub max_seed_mutations: usize,
+}
+
+impl Default for ScoringProfile {
+    fn default() -> ScoringProfile {
+        ScoringProfile {
+            matrix: SCORE_MATRIX.iter().map(|row| row.to_vec()).collect(),
+            seed_size: DEFAULT_SEED_SIZE,
+            max_mutations: DEFAULT_MAX_MUTATIONS,
+            max_seed_mutations: DEFAULT_MAX_SEED_MUTATIONS,
+        }
+    }
+}
+
+impl ScoringProfile {
+    /// Reads a scoring profile from a file; see `ScoringProfile::parse` for the format.
+    pub fn from_file<P: AsRef<Path> + Debug>(filename: &P) -> Result<ScoringProfile> {
+        let mut text = String::new();
+        File::open(filename)
+            .and_then(|mut file| file.read_to_string(&mut text))
+            .chain_err(|| format!("failed to read scoring profile {:?}", filename))?;
+
+        Self::parse(&text).chain_err(|| format!("invalid scoring profile in {:?}", filename))
+    }
+
+    /// Parses a scoring profile consisting of `key = value` lines, with the keys `seed_size`,
+    /// `max_mutations`, `max_seed_mutations`, and `matrix` (see `ScoringProfile::parse_matrix`).
+    /// Keys that are not specified take their default values. Empty lines and lines starting
+    /// with '#' are ignored.
+    pub fn parse(text: &str) -> Result<ScoringProfile> {
+        let mut profile = ScoringProfile::default();
+
+        for (linenum, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (key, value) = match line.find('=') {
+                Some(idx) => (line[..idx].trim(), line[idx + 1..].trim()),
+                None => {
+                    return Err(format!("expected 'key = value' at line {}", linenum + 1).into())
+                }
+            };
+
+            match key.to_ascii_lowercase().as_ref() {
+                "seed_size" => profile.seed_size = Self::parse_value(key, value)?,
+                "max_mutations" => profile.max_mutations = Self::parse_value(key, value)?,
+                "max_seed_mutations" => profile.max_seed_mutations = Self::parse_value(key, value)?,
+                "matrix" => profile.matrix = Self::parse_matrix(value)?,
+                _ => return Err(format!("unknown key {:?} at line {}", key, linenum + 1).into()),
+            }
+        }
+
+        profile.validate()?;
+
+        Ok(profile)
+    }
+
+    fn parse_value(key: &str, value: &str) -> Result<usize> {
+        match value.parse::<usize>() {
+            Ok(value) => Ok(value),
+            Err(err) => Err(format!("invalid {} {:?}: {}", key, value, err).into()),
+        }
+    }
+
+    /// Parses a scoring matrix consisting of rows separated by ';', each row consisting of
+    /// scores separated by ','; for example "500,100,50,20; 80,30,15,2; 20,5,1,0".
+    pub fn parse_matrix(text: &str) -> Result<Vec<Vec<u64>>> {
+        let mut matrix = Vec::new();
+        for row in text.split(';') {
+            let mut values = Vec::new();
+            for value in row.split(',') {
+                match value.trim().parse::<u64>() {
+                    Ok(value) => values.push(value),
+                    Err(err) => {
+                        return Err(format!("invalid score {:?} in matrix: {}", value, err).into())
+                    }
+                }
+            }
+
+            matrix.push(values);
+        }
+
+        Ok(matrix)
+    }
+
+    /// Checks that the matrix contains a score for every possible combination of seed and
+    /// non-seed mismatches.
+    pub fn validate(&self) -> Result<()> {
+        if self.max_mutations > MAX_MUTATIONS_LIMIT {
+            return Err(format!("max_mutations must be at most {}", MAX_MUTATIONS_LIMIT).into());
+        } else if self.max_seed_mutations > self.max_mutations {
+            return Err("max_seed_mutations must not be greater than max_mutations".into());
+        } else if self.matrix.len() <= self.max_seed_mutations {
+            return Err(format!(
+                "scoring matrix must have at least {} rows (max_seed_mutations + 1)",
+                self.max_seed_mutations + 1
+            )
+            .into());
+        }
+
+        for (n_seed, row) in self.matrix.iter().enumerate() {
+            if n_seed <= self.max_seed_mutations && row.len() <= self.max_mutations - n_seed {
+                return Err(format!(
+                    "scoring matrix must have at least {} scores for {} seed mismatches",
+                    self.max_mutations - n_seed + 1,
+                    n_seed
+                )
+                .into());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the profile formatted as comment lines for use in output headers. Nothing is
+    /// returned for the default profile, so that the output format only changes if a custom
+    /// profile is used.
+    pub fn to_header(&self) -> String {
+        if *self == ScoringProfile::default() {
+            return String::new();
+        }
+
+        self.to_string()
+            .lines()
+            .map(|line| format!("# {}\n", line))
+            .collect()
+    }
+
+    fn score(&self, n_seed: usize, n_rest: usize) -> u64 {
+        self.matrix[n_seed][n_rest]
+    }
+}
+
+impl fmt::Display for ScoringProfile {
+    /// Formats the profile as `key = value` lines, as accepted by `ScoringProfile::parse`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let matrix: Vec<_> = self
+            .matrix
+            .iter()
+            .map(|row| {
+                let row: Vec<_> = row.iter().map(|v| v.to_string()).collect();
+                row.join(",")
+            })
+            .collect();
+
+        writeln!(f, "seed_size = {}", self.seed_size)?;
+        writeln!(f, "max_mutations = {}", self.max_mutations)?;
+        writeln!(f, "max_seed_mutations = {}", self.max_seed_mutations)?;
+        writeln!(f, "matrix = {}", matrix.join("; "))
+    }
+}
+
+#[derive(Clone, Copy)]
 struct Permutation {
     kmer: KMer,
+    /// Total number of mismatches relative to the query K-mer in and outside of the seed
     n_seed: usize,
     n_rest: usize,
-}
-
-impl Permutation {
-    fn score(&self) -> u64 {
-        SCORE_MATRIX[self.n_seed][self.n_rest]
-    }
+    /// Bit-mask of every position at which the K-mer differs from the query K-mer, i.e. the
+    /// mismatches inherited from the permutation this one was derived from plus the mismatch
+    /// introduced by this permutation; the number of set bits is `n_seed + n_rest`. The lowest
+    /// bit represents the first nucleotide of the K-mer (i.e. the nucleotide closest to 3' PAMs)
+    mismatches: u64,
 }
 
 fn permute(
     permutations: &mut Vec<Permutation>,
-    kmer: KMer,
-    seed_muts: usize,
-    rest_muts: usize,
+    profile: &ScoringProfile,
+    kmer_len: usize,
+    parent: Permutation,
     pos: usize,
 ) {
-    if seed_muts + rest_muts < MAX_MUTATIONS {
-        for pos in pos..KMER_LEN {
-            let (seed_muts, rest_muts) = if pos < SEED_SIZE {
-                (seed_muts + 1, rest_muts)
+    if parent.n_seed + parent.n_rest < profile.max_mutations {
+        for pos in pos..kmer_len {
+            let (n_seed, n_rest) = if pos < profile.seed_size {
+                (parent.n_seed + 1, parent.n_rest)
             } else {
-                (seed_muts, rest_muts + 1)
+                (parent.n_seed, parent.n_rest + 1)
             };
 
-            if seed_muts <= MAX_SEED_MUTATIONS {
-                let current_nucleotide = (kmer.0 >> (2 * pos)) & 3;
-                let kmer_template = kmer.0 & !(kmer.0 & (3 << (2 * pos)));
+            if n_seed <= profile.max_seed_mutations {
+                let kmer = parent.kmer.0;
+                let current_nucleotide = (kmer >> (2 * pos)) & 3;
+                let kmer_template = kmer & !(kmer & (3 << (2 * pos)));
                 for nucleotide in 0..4 {
                     if nucleotide != current_nucleotide {
-                        let new_kmer = KMer::new(kmer_template | (nucleotide << (2 * pos)));
-                        permutations.push(Permutation {
-                            kmer: new_kmer,
-                            n_seed: seed_muts,
-                            n_rest: rest_muts,
-                        });
+                        let permutation = Permutation {
+                            kmer: KMer::new(kmer_template | (nucleotide << (2 * pos))),
+                            n_seed,
+                            n_rest,
+                            mismatches: parent.mismatches | (1 << pos),
+                        };
 
-                        permute(permutations, new_kmer, seed_muts, rest_muts, pos + 1);
+                        permutations.push(permutation);
+                        permute(permutations, profile, kmer_len, permutation, pos + 1);
                     }
                 }
             }
@@ -73,39 +252,136 @@ fn permute(
     }
 }
 
-fn permutations(kmer: KMer) -> Vec<Permutation> {
-    let mut permutations = Vec::with_capacity(9 * 1024);
-    permutations.push(Permutation {
+fn permutations(profile: &ScoringProfile, kmer: KMer, kmer_len: usize) -> Vec<Permutation> {
+    let permutation = Permutation {
         kmer,
         n_seed: 0,
         n_rest: 0,
-    });
+        mismatches: 0,
+    };
 
-    permute(&mut permutations, kmer, 0, 0, 0);
+    let mut permutations = Vec::with_capacity(9 * 1024);
+    permutations.push(permutation);
+    permute(&mut permutations, profile, kmer_len, permutation, 0);
     permutations
 }
 
-pub fn calculate_score(index: &KMerIndex, kmer: KMer) -> u64 {
-    let mut score = 0;
-    for permutation in permutations(kmer) {
-        if let Some(count) = index.get_count(permutation.kmer) {
-            score += u64::from(count) * permutation.score();
+/// Calculates the CRISPy score for a K-mer, scaling the contribution of each off-target by the
+/// weight of the PAM at that site.
+pub fn calculate_score(index: &KMerIndex, profile: &ScoringProfile, kmer: KMer) -> u64 {
+    let pams = &index.enzyme().pams;
+
+    let mut score = 0.0;
+    for permutation in permutations(profile, kmer, pams.kmer_len()) {
+        let permutation_score = profile.score(permutation.n_seed, permutation.n_rest);
+
+        for (pam, weight) in pams.weights().iter().enumerate() {
+            if let Some(count) = index.get_count(pam, permutation.kmer) {
+                score += f64::from(count) * permutation_score as f64 * weight;
+            }
         }
     }
 
-    score
+    score.round() as u64
 }
 
-pub fn find_offtargets(index: &KMerIndex, kmer: KMer, min_score: u64) -> Vec<(u64, &Position)> {
+/// Calculates the CFD specificity score (0-100) for a K-mer, as the inverse of the sum of CFD
+/// scores for all off-targets. The on-target site (a perfect match with a canonical PAM) is not
+/// counted.
+pub fn calculate_cfd_specificity(
+    index: &KMerIndex,
+    profile: &ScoringProfile,
+    cfd: &CFD,
+    kmer: KMer,
+) -> f64 {
+    let pams = &index.enzyme().pams;
+
+    let mut total = 0.0;
+    let mut on_target = false;
+    for permutation in permutations(profile, kmer, pams.kmer_len()) {
+        for pam in 0..pams.pams().len() {
+            if let Some(count) = index.get_count(pam, permutation.kmer) {
+                let score = cfd.score(kmer, permutation.kmer, pam);
+
+                if permutation.kmer == kmer && score >= 1.0 {
+                    on_target = true;
+                }
+
+                total += f64::from(count) * score;
+            }
+        }
+    }
+
+    if on_target {
+        total -= 1.0;
+    }
+
+    100.0 / (1.0 + total.max(0.0))
+}
+
+/// Calculates the MIT specificity score (0-100) for a K-mer, based on the sum of MIT hit scores
+/// for all off-targets. The on-target site (a perfect match) is not counted.
+pub fn calculate_mit_specificity(
+    index: &KMerIndex,
+    profile: &ScoringProfile,
+    mit: &MIT,
+    kmer: KMer,
+) -> f64 {
+    let pams = &index.enzyme().pams;
+
+    let mut total = 0.0;
+    let mut on_target = false;
+    for permutation in permutations(profile, kmer, pams.kmer_len()) {
+        for pam in 0..pams.pams().len() {
+            if let Some(count) = index.get_count(pam, permutation.kmer) {
+                on_target |= permutation.mismatches == 0;
+                total += f64::from(count) * mit.score(permutation.mismatches);
+            }
+        }
+    }
+
+    if on_target {
+        total -= 100.0;
+    }
+
+    100.0 * 100.0 / (100.0 + total.max(0.0))
+}
+
+/// Off-target site found by `find_offtargets`
+#[derive(Clone, Debug)]
+pub struct OffTarget<'a> {
+    pub kmer: KMer,
+    /// Bit-mask of all mismatches between the K-mer and the query K-mer; see `MIT::score`
+    pub mismatches: u64,
+    pub score: u64,
+    pub position: &'a Position,
+}
+
+pub fn find_offtargets<'a>(
+    index: &'a KMerIndex,
+    profile: &ScoringProfile,
+    kmer: KMer,
+    min_score: u64,
+) -> Vec<OffTarget<'a>> {
+    let pams = &index.enzyme().pams;
     let mut result = Vec::new();
 
-    for permutation in permutations(kmer) {
-        let score = permutation.score();
+    for permutation in permutations(profile, kmer, pams.kmer_len()) {
+        let score = profile.score(permutation.n_seed, permutation.n_rest);
 
         if score >= min_score {
             if let Some(positions) = index.get_positions(permutation.kmer) {
-                for pos in positions {
-                    result.push((score, pos));
+                for position in positions {
+                    let score = (score as f64 * pams.weight(position.pam())).round() as u64;
+
+                    if score >= min_score {
+                        result.push(OffTarget {
+                            kmer: permutation.kmer,
+                            mismatches: permutation.mismatches,
+                            score,
+                            position,
+                        });
+                    }
                 }
             }
         }
@@ -113,3 +389,197 @@ pub fn find_offtargets(index: &KMerIndex, kmer: KMer, min_score: u64) -> Vec<(u6
 
     result
 }
+
+/// Models used to score gRNAs and their off-targets
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Model {
+    /// The CRISPy score; see `SCORE_MATRIX`
+    CRISPy,
+    /// Cutting Frequency Determination score; Doench et al. 2016
+    CFD,
+    /// MIT specificity score; Hsu et al. 2013
+    MIT,
+}
+
+impl Model {
+    pub fn names() -> &'static [&'static str] {
+        &["crispy", "cfd", "mit"]
+    }
+
+    pub fn parse(name: &str) -> Result<Model> {
+        match name.to_ascii_lowercase().as_ref() {
+            "crispy" => Ok(Model::CRISPy),
+            "cfd" => Ok(Model::CFD),
+            "mit" => Ok(Model::MIT),
+            _ => Err(format!("unknown scoring model {:?}", name).into()),
+        }
+    }
+
+    /// Name of the column containing scores for gRNAs
+    pub fn column(self) -> &'static str {
+        match self {
+            Model::CRISPy => "Score",
+            Model::CFD => "CFDSpecificity",
+            Model::MIT => "MITSpecificity",
+        }
+    }
+
+    /// Name of the column containing scores for individual off-targets
+    pub fn offtarget_column(self) -> &'static str {
+        match self {
+            Model::CRISPy => "Score",
+            Model::CFD => "CFD",
+            Model::MIT => "MIT",
+        }
+    }
+}
+
+/// Scores gRNAs and off-targets using one or more models.
+pub struct Scorer<'a> {
+    index: &'a KMerIndex,
+    profile: ScoringProfile,
+    models: Vec<Model>,
+    cfd: Option<CFD>,
+    mit: Option<MIT>,
+}
+
+impl<'a> Scorer<'a> {
+    /// Returns a scorer for the given models, or an error if a model cannot be used with the
+    /// enzyme for which the index was built or if the profile allows more mismatches than there
+    /// are nucleotides in the K-mers of the index.
+    pub fn new(
+        index: &'a KMerIndex,
+        profile: &ScoringProfile,
+        models: &[Model],
+    ) -> Result<Scorer<'a>> {
+        let kmer_len = index.enzyme().kmer_len();
+        if profile.max_mutations > kmer_len {
+            return Err(format!(
+                "max_mutations ({}) must not be greater than the K-mer length of the index ({})",
+                profile.max_mutations, kmer_len
+            )
+            .into());
+        }
+
+        let cfd = if models.contains(&Model::CFD) {
+            Some(CFD::new(index.enzyme())?)
+        } else {
+            None
+        };
+
+        let mit = if models.contains(&Model::MIT) {
+            Some(MIT::new(index.enzyme())?)
+        } else {
+            None
+        };
+
+        Ok(Scorer {
+            index,
+            profile: profile.clone(),
+            models: models.to_owned(),
+            cfd,
+            mit,
+        })
+    }
+
+    pub fn index(&self) -> &KMerIndex {
+        self.index
+    }
+
+    pub fn profile(&self) -> &ScoringProfile {
+        &self.profile
+    }
+
+    pub fn models(&self) -> &[Model] {
+        &self.models
+    }
+
+    /// Returns the header for columns produced by `Scorer::score`
+    pub fn header(&self) -> String {
+        let columns: Vec<_> = self.models.iter().map(|m| m.column()).collect();
+
+        columns.join("\t")
+    }
+
+    /// Returns the header for columns produced by `Scorer::score_offtarget`
+    pub fn offtarget_header(&self) -> String {
+        let columns: Vec<_> = self.models.iter().map(|m| m.offtarget_column()).collect();
+
+        columns.join("\t")
+    }
+
+    /// Returns the scores for a gRNA K-mer, one per model
+    pub fn scores(&self, kmer: KMer) -> Vec<f64> {
+        self.models
+            .iter()
+            .map(|model| match model {
+                Model::CRISPy => calculate_score(self.index, &self.profile, kmer) as f64,
+                Model::CFD => {
+                    let cfd = self.cfd.as_ref().expect("CFD not initialized");
+
+                    calculate_cfd_specificity(self.index, &self.profile, cfd, kmer)
+                }
+                Model::MIT => {
+                    let mit = self.mit.as_ref().expect("MIT not initialized");
+
+                    calculate_mit_specificity(self.index, &self.profile, mit, kmer)
+                }
+            })
+            .collect()
+    }
+
+    /// Returns tab-separated scores for a gRNA K-mer
+    pub fn score(&self, kmer: KMer) -> String {
+        let scores: Vec<String> = self
+            .models
+            .iter()
+            .zip(self.scores(kmer))
+            .map(|(model, score)| match model {
+                Model::CRISPy => format!("{}", score),
+                Model::CFD | Model::MIT => format!("{:.1}", score),
+            })
+            .collect();
+
+        scores.join("\t")
+    }
+
+    /// Returns the scores for an off-target of a gRNA K-mer, one per model
+    pub fn offtarget_scores(&self, kmer: KMer, offtarget: &OffTarget) -> Vec<f64> {
+        self.models
+            .iter()
+            .map(|model| match model {
+                Model::CRISPy => offtarget.score as f64,
+                Model::CFD => {
+                    let cfd = self.cfd.as_ref().expect("CFD not initialized");
+
+                    cfd.score(kmer, offtarget.kmer, offtarget.position.pam())
+                }
+                Model::MIT => {
+                    let mit = self.mit.as_ref().expect("MIT not initialized");
+
+                    mit.score(offtarget.mismatches)
+                }
+            })
+            .collect()
+    }
+
+    /// Returns tab-separated scores for an off-target of a gRNA K-mer
+    pub fn score_offtarget(&self, kmer: KMer, offtarget: &OffTarget) -> String {
+        Self::format_offtarget_scores(&self.models, &self.offtarget_scores(kmer, offtarget))
+    }
+
+    /// Formats scores returned by `offtarget_scores` as tab-separated values
+    pub fn format_offtarget_scores(models: &[Model], scores: &[f64]) -> String {
+        let scores: Vec<String> = models
+            .iter()
+            .zip(scores)
+            .map(|(model, score)| match model {
+                Model::CRISPy => format!("{}", score),
+                Model::CFD => format!("{:.3}", score),
+                Model::MIT => format!("{:.1}", score),
+            })
+            .collect();
+
+        scores.join("\t")
+    }
+}
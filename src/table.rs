@@ -13,7 +13,8 @@ pub fn read<P: AsRef<Path> + Debug>(path: &P) -> Result<Vec<Vec<String>>> {
     let mut table: Vec<Vec<String>> = Vec::new();
     for line in reader.lines() {
         let line = line.chain_err(|| "error reading line from table")?;
-        if !line.trim().is_empty() {
+        // Skip empty lines and comments (e.g. headers written by CRISPyR)
+        if !line.trim().is_empty() && !line.starts_with('#') {
             table.push(line.split('\t').map(|v| v.to_string()).collect());
         }
     }
@@ -0,0 +1,118 @@
+use std::cmp::Ordering;
+
+/// A sequence packed using 2 bits per base (A, C, G, and T). Runs of other bases are stored
+/// separately and are reported as N; soft-masking is not preserved.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TwoBitRecord {
+    pub len: u64,
+    /// Runs (start, length) of bases other than A, C, G, and T
+    pub runs: Vec<(u64, u64)>,
+    /// Byte offset of the packed bases in the block of packed sequences
+    pub offset: u64,
+}
+
+impl TwoBitRecord {
+    /// Packs a sequence, returning the record (with offset 0) and the packed bases
+    pub fn pack(sequence: &[u8]) -> (TwoBitRecord, Vec<u8>) {
+        let mut runs: Vec<(u64, u64)> = Vec::new();
+        let mut packed = vec![0; packed_len(sequence.len() as u64)];
+
+        for (idx, nuc) in sequence.iter().enumerate() {
+            let value = match nuc {
+                b'a' | b'A' => 0,
+                b'c' | b'C' => 1,
+                b'g' | b'G' => 2,
+                b't' | b'T' => 3,
+                _ => {
+                    match runs.last_mut() {
+                        Some((start, len)) if *start + *len == idx as u64 => *len += 1,
+                        _ => runs.push((idx as u64, 1)),
+                    }
+
+                    0
+                }
+            };
+
+            packed[idx / 4] |= value << (6 - 2 * (idx % 4));
+        }
+
+        let record = TwoBitRecord {
+            len: sequence.len() as u64,
+            runs,
+            offset: 0,
+        };
+
+        (record, packed)
+    }
+
+    /// Returns the packed bases of this sequence in the block of packed sequences
+    pub fn packed<'a>(&self, data: &'a [u8]) -> &'a [u8] {
+        let offset = self.offset as usize;
+
+        &data[offset..offset + packed_len(self.len)]
+    }
+
+    /// Returns the (upper-case) bases from `start` to `end` (0-based, exclusive), using the block
+    /// of packed sequences in which this sequence is stored.
+    pub fn fetch(&self, data: &[u8], start: u64, end: u64) -> Vec<u8> {
+        assert!(start <= end && end <= self.len);
+
+        let packed = self.packed(data);
+        let mut sequence: Vec<u8> = (start..end)
+            .map(|idx| {
+                let value = packed[(idx / 4) as usize] >> (6 - 2 * (idx % 4));
+                b"ACGT"[(value & 3) as usize]
+            })
+            .collect();
+
+        // Runs are sorted and non-overlapping; skip runs ending before the start of the range
+        let first = self
+            .runs
+            .binary_search_by(|&(run_start, run_len)| {
+                if run_start + run_len <= start {
+                    Ordering::Less
+                } else {
+                    Ordering::Greater
+                }
+            })
+            .unwrap_or_else(|idx| idx);
+
+        for &(run_start, run_len) in &self.runs[first..] {
+            if run_start >= end {
+                break;
+            }
+
+            let from = (u64::max(run_start, start) - start) as usize;
+            let to = (u64::min(run_start + run_len, end) - start) as usize;
+            for nuc in &mut sequence[from..to] {
+                *nuc = b'N';
+            }
+        }
+
+        sequence
+    }
+}
+
+/// Returns the number of bytes required to pack a sequence of the given length (4 bases per
+/// byte, rounded up)
+pub fn packed_len(len: u64) -> usize {
+    ((len + 3) >> 2) as usize
+}
+
+/// A collection of packed sequences, stored as a single block of packed bases
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TwoBitSequences {
+    pub records: Vec<TwoBitRecord>,
+    pub data: Vec<u8>,
+}
+
+impl TwoBitSequences {
+    /// Adds a packed sequence, updating the offset of the record
+    pub fn push(&mut self, mut record: TwoBitRecord, packed: &[u8]) {
+        assert_eq!(packed.len(), packed_len(record.len));
+
+        record.offset = self.data.len() as u64;
+        self.records.push(record);
+        self.data.extend_from_slice(packed);
+    }
+}
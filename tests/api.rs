@@ -0,0 +1,236 @@
+extern crate bio;
+extern crate bio_types;
+extern crate crispyr;
+
+use bio::alphabets::dna;
+use bio_types::strand::Strand;
+
+use crispyr::api::{
+    find_targets, guide_kmer, offtargets, score_guide, spacer_mismatches, IndexBuilder,
+    IndexOptions,
+};
+use crispyr::common::encode_dna;
+use crispyr::enzyme::Enzyme;
+use crispyr::score::{Model, Scorer, ScoringProfile};
+
+mod fixtures;
+use fixtures::{flanked, GUIDE, VARIANT};
+
+fn build_index(positions: bool, embed: bool) -> crispyr::index::KMerIndex {
+    let options = IndexOptions {
+        positions,
+        embed_sequences: embed,
+        ..Default::default()
+    };
+
+    fixtures::build_index(&[GUIDE, &dna::revcomp(VARIANT)], options)
+}
+
+#[test]
+fn test_index_builder() {
+    let index = build_index(true, true);
+
+    assert_eq!(index.enzyme(), &Enzyme::cas9());
+    assert_eq!(index.refseqs(), &["chr1".to_string(), "chr2".to_string()]);
+    assert!(index.has_positions());
+    assert!(index.has_sequences());
+    assert_eq!(index.kmer_count(), 1);
+    assert_eq!(index.fetch_sequence(0, 6, 29), Some(GUIDE.to_vec()));
+
+    let kmer = guide_kmer(&Enzyme::cas9(), GUIDE).unwrap();
+    assert_eq!(index.get_positions(kmer).map(|v| v.len()), Some(2));
+}
+
+#[test]
+fn test_index_builder_errors() {
+    let mut builder = IndexBuilder::new(&Enzyme::cas9());
+    builder.add("chr1", GUIDE).unwrap();
+    assert!(builder.add("chr1", GUIDE).is_err());
+
+    let options = IndexOptions {
+        embed_sequences: true,
+        ..Default::default()
+    };
+    let builder = IndexBuilder::with_options(&Enzyme::cas9(), options);
+    assert!(builder.build().is_err());
+}
+
+#[test]
+fn test_find_targets() {
+    let mut sequence = flanked(GUIDE);
+    sequence.extend(dna::revcomp(VARIANT).to_ascii_lowercase());
+    sequence.extend(b"ATATAT");
+
+    let sites = find_targets(&Enzyme::cas9(), &sequence);
+    assert_eq!(sites.len(), 2);
+
+    assert_eq!(sites[0].start, 6);
+    assert_eq!(sites[0].end, 29);
+    assert_eq!(sites[0].cutsite, 23);
+    assert_eq!(sites[0].strand, Strand::Forward);
+    assert_eq!(sites[0].sequence, GUIDE);
+    assert!(!sites[0].masked);
+
+    assert_eq!(sites[1].start, 35);
+    assert_eq!(sites[1].end, 58);
+    assert_eq!(sites[1].cutsite, 41);
+    assert_eq!(sites[1].strand, Strand::Reverse);
+    assert_eq!(sites[1].sequence, VARIANT);
+    assert!(sites[1].masked);
+}
+
+#[test]
+fn test_target_site_context() {
+    let sequence = flanked(&dna::revcomp(GUIDE));
+    let sites = find_targets(&Enzyme::cas9(), &sequence);
+    assert_eq!(sites.len(), 1);
+
+    let mut expected = b"ATAT".to_vec();
+    expected.extend(GUIDE);
+    expected.extend(b"ATATAT");
+    assert_eq!(sites[0].context(&sequence, 4, 6), Some(expected));
+    assert_eq!(sites[0].context(&sequence, 4, 7), None);
+}
+
+#[test]
+fn test_guide_kmer() {
+    let enzyme = Enzyme::cas9();
+
+    assert_eq!(guide_kmer(&enzyme, GUIDE), encode_dna(&GUIDE[7..20]));
+    assert_eq!(
+        guide_kmer(&enzyme, &GUIDE.to_ascii_lowercase()),
+        guide_kmer(&enzyme, GUIDE)
+    );
+    assert_eq!(guide_kmer(&enzyme, b"GATCACTGATCAGTACTGATTAA"), None);
+    assert_eq!(guide_kmer(&enzyme, b"GATCACTGATCNGTACTGATTGG"), None);
+    assert_eq!(guide_kmer(&enzyme, b"TGG"), None);
+}
+
+#[test]
+fn test_score_guide() {
+    let index = build_index(false, false);
+    let scorer = Scorer::new(
+        &index,
+        &ScoringProfile::default(),
+        &[Model::CRISPy, Model::CFD],
+    )
+    .unwrap();
+
+    let scores = score_guide(&scorer, GUIDE).unwrap();
+    assert_eq!(scores.len(), 2);
+    assert_eq!(scores[0], 1000.0);
+
+    assert!(score_guide(&scorer, b"GATCACTGATCAGTACTGATTAA").is_err());
+}
+
+#[test]
+fn test_offtargets() {
+    let index = build_index(true, true);
+    let scorer = Scorer::new(&index, &ScoringProfile::default(), &[Model::CRISPy]).unwrap();
+
+    let mut sites: Vec<_> = offtargets(&scorer, GUIDE, 0).unwrap().collect();
+    sites.sort_by_key(|v| v.name);
+    assert_eq!(sites.len(), 2);
+
+    assert_eq!(sites[0].name, "chr1");
+    assert_eq!((sites[0].start, sites[0].end), (6, 29));
+    assert_eq!(sites[0].cutsite, 23);
+    assert_eq!(sites[0].strand, '+');
+    assert_eq!(sites[0].sequence.as_deref(), Some(GUIDE));
+    assert_eq!(sites[0].spacer_mismatches, Some(vec![]));
+    assert_eq!(sites[0].scores, vec![500.0]);
+
+    assert_eq!(sites[1].name, "chr2");
+    assert_eq!((sites[1].start, sites[1].end), (6, 29));
+    assert_eq!(sites[1].cutsite, 12);
+    assert_eq!(sites[1].strand, '-');
+    assert_eq!(sites[1].sequence.as_deref(), Some(VARIANT));
+    assert_eq!(sites[1].spacer_mismatches, Some(vec![1]));
+}
+
+#[test]
+fn test_offtargets_without_sequences() {
+    let index = build_index(true, false);
+    let scorer = Scorer::new(&index, &ScoringProfile::default(), &[Model::CRISPy]).unwrap();
+
+    for site in offtargets(&scorer, GUIDE, 0).unwrap() {
+        assert_eq!(site.sequence, None);
+        assert_eq!(site.spacer_mismatches, None);
+    }
+}
+
+#[test]
+fn test_offtargets_without_positions() {
+    let index = build_index(false, false);
+    let scorer = Scorer::new(&index, &ScoringProfile::default(), &[Model::CRISPy]).unwrap();
+
+    assert!(offtargets(&scorer, GUIDE, 0).is_err());
+}
+
+#[test]
+fn test_spacer_mismatches() {
+    let cas9 = Enzyme::cas9();
+
+    assert_eq!(spacer_mismatches(&cas9, GUIDE, GUIDE), Some(vec![]));
+    assert_eq!(spacer_mismatches(&cas9, GUIDE, VARIANT), Some(vec![1]));
+    assert_eq!(
+        spacer_mismatches(&cas9, GUIDE, b"GATCACTGATCAGTACTGATTAG"),
+        Some(vec![])
+    );
+    assert_eq!(
+        spacer_mismatches(&cas9, GUIDE, b"gatcactgatcagtNctgaacgg"),
+        Some(vec![15, 20])
+    );
+    assert_eq!(spacer_mismatches(&cas9, GUIDE, &GUIDE[1..]), None);
+
+    let cas12a = Enzyme::ascas12a();
+    let guide = b"TTTAGATCACTGATCAGTACTGATTGC";
+    let site = b"TTTCAATCACTGATCAGTACTGATTGA";
+    assert_eq!(spacer_mismatches(&cas12a, guide, site), Some(vec![1, 23]));
+}
+
+/// Returns the number of sites of a target (gRNA and PAM) found when excluding soft-masked
+/// sites, with the target flanked by 6 bp and the base at `masked` soft-masked
+fn unmasked_sites(enzyme: &Enzyme, target: &[u8], forward: bool, masked: usize) -> usize {
+    let mut sequence = if forward {
+        flanked(target)
+    } else {
+        flanked(&dna::revcomp(target))
+    };
+    sequence[masked] = sequence[masked].to_ascii_lowercase();
+
+    let options = IndexOptions {
+        positions: true,
+        exclude_masked: true,
+        ..Default::default()
+    };
+    let mut builder = IndexBuilder::with_options(enzyme, options);
+    builder.add("chr1", &sequence).unwrap();
+    let index = builder.build().unwrap();
+
+    let kmer = guide_kmer(enzyme, target).unwrap();
+    index.get_positions(kmer).map_or(0, |v| v.len())
+}
+
+fn check_masked_sites(enzyme: &Enzyme, target: &[u8]) {
+    let (start, end) = (6, 6 + target.len());
+
+    for &forward in &[true, false] {
+        // Bases flanking the target site
+        assert_eq!(unmasked_sites(enzyme, target, forward, start - 1), 1);
+        assert_eq!(unmasked_sites(enzyme, target, forward, end), 1);
+        // The first and last bases of the target site
+        assert_eq!(unmasked_sites(enzyme, target, forward, start), 0);
+        assert_eq!(unmasked_sites(enzyme, target, forward, end - 1), 0);
+    }
+}
+
+#[test]
+fn test_index_builder_exclude_masked_tail_pam() {
+    check_masked_sites(&Enzyme::cas9(), GUIDE);
+}
+
+#[test]
+fn test_index_builder_exclude_masked_head_pam() {
+    check_masked_sites(&Enzyme::ascas12a(), b"TTTAAATCACTGATCAGTACTGATTGC");
+}
@@ -0,0 +1,50 @@
+extern crate crispyr;
+
+use crispyr::cfd::CFD;
+use crispyr::common::encode_dna;
+use crispyr::enzyme::Enzyme;
+
+#[test]
+fn test_cfd_requires_3bp_3prime_pam() {
+    assert!(CFD::new(&Enzyme::cas9()).is_ok());
+    assert!(CFD::new(&Enzyme::cas9_nc()).is_ok());
+    assert!(CFD::new(&Enzyme::sacas9()).is_err());
+    assert!(CFD::new(&Enzyme::ascas12a()).is_err());
+    assert!(CFD::new(&Enzyme::cas9().with_kmer_len(20)).is_ok());
+}
+
+#[test]
+fn test_cfd_pam_scores() {
+    let cfd = CFD::new(&Enzyme::cas9_nc()).unwrap();
+
+    assert_eq!(cfd.pam_score(0), 1.0);
+    assert!((cfd.pam_score(1) - 0.259259259).abs() < 1e-9);
+    assert!((cfd.pam_score(2) - 0.069444444).abs() < 1e-9);
+}
+
+#[test]
+fn test_cfd_perfect_match() {
+    let cfd = CFD::new(&Enzyme::cas9_nc()).unwrap();
+    let kmer = encode_dna(b"ACTGAGTCAGATA").unwrap();
+
+    assert_eq!(cfd.score(kmer, kmer, 0), 1.0);
+    assert!((cfd.score(kmer, kmer, 1) - 0.259259259).abs() < 1e-9);
+}
+
+#[test]
+fn test_cfd_mismatches() {
+    let cfd = CFD::new(&Enzyme::cas9()).unwrap();
+    let query = encode_dna(b"ACTGAGTCAGATA").unwrap();
+
+    // rA:dA at position 20 (PAM-proximal)
+    let offtarget = encode_dna(b"ACTGAGTCAGATT").unwrap();
+    assert!((cfd.score(query, offtarget, 0) - 0.6).abs() < 1e-9);
+
+    // rG:dG at position 17 is not tolerated
+    let offtarget = encode_dna(b"ACTGAGTCACATA").unwrap();
+    assert_eq!(cfd.score(query, offtarget, 0), 0.0);
+
+    // Scores for multiple mismatches (rU:dT at 19, rA:dA at 20) are multiplied
+    let offtarget = encode_dna(b"ACTGAGTCAGAAT").unwrap();
+    assert!((cfd.score(query, offtarget, 0) - 0.6 * 0.3).abs() < 1e-9);
+}
@@ -0,0 +1,28 @@
+extern crate crispyr;
+
+use crispyr::common::{decode_dna, encode_dna, KMer};
+
+#[test]
+fn test_encode_dna() {
+    assert_eq!(encode_dna(b""), Some(KMer::new(0)));
+    assert_eq!(encode_dna(b"ACGT"), Some(KMer::new(0b00_01_10_11)));
+    assert_eq!(encode_dna(b"acgt"), encode_dna(b"ACGT"));
+    assert_eq!(encode_dna(b"ACNT"), None);
+}
+
+#[test]
+fn test_decode_dna() {
+    assert_eq!(decode_dna(KMer::new(0b00_01_10_11), 4), "ACGT");
+    assert_eq!(decode_dna(KMer::new(0b00_01_10_11), 6), "AAACGT");
+    assert_eq!(decode_dna(KMer::new(0), 0), "");
+}
+
+#[test]
+fn test_decode_encoded_dna() {
+    let seq = b"TAGCTACTAGCTGACTGGATCCATGCAAGTCG";
+
+    for len in 0..=seq.len() {
+        let kmer = encode_dna(&seq[..len]).unwrap();
+        assert_eq!(decode_dna(kmer, len).as_bytes(), &seq[..len]);
+    }
+}
@@ -1,20 +1,21 @@
 extern crate crispyr;
 use crispyr::enzyme::Enzyme;
+use crispyr::pam::{PAMSet, PAM};
 
 #[test]
 fn test_enzyme_cas9() {
-    assert_eq!(Enzyme::get("cas9").map(|v| v.name), Some("Cas9"));
-    assert_eq!(Enzyme::get("Cas9").map(|v| v.name), Some("Cas9"));
-    assert_eq!(Enzyme::get("cAs9").map(|v| v.name), Some("Cas9"));
-    assert_eq!(Enzyme::get("CAS9").map(|v| v.name), Some("Cas9"));
+    assert_eq!(Enzyme::get("cas9").map(|v| v.name).as_deref(), Some("Cas9"));
+    assert_eq!(Enzyme::get("Cas9").map(|v| v.name).as_deref(), Some("Cas9"));
+    assert_eq!(Enzyme::get("cAs9").map(|v| v.name).as_deref(), Some("Cas9"));
+    assert_eq!(Enzyme::get("CAS9").map(|v| v.name).as_deref(), Some("Cas9"));
 }
 
 #[test]
 fn test_enzyme_mad7() {
-    assert_eq!(Enzyme::get("mad7").map(|v| v.name), Some("Mad7"));
-    assert_eq!(Enzyme::get("Mad7").map(|v| v.name), Some("Mad7"));
-    assert_eq!(Enzyme::get("mAD7").map(|v| v.name), Some("Mad7"));
-    assert_eq!(Enzyme::get("MAD7").map(|v| v.name), Some("Mad7"));
+    assert_eq!(Enzyme::get("mad7").map(|v| v.name).as_deref(), Some("Mad7"));
+    assert_eq!(Enzyme::get("Mad7").map(|v| v.name).as_deref(), Some("Mad7"));
+    assert_eq!(Enzyme::get("mAD7").map(|v| v.name).as_deref(), Some("Mad7"));
+    assert_eq!(Enzyme::get("MAD7").map(|v| v.name).as_deref(), Some("Mad7"));
 }
 
 #[test]
@@ -24,3 +25,163 @@ fn test_enzyme_unknown() {
     assert_eq!(Enzyme::get("Cat9"), None);
     assert_eq!(Enzyme::get("Foo"), None);
 }
+
+#[test]
+fn test_enzyme_parse() {
+    let enzyme = Enzyme::parse(
+        "# Streptococcus thermophilus CRISPR1 Cas9\n\
+         name = St1Cas9\n\
+         pam = nnagaaw\n\
+         pam_position = tail\n\
+         \n\
+         grna_len = 27\n\
+         cutsite = -3\n",
+    )
+    .unwrap();
+
+    assert_eq!(enzyme.name, "St1Cas9");
+    assert_eq!(enzyme.extension, ".crispyr_st1cas9");
+    assert_eq!(enzyme.pams, PAMSet::new(PAM::tail(b"NNAGAAW")));
+    assert_eq!(enzyme.grna_len, 27);
+    assert_eq!(enzyme.cutsite, -3);
+    assert_eq!(enzyme.cutsite_bottom, -3);
+    assert_eq!(enzyme.overhang(), 0);
+}
+
+#[test]
+fn test_enzyme_parse_matches_builtin() {
+    let enzyme =
+        Enzyme::parse("name = Mad7\npam = YTTN\npam_position = 5'\ngrna_len = 25\ncutsite = 19\n")
+            .unwrap();
+
+    assert_eq!(Some(enzyme), Enzyme::get("mad7"));
+    assert_eq!(Enzyme::mad7().overhang(), 0);
+}
+
+#[test]
+fn test_enzyme_parse_missing_key() {
+    assert!(Enzyme::parse("name = Foo\npam = NGG\npam_position = tail\ngrna_len = 23\n").is_err());
+}
+
+#[test]
+fn test_enzyme_parse_invalid_values() {
+    let template = "name = Foo\npam = NGG\npam_position = tail\ngrna_len = 23\ncutsite = -3\n";
+
+    assert!(Enzyme::parse(template).is_ok());
+    assert!(Enzyme::parse(&template.replace("NGG", "NXG")).is_err());
+    assert!(Enzyme::parse(&template.replace("tail", "middle")).is_err());
+    assert!(Enzyme::parse(&template.replace("23", "15")).is_err());
+    assert!(Enzyme::parse(&template.replace("-3", "three")).is_err());
+    assert!(Enzyme::parse(&template.replace("Foo", "Foo bar")).is_err());
+    assert!(Enzyme::parse(&format!("{}color = blue\n", template)).is_err());
+}
+
+#[test]
+fn test_enzyme_catalogue() {
+    for enzyme in Enzyme::catalogue() {
+        assert!(
+            enzyme.validate().is_ok(),
+            "invalid enzyme {:?}",
+            enzyme.name
+        );
+        assert_eq!(Enzyme::get(&enzyme.name), Some(enzyme.clone()));
+        assert_eq!(Enzyme::get(&enzyme.name.to_ascii_uppercase()), Some(enzyme));
+    }
+}
+
+#[test]
+fn test_enzyme_catalogue_unique_extensions() {
+    let mut extensions: Vec<_> = Enzyme::catalogue()
+        .into_iter()
+        .map(|v| v.extension)
+        .collect();
+    let count = extensions.len();
+    extensions.sort();
+    extensions.dedup();
+
+    assert_eq!(extensions.len(), count);
+}
+
+#[test]
+fn test_enzyme_spcas9_alias() {
+    assert_eq!(Enzyme::get("SpCas9"), Some(Enzyme::cas9()));
+}
+
+#[test]
+fn test_enzyme_xcas9() {
+    let enzyme = Enzyme::xcas9();
+    assert_eq!(enzyme.pams.to_string(), "NGN,GAA,GAT");
+    assert_eq!(enzyme.grna_len - enzyme.pams.len(), 20);
+
+    let spacer = b"ACGTACGTACGTACGTACGT";
+    for (pam, expected) in &[
+        ("TGC", Some(0)),
+        ("GAA", Some(1)),
+        ("GAT", Some(2)),
+        ("GAC", None),
+    ] {
+        let mut target = spacer.to_vec();
+        target.extend(pam.as_bytes());
+
+        let found = enzyme.pams.kmer(&target).map(|(idx, _, _)| idx);
+        assert_eq!(found, *expected, "PAM {}", pam);
+    }
+}
+
+#[test]
+fn test_enzyme_parse_multiple_pams() {
+    let enzyme = Enzyme::parse(
+        "name = Foo\npam = NGG, nag:0.26, NGA : 0.07\npam_position = tail\ngrna_len = 23\ncutsite = -3\n",
+    )
+    .unwrap();
+
+    let expected = PAMSet::new(PAM::tail(b"NGG"))
+        .with_pam(PAM::tail(b"NAG"), 0.26)
+        .with_pam(PAM::tail(b"NGA"), 0.07);
+
+    assert_eq!(enzyme.pams, expected);
+}
+
+#[test]
+fn test_enzyme_parse_invalid_pams() {
+    let template = "name = Foo\npam = PAMS\npam_position = tail\ngrna_len = 23\ncutsite = -3\n";
+
+    assert!(Enzyme::parse(&template.replace("PAMS", "NGG,NAG:0.5")).is_ok());
+    assert!(Enzyme::parse(&template.replace("PAMS", "NGG,NA:0.5")).is_err());
+    assert!(Enzyme::parse(&template.replace("PAMS", "NGG,NAG:0")).is_err());
+    assert!(Enzyme::parse(&template.replace("PAMS", "NGG,NAG:1.5")).is_err());
+    assert!(Enzyme::parse(&template.replace("PAMS", "NGG,NAG:x")).is_err());
+    assert!(Enzyme::parse(&template.replace("PAMS", "NGG,")).is_err());
+    assert!(
+        Enzyme::parse(&template.replace("PAMS", "NGG,NAG,NGA,NCG,NTG,NAA,NAC,NAT,NCA")).is_err()
+    );
+}
+
+#[test]
+fn test_enzyme_parse_staggered_cutsite() {
+    let enzyme = Enzyme::parse(
+        "name = Foo\npam = TTTV\npam_position = head\ngrna_len = 27\ncutsite = 22\ncutsite_bottom = 27\n",
+    )
+    .unwrap();
+
+    assert_eq!(enzyme.cutsite, 22);
+    assert_eq!(enzyme.cutsite_bottom, 27);
+    assert_eq!(enzyme.overhang(), 5);
+}
+
+#[test]
+fn test_enzyme_overhang() {
+    assert_eq!(Enzyme::cas9().overhang(), 0);
+    assert_eq!(Enzyme::cas9().with_bottom_cutsite(-4).overhang(), -1);
+    assert_eq!(Enzyme::ascas12a().overhang(), 5);
+}
+
+#[test]
+fn test_enzyme_kmer_len() {
+    assert_eq!(Enzyme::cas9().kmer_len(), 13);
+    assert_eq!(Enzyme::cas9().with_kmer_len(20).kmer_len(), 20);
+
+    assert!(Enzyme::cas9().with_kmer_len(20).validate().is_ok());
+    assert!(Enzyme::cas9().with_kmer_len(21).validate().is_err());
+    assert!(Enzyme::cas9().with_kmer_len(0).validate().is_err());
+}
@@ -0,0 +1,107 @@
+extern crate crispyr;
+
+use crispyr::common::KMer;
+use crispyr::enzyme::Enzyme;
+use crispyr::external::{ExternalPositions, ENTRY_SIZE};
+use crispyr::index::*;
+
+mod util;
+use util::TempDir;
+
+fn sites() -> Vec<(Position, KMer)> {
+    let mut sites = Vec::new();
+    for idx in 0..300u64 {
+        let kmer = KMer::new((idx * 7) % 13);
+        let pos = ((idx * 31) % 97) as i64 - 10;
+        let position = if idx % 3 == 0 {
+            Position::reverse((idx % 4) as u32, pos).unwrap()
+        } else {
+            Position::forward((idx % 4) as u32, pos).unwrap()
+        };
+
+        sites.push((position.with_pam((idx % 2) as usize).unwrap(), kmer));
+    }
+
+    sites
+}
+
+fn refseqs() -> Vec<String> {
+    vec!["chr1".into(), "chr2".into(), "chr3".into(), "chr4".into()]
+}
+
+/// Writes an index using both in-memory and external positions, and returns the two files
+fn write_indexes(sites: &[(Position, KMer)], memory: usize) -> (Vec<u8>, Vec<u8>, usize) {
+    let enzyme = Enzyme::cas9().with_kmer_len(4);
+    let dir = TempDir::new();
+
+    let mut kmers = KMerMap::new(1, 4, true);
+    for (position, kmer) in sites {
+        kmers.add(*position, *kmer);
+    }
+
+    let memory_path = dir.join("memory.idx");
+    KMerIndex::new(&enzyme, refseqs(), kmers)
+        .write(&memory_path)
+        .unwrap();
+
+    let external_path = dir.join("external.idx");
+    let mut positions = ExternalPositions::new(&external_path, memory);
+    for (position, kmer) in sites {
+        positions.add(*position, *kmer).unwrap();
+    }
+
+    let kmer_count = KMerIndex::new(&enzyme, refseqs(), KMerMap::new(1, 4, true))
+        .write_external(&external_path, positions)
+        .unwrap();
+
+    let memory = std::fs::read(&memory_path).unwrap();
+    let external = std::fs::read(&external_path).unwrap();
+
+    (memory, external, kmer_count)
+}
+
+#[test]
+fn test_external_positions_single_run() {
+    let (memory, external, kmer_count) = write_indexes(&sites(), 1 << 20);
+
+    assert_eq!(kmer_count, 13);
+    assert_eq!(memory, external);
+}
+
+#[test]
+fn test_external_positions_multiple_runs() {
+    let (memory, external, kmer_count) = write_indexes(&sites(), 7 * ENTRY_SIZE);
+
+    assert_eq!(kmer_count, 13);
+    assert_eq!(memory, external);
+}
+
+#[test]
+fn test_external_positions_many_runs() {
+    // More runs than are merged at once, requiring intermediate merges
+    let (memory, external, _) = write_indexes(&sites(), 1);
+
+    assert_eq!(memory, external);
+}
+
+#[test]
+fn test_external_positions_runs() {
+    let dir = TempDir::new();
+    let path = dir.join("runs.idx");
+    let mut positions = ExternalPositions::new(&path, 10 * ENTRY_SIZE);
+    assert_eq!(positions.capacity(), 10);
+
+    for (position, kmer) in sites().into_iter().take(25) {
+        positions.add(position, kmer).unwrap();
+    }
+
+    assert_eq!(positions.runs(), 2);
+}
+
+#[test]
+fn test_external_positions_empty() {
+    let (memory, external, kmer_count) = write_indexes(&[], 1024);
+
+    assert_eq!(kmer_count, 0);
+    assert_eq!(memory, external);
+}
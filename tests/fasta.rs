@@ -0,0 +1,241 @@
+extern crate crispyr;
+
+use std::cell::Cell;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::path::Path;
+use std::rc::Rc;
+
+use flate2::write::GzEncoder;
+use flate2::{Compression, GzBuilder};
+
+use crispyr::fasta::{open, open_indexed, BgzfReader};
+
+mod util;
+use util::TempDir;
+
+const FASTA: &str = ">seq1\nACGTACGTAC\nGTACG\n>seq2\nTTTTGGGGCC\nCCAAAA\n";
+const FAI: &str = "seq1\t15\t6\t10\t11\nseq2\t16\t29\t10\t11\n";
+
+/// Compresses data as BGZF blocks containing at most `block_size` bytes, returning the
+/// compressed data and the corresponding '.gzi' index
+fn bgzip(data: &[u8], block_size: usize) -> (Vec<u8>, Vec<u8>) {
+    let mut compressed = Vec::new();
+    let mut offsets = Vec::new();
+
+    // The final block is empty, as the EOF marker written by bgzip
+    let mut chunks: Vec<&[u8]> = data.chunks(block_size).collect();
+    chunks.push(&[]);
+
+    for (idx, chunk) in chunks.iter().enumerate() {
+        if idx > 0 {
+            let offset = usize::min(idx * block_size, data.len());
+            offsets.push((compressed.len() as u64, offset as u64));
+        }
+
+        let mut encoder = GzBuilder::new()
+            .extra(vec![b'B', b'C', 2, 0, 0, 0])
+            .write(Vec::new(), Compression::default());
+        encoder.write_all(chunk).unwrap();
+        let mut block = encoder.finish().unwrap();
+
+        let bsize = (block.len() - 1) as u16;
+        block[16..18].copy_from_slice(&bsize.to_le_bytes());
+        compressed.extend_from_slice(&block);
+    }
+
+    let mut gzi = Vec::new();
+    gzi.extend_from_slice(&(offsets.len() as u64).to_le_bytes());
+    for (compressed, uncompressed) in offsets {
+        gzi.extend_from_slice(&compressed.to_le_bytes());
+        gzi.extend_from_slice(&uncompressed.to_le_bytes());
+    }
+
+    (compressed, gzi)
+}
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+fn read_all(path: &Path) -> String {
+    let mut data = String::new();
+    open(path.to_str().unwrap())
+        .unwrap()
+        .read_to_string(&mut data)
+        .unwrap();
+
+    data
+}
+
+fn fetch(path: &Path, name: &str, start: u64, end: u64) -> Vec<u8> {
+    let mut reader = open_indexed(path.to_str().unwrap()).unwrap();
+    let mut seq = Vec::new();
+    reader.fetch(name, start, end).unwrap();
+    reader.read(&mut seq).unwrap();
+
+    seq
+}
+
+#[test]
+fn test_open_plain() {
+    let dir = TempDir::new();
+    let path = dir.join("open_plain.fasta");
+    std::fs::write(&path, FASTA).unwrap();
+
+    assert_eq!(read_all(&path), FASTA);
+}
+
+#[test]
+fn test_open_gzip() {
+    let dir = TempDir::new();
+    let path = dir.join("open_gzip.fasta.gz");
+    std::fs::write(&path, gzip(FASTA.as_bytes())).unwrap();
+
+    assert_eq!(read_all(&path), FASTA);
+}
+
+#[test]
+fn test_open_bgzip() {
+    let dir = TempDir::new();
+    let path = dir.join("open_bgzip.fasta.gz");
+    std::fs::write(&path, bgzip(FASTA.as_bytes(), 7).0).unwrap();
+
+    assert_eq!(read_all(&path), FASTA);
+}
+
+#[test]
+fn test_open_indexed_plain() {
+    let dir = TempDir::new();
+    let path = dir.join("indexed_plain.fasta");
+    std::fs::write(&path, FASTA).unwrap();
+    std::fs::write(path.with_extension("fasta.fai"), FAI).unwrap();
+
+    assert_eq!(fetch(&path, "seq1", 0, 15), b"ACGTACGTACGTACG");
+    assert_eq!(fetch(&path, "seq2", 8, 14), b"CCCCAA");
+}
+
+#[test]
+fn test_open_indexed_bgzip() {
+    let dir = TempDir::new();
+    let path = dir.join("indexed_bgzip.fasta.gz");
+    let (data, gzi) = bgzip(FASTA.as_bytes(), 7);
+    std::fs::write(&path, data).unwrap();
+    std::fs::write(path.with_extension("gz.fai"), FAI).unwrap();
+    std::fs::write(path.with_extension("gz.gzi"), gzi).unwrap();
+
+    assert_eq!(fetch(&path, "seq1", 0, 15), b"ACGTACGTACGTACG");
+    assert_eq!(fetch(&path, "seq1", 9, 11), b"CG");
+    assert_eq!(fetch(&path, "seq2", 0, 16), b"TTTTGGGGCCCCAAAA");
+    assert_eq!(fetch(&path, "seq2", 8, 14), b"CCCCAA");
+}
+
+#[test]
+fn test_open_indexed_bgzip_without_gzi() {
+    let dir = TempDir::new();
+    let path = dir.join("indexed_bgzip_without_gzi.fasta.gz");
+    std::fs::write(&path, bgzip(FASTA.as_bytes(), 7).0).unwrap();
+    std::fs::write(path.with_extension("gz.fai"), FAI).unwrap();
+
+    assert!(open_indexed(path.to_str().unwrap()).is_err());
+}
+
+#[test]
+fn test_open_indexed_gzip() {
+    let dir = TempDir::new();
+    let path = dir.join("indexed_gzip.fasta.gz");
+    std::fs::write(&path, gzip(FASTA.as_bytes())).unwrap();
+    std::fs::write(path.with_extension("gz.fai"), FAI).unwrap();
+
+    assert!(open_indexed(path.to_str().unwrap()).is_err());
+}
+
+#[test]
+fn test_bgzf_reader_seek() {
+    let dir = TempDir::new();
+    let path = dir.join("bgzf_reader_seek.gz");
+    let data: Vec<u8> = (0..100).collect();
+    let (compressed, gzi) = bgzip(&data, 16);
+    std::fs::write(path.with_extension("gz.gzi"), gzi).unwrap();
+
+    let gzi_path = path.with_extension("gz.gzi");
+    let mut reader =
+        BgzfReader::new(std::io::Cursor::new(compressed), gzi_path.to_str().unwrap()).unwrap();
+
+    for &pos in &[0u64, 15, 16, 17, 50, 95, 99] {
+        let mut buf = [0; 3];
+        assert_eq!(reader.seek(SeekFrom::Start(pos)).unwrap(), pos);
+        let len = reader.read(&mut buf).unwrap();
+        assert!(len > 0);
+        assert_eq!(&buf[..len], &data[pos as usize..pos as usize + len]);
+    }
+
+    assert_eq!(reader.seek(SeekFrom::Current(-10)).unwrap(), 90);
+    let mut rest = Vec::new();
+    reader.read_to_end(&mut rest).unwrap();
+    assert_eq!(rest, &data[90..]);
+
+    assert_eq!(reader.seek(SeekFrom::Start(100)).unwrap(), 100);
+    assert_eq!(reader.read(&mut [0; 3]).unwrap(), 0);
+
+    let err = reader.seek(SeekFrom::Current(-101)).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    assert_eq!(reader.seek(SeekFrom::Current(-100)).unwrap(), 0);
+}
+
+/// Wrapper counting the number of seeks in the underlying reader
+struct CountingReader<R> {
+    reader: R,
+    seeks: Rc<Cell<usize>>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl<R: Seek> Seek for CountingReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.seeks.set(self.seeks.get() + 1);
+        self.reader.seek(pos)
+    }
+}
+
+#[test]
+fn test_bgzf_reader_seek_within_block() {
+    let dir = TempDir::new();
+    let gzi_path = dir.join("seek_within_block.gz.gzi");
+    let data: Vec<u8> = (0..100).collect();
+    let (compressed, gzi) = bgzip(&data, 16);
+    std::fs::write(&gzi_path, gzi).unwrap();
+
+    let seeks = Rc::new(Cell::new(0));
+    let reader = CountingReader {
+        reader: std::io::Cursor::new(compressed),
+        seeks: seeks.clone(),
+    };
+    let mut reader = BgzfReader::new(reader, gzi_path.to_str().unwrap()).unwrap();
+
+    let mut buf = [0; 2];
+    reader.seek(SeekFrom::Start(20)).unwrap();
+    reader.read_exact(&mut buf).unwrap();
+    assert_eq!(buf, [20, 21]);
+    assert_eq!(seeks.get(), 1);
+
+    // The block containing positions 16 to 31 is already decompressed
+    assert_eq!(reader.seek(SeekFrom::Start(17)).unwrap(), 17);
+    reader.read_exact(&mut buf).unwrap();
+    assert_eq!(buf, [17, 18]);
+    assert_eq!(reader.seek(SeekFrom::Current(10)).unwrap(), 29);
+    reader.read_exact(&mut buf).unwrap();
+    assert_eq!(buf, [29, 30]);
+    assert_eq!(seeks.get(), 1);
+
+    reader.seek(SeekFrom::Start(5)).unwrap();
+    reader.read_exact(&mut buf).unwrap();
+    assert_eq!(buf, [5, 6]);
+    assert_eq!(seeks.get(), 2);
+}
@@ -0,0 +1,155 @@
+extern crate crispyr;
+
+use std::collections::HashMap;
+
+use crispyr::filter::SequenceFilter;
+
+mod util;
+use util::TempDir;
+
+fn regions(regions: &[(&str, u64, u64)]) -> HashMap<String, Vec<(u64, u64)>> {
+    let mut map: HashMap<String, Vec<(u64, u64)>> = HashMap::new();
+    for &(name, start, end) in regions {
+        map.entry(name.to_string()).or_default().push((start, end));
+    }
+
+    map
+}
+
+#[test]
+fn test_filter_empty() {
+    let filter = SequenceFilter::default();
+
+    assert!(filter.is_empty());
+    assert!(filter.includes("chr1"));
+    assert_eq!(filter.mask("chr1", 10), None);
+    assert_eq!(filter.to_string(), "all sequences");
+}
+
+#[test]
+fn test_filter_include() {
+    let filter = SequenceFilter {
+        include: vec!["chr1".to_string(), "chr2".to_string()],
+        ..Default::default()
+    };
+
+    assert!(!filter.is_empty());
+    assert!(filter.includes("chr1"));
+    assert!(filter.includes("chr2"));
+    assert!(!filter.includes("chr3"));
+}
+
+#[test]
+fn test_filter_include_regex() {
+    let filter = SequenceFilter {
+        include: vec!["chrM".to_string()],
+        include_regex: vec![SequenceFilter::regex("^chr[0-9XY]+$").unwrap()],
+        ..Default::default()
+    };
+
+    assert!(filter.includes("chr1"));
+    assert!(filter.includes("chrX"));
+    assert!(filter.includes("chrM"));
+    assert!(!filter.includes("chr1_KI270706v1_random"));
+}
+
+#[test]
+fn test_filter_exclude() {
+    let filter = SequenceFilter {
+        exclude: vec!["chrEBV".to_string()],
+        exclude_regex: vec![
+            SequenceFilter::regex("_alt$").unwrap(),
+            SequenceFilter::regex("^chrUn").unwrap(),
+        ],
+        ..Default::default()
+    };
+
+    assert!(filter.includes("chr1"));
+    assert!(!filter.includes("chrEBV"));
+    assert!(!filter.includes("chr6_GL000250v2_alt"));
+    assert!(!filter.includes("chrUn_KI270302v1"));
+}
+
+#[test]
+fn test_filter_include_and_exclude() {
+    let filter = SequenceFilter {
+        include_regex: vec![SequenceFilter::regex("^chr").unwrap()],
+        exclude_regex: vec![SequenceFilter::regex("_alt$").unwrap()],
+        ..Default::default()
+    };
+
+    assert!(filter.includes("chr1"));
+    assert!(!filter.includes("chr1_alt"));
+    assert!(!filter.includes("HLA-A*01:01:01:01"));
+}
+
+#[test]
+fn test_filter_invalid_regex() {
+    assert!(SequenceFilter::regex("chr(").is_err());
+}
+
+#[test]
+fn test_filter_regions() {
+    let filter = SequenceFilter {
+        bed: Some("regions.bed".to_string()),
+        regions: regions(&[("chr1", 2, 4), ("chr1", 6, 12), ("chr2", 0, 1)]),
+        ..Default::default()
+    };
+
+    assert!(!filter.is_empty());
+    assert!(filter.includes("chr1"));
+    assert!(filter.includes("chr2"));
+    assert!(!filter.includes("chr3"));
+
+    let mask: Vec<bool> = "xx..xx....".bytes().map(|v| v == b'x').collect();
+    assert_eq!(filter.mask("chr1", 10), Some(mask));
+    assert_eq!(filter.mask("chr3", 2), Some(vec![true, true]));
+    assert_eq!(
+        filter.to_string(),
+        "restricted to 3 regions in 2 sequences (9 bp) in \"regions.bed\""
+    );
+}
+
+#[test]
+fn test_filter_with_bed() {
+    let dir = TempDir::new();
+    let path = dir.join("filter.bed");
+    std::fs::write(&path, "chr1\t10\t20\ta\nchr2\t0\t5\tb\n").unwrap();
+
+    let filter = SequenceFilter::default()
+        .with_bed(path.to_str().unwrap())
+        .unwrap();
+
+    assert_eq!(filter.bed.as_deref(), path.to_str());
+    assert_eq!(
+        filter.bed_md5,
+        Some(md5::compute("chr1\t10\t20\ta\nchr2\t0\t5\tb\n").0)
+    );
+    assert_eq!(filter.regions, regions(&[("chr1", 10, 20), ("chr2", 0, 5)]));
+    assert_eq!(
+        filter.to_string(),
+        format!(
+            "restricted to 2 regions in 2 sequences (15 bp) in {:?} (MD5 {:x})",
+            path.to_str().unwrap(),
+            md5::compute("chr1\t10\t20\ta\nchr2\t0\t5\tb\n")
+        )
+    );
+
+    // Filters read from indexes lack regions
+    let filter = SequenceFilter {
+        regions: HashMap::new(),
+        ..filter
+    };
+    assert!(filter.to_string().starts_with("restricted to regions in "));
+}
+
+#[test]
+fn test_filter_with_invalid_bed() {
+    let dir = TempDir::new();
+    let path = dir.join("filter_invalid.bed");
+    std::fs::write(&path, "chr1\t20\t10\n").unwrap();
+
+    let result = SequenceFilter::default().with_bed(path.to_str().unwrap());
+
+    assert!(result.is_err());
+}
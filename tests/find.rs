@@ -0,0 +1,34 @@
+extern crate crispyr;
+
+use crispyr::api::{find_targets, IndexOptions};
+use crispyr::commands::find::verify_offtargets;
+use crispyr::enzyme::Enzyme;
+use crispyr::score::{Model, Scorer, ScoringProfile};
+
+mod fixtures;
+use fixtures::{build_index, flanked, GUIDE, VARIANT, VARIANT_2};
+
+#[test]
+fn test_verify_offtargets() {
+    let options = IndexOptions {
+        positions: true,
+        embed_sequences: true,
+        ..Default::default()
+    };
+    // Off-targets with 0, 1, and 2 mismatches outside the K-mer
+    let index = build_index(&[GUIDE, VARIANT, VARIANT_2], options);
+    let scorer = Scorer::new(&index, &ScoringProfile::default(), &[Model::CRISPy]).unwrap();
+
+    let site = find_targets(&Enzyme::cas9(), &flanked(GUIDE))
+        .into_iter()
+        .find(|site| site.sequence == GUIDE)
+        .unwrap();
+
+    assert_eq!(scorer.score(site.kmer), "1500");
+    assert_eq!(
+        verify_offtargets(&scorer, &site, 4).unwrap(),
+        "\t1500\t1,1,1,0,0"
+    );
+    assert_eq!(verify_offtargets(&scorer, &site, 1).unwrap(), "\t1000\t1,1");
+    assert_eq!(verify_offtargets(&scorer, &site, 0).unwrap(), "\t500\t1");
+}
@@ -0,0 +1,33 @@
+// Not every test binary uses every fixture
+#![allow(dead_code)]
+
+use crispyr::api::{IndexBuilder, IndexOptions};
+use crispyr::enzyme::Enzyme;
+use crispyr::index::KMerIndex;
+
+/// Cas9 target site (20 bp spacer and NGG PAM)
+pub const GUIDE: &[u8] = b"GATCACTGATCAGTACTGATTGG";
+/// Off-target of `GUIDE` with 1 mismatch outside the PAM-proximal 13 bp K-mer
+pub const VARIANT: &[u8] = b"TATCACTGATCAGTACTGATTGG";
+/// Off-target of `GUIDE` with 2 mismatches outside the PAM-proximal 13 bp K-mer
+pub const VARIANT_2: &[u8] = b"TTTCACTGATCAGTACTGATTGG";
+
+/// Returns the target sequence flanked by 6 bp (without target sites) on either side
+pub fn flanked(target: &[u8]) -> Vec<u8> {
+    let mut sequence = b"ATATAT".to_vec();
+    sequence.extend(target);
+    sequence.extend(b"ATATAT");
+    sequence
+}
+
+/// Builds a Cas9 index containing one sequence per target ("chr1", "chr2", etc.), each consisting
+/// of the target and its flanks
+pub fn build_index(targets: &[&[u8]], options: IndexOptions) -> KMerIndex {
+    let mut builder = IndexBuilder::with_options(&Enzyme::cas9(), options);
+    for (idx, target) in targets.iter().enumerate() {
+        let name = format!("chr{}", idx + 1);
+        builder.add(&name, &flanked(target)).unwrap();
+    }
+
+    builder.build().unwrap()
+}
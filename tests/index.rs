@@ -1,99 +1,1065 @@
 extern crate crispyr;
 
+use std::path::PathBuf;
+
+use crispyr::common::KMer;
+use crispyr::constants::{
+    BED_MD5_INDEX_VERSION, INDEX_HEADER, INDEX_VERSION, MAX_REFSEQS, MIN_INDEX_VERSION,
+    PACKED_POSITIONS_INDEX_VERSION, WIDE_POSITIONS_INDEX_VERSION,
+};
+use crispyr::enzyme::Enzyme;
+use crispyr::filter::SequenceFilter;
 use crispyr::index::*;
+use crispyr::provenance::{Provenance, SequenceInfo, Source};
+use crispyr::twobit::{TwoBitRecord, TwoBitSequences};
+
+mod util;
+use util::TempDir;
+
+#[test]
+fn test_position_to_raw_forward_zeroes() {
+    let pos = Position::forward(0, 0).unwrap();
+    let repr = pos.to_raw();
+
+    assert_eq!(pos, Position::from_raw(repr.0, repr.1));
+}
+
+#[test]
+fn test_position_to_raw_reverse_zeroes() {
+    let pos = Position::reverse(0, 0).unwrap();
+    let repr = pos.to_raw();
+
+    assert_eq!(pos, Position::from_raw(repr.0, repr.1));
+}
+
+#[test]
+fn test_position_to_raw_forward_ref_no_pos() {
+    let pos = Position::forward(274, 0).unwrap();
+    let repr = pos.to_raw();
+
+    assert_eq!(pos, Position::from_raw(repr.0, repr.1));
+}
+
+#[test]
+fn test_position_to_raw_reverse_ref_no_pos() {
+    let pos = Position::reverse(274, 0).unwrap();
+    let repr = pos.to_raw();
+
+    assert_eq!(pos, Position::from_raw(repr.0, repr.1));
+}
+
+#[test]
+fn test_position_to_raw_forward_pos_no_ref() {
+    let pos = Position::forward(0, 7913).unwrap();
+    let repr = pos.to_raw();
+
+    assert_eq!(pos, Position::from_raw(repr.0, repr.1));
+}
+
+#[test]
+fn test_position_to_raw_reverse_pos_no_ref() {
+    let pos = Position::reverse(0, 7913).unwrap();
+    let repr = pos.to_raw();
+
+    assert_eq!(pos, Position::from_raw(repr.0, repr.1));
+}
+
+#[test]
+fn test_position_to_raw_forward_neg_pos_no_ref() {
+    let pos = Position::forward(0, -7913).unwrap();
+    let repr = pos.to_raw();
+
+    assert_eq!(pos, Position::from_raw(repr.0, repr.1));
+}
+
+#[test]
+fn test_position_to_raw_reverse_neg_pos_no_ref() {
+    let pos = Position::reverse(0, -7913).unwrap();
+    let repr = pos.to_raw();
+
+    assert_eq!(pos, Position::from_raw(repr.0, repr.1));
+}
+
+#[test]
+fn test_position_to_raw_forward_pos_and_ref() {
+    let pos = Position::forward(17, 7913).unwrap();
+    let repr = pos.to_raw();
+
+    assert_eq!(pos, Position::from_raw(repr.0, repr.1));
+}
+
+#[test]
+fn test_position_to_raw_reverse_pos_and_ref() {
+    let pos = Position::reverse(17, 7913).unwrap();
+    let repr = pos.to_raw();
+
+    assert_eq!(pos, Position::from_raw(repr.0, repr.1));
+}
 
 #[test]
-fn test_position_to_u64_forward_zeroes() {
-    let pos = Position::forward(0, 0);
-    let repr = pos.to_u64();
+fn test_position_to_raw_forward_neg_pos_and_ref() {
+    let pos = Position::forward(17, -7913).unwrap();
+    let repr = pos.to_raw();
 
-    assert_eq!(pos, Position::from_u64(repr));
+    assert_eq!(pos, Position::from_raw(repr.0, repr.1));
 }
 
 #[test]
-fn test_position_to_u64_reverse_zeroes() {
-    let pos = Position::reverse(0, 0);
-    let repr = pos.to_u64();
+fn test_position_to_raw_reverse_neg_pos_and_ref() {
+    let pos = Position::reverse(17, -7913).unwrap();
+    let repr = pos.to_raw();
 
-    assert_eq!(pos, Position::from_u64(repr));
+    assert_eq!(pos, Position::from_raw(repr.0, repr.1));
 }
 
 #[test]
-fn test_position_to_u64_forward_ref_no_pos() {
-    let pos = Position::forward(274, 0);
-    let repr = pos.to_u64();
+fn test_position_to_raw_forward_pam() {
+    let pos = Position::forward(17, -7913).unwrap().with_pam(7).unwrap();
+    let repr = pos.to_raw();
 
-    assert_eq!(pos, Position::from_u64(repr));
+    assert_eq!(pos, Position::from_raw(repr.0, repr.1));
+    assert_eq!(Position::from_raw(repr.0, repr.1).pam(), 7);
 }
 
 #[test]
-fn test_position_to_u64_reverse_ref_no_pos() {
-    let pos = Position::reverse(274, 0);
-    let repr = pos.to_u64();
+fn test_position_to_raw_reverse_pam() {
+    let pos = Position::reverse(17, 7913).unwrap().with_pam(3).unwrap();
+    let repr = pos.to_raw();
 
-    assert_eq!(pos, Position::from_u64(repr));
+    assert_eq!(pos, Position::from_raw(repr.0, repr.1));
+    assert_eq!(Position::from_raw(repr.0, repr.1).pam(), 3);
 }
 
 #[test]
-fn test_position_to_u64_forward_pos_no_ref() {
-    let pos = Position::forward(0, 7913);
-    let repr = pos.to_u64();
+fn test_position_to_raw_forward_large_pos() {
+    let pos = Position::forward(17, 5_000_000_000)
+        .unwrap()
+        .with_pam(2)
+        .unwrap();
+    let repr = pos.to_raw();
 
-    assert_eq!(pos, Position::from_u64(repr));
+    assert_eq!(pos, Position::from_raw(repr.0, repr.1));
+    assert_eq!(pos.pos(), 5_000_000_000);
+    assert_eq!(pos.refseq(), 17);
+    assert_eq!(pos.pam(), 2);
 }
 
 #[test]
-fn test_position_to_u64_reverse_pos_no_ref() {
-    let pos = Position::reverse(0, 7913);
-    let repr = pos.to_u64();
+fn test_position_to_raw_reverse_large_pos() {
+    let pos = Position::reverse(17, -5_000_000_000).unwrap();
+    let repr = pos.to_raw();
 
-    assert_eq!(pos, Position::from_u64(repr));
+    assert_eq!(pos, Position::from_raw(repr.0, repr.1));
+    assert_eq!(pos.pos(), -5_000_000_000);
+    assert_eq!(pos.strand(), '-');
 }
 
 #[test]
-fn test_position_to_u64_forward_neg_pos_no_ref() {
-    let pos = Position::forward(0, -7913);
-    let repr = pos.to_u64();
+fn test_position_to_raw_large_refseq() {
+    let refseq = (MAX_REFSEQS - 1) as u32;
+    let pos = Position::forward(refseq, (1 << 59) - 1)
+        .unwrap()
+        .with_pam(7)
+        .unwrap();
+    let repr = pos.to_raw();
 
-    assert_eq!(pos, Position::from_u64(repr));
+    assert_eq!(pos, Position::from_raw(repr.0, repr.1));
+    assert_eq!(pos.refseq(), refseq);
+    assert_eq!(pos.pos(), (1 << 59) - 1);
+    assert_eq!(pos.strand(), '+');
+    assert_eq!(pos.pam(), 7);
+
+    let pos = Position::reverse(refseq, -(1 << 59)).unwrap();
+    let repr = pos.to_raw();
+    assert_eq!(pos, Position::from_raw(repr.0, repr.1));
+    assert_eq!(pos.refseq(), refseq);
+    assert_eq!(pos.pos(), -(1 << 59));
+    assert_eq!(pos.strand(), '-');
+}
+
+#[test]
+fn test_position_pam_overflow() {
+    let pos = Position::forward(0, 0).unwrap();
+
+    assert!(pos.with_pam(8).is_err());
+    assert!(Position::new(0, 0, false, 8).is_err());
+}
+
+#[test]
+fn test_position_pos_overflow() {
+    assert!(Position::forward(0, 1 << 59).is_err());
+    assert!(Position::reverse(0, -(1 << 59) - 1).is_err());
+    assert!(Position::forward(0, i64::MAX).is_err());
 }
 
 #[test]
-fn test_position_to_u64_reverse_neg_pos_no_ref() {
-    let pos = Position::reverse(0, -7913);
-    let repr = pos.to_u64();
+fn test_kmer_table_dense() {
+    let mut table = KMerTable::new(4);
+    *table.get_mut(KMer::new(17)) += 2;
+    *table.get_mut(KMer::new(3)) += 1;
 
-    assert_eq!(pos, Position::from_u64(repr));
+    assert!(matches!(table, KMerTable::Dense(_)));
+    assert_eq!(table.len(), 2);
+    assert_eq!(table.get(KMer::new(17)), Some(&2));
+    assert_eq!(table.get(KMer::new(16)), None);
+    assert_eq!(table.iter(), vec![(KMer::new(3), &1), (KMer::new(17), &2)]);
 }
 
 #[test]
-fn test_position_to_u64_forward_pos_and_ref() {
-    let pos = Position::forward(17, 7913);
-    let repr = pos.to_u64();
+fn test_kmer_table_sparse() {
+    let mut table = KMerTable::new(20);
+    *table.get_mut(KMer::new(1 << 39)) += 2;
+    *table.get_mut(KMer::new(3)) += 1;
 
-    assert_eq!(pos, Position::from_u64(repr));
+    assert!(matches!(table, KMerTable::Sparse(_)));
+    assert_eq!(table.len(), 2);
+    assert_eq!(table.get(KMer::new(1 << 39)), Some(&2));
+    assert_eq!(table.get(KMer::new(16)), None);
+    assert_eq!(
+        table.iter(),
+        vec![(KMer::new(3), &1), (KMer::new(1 << 39), &2)]
+    );
+}
+
+fn write_and_read_index(kmers: KMerMap) -> KMerIndex {
+    let enzyme = Enzyme::cas9().with_kmer_len(4);
+    let refseqs = vec!["chr1".to_string(), "chr2".to_string()];
+    let dir = TempDir::new();
+    let filename = dir.join("index.idx");
+
+    KMerIndex::new(&enzyme, refseqs, kmers)
+        .write(&filename)
+        .unwrap();
+    let index = KMerIndex::read(&filename).unwrap();
+
+    assert_eq!(index.enzyme(), &enzyme);
+    index
 }
 
 #[test]
-fn test_position_to_u64_reverse_pos_and_ref() {
-    let pos = Position::reverse(17, 7913);
-    let repr = pos.to_u64();
+fn test_index_counts_round_trip() {
+    let mut kmers = KMerMap::new(1, 4, false);
+    kmers.add(Position::forward(0, 10).unwrap(), KMer::new(17));
+    kmers.add(Position::reverse(1, 20).unwrap(), KMer::new(17));
+    kmers.add(Position::forward(1, 30).unwrap(), KMer::new(3));
+
+    let index = write_and_read_index(kmers);
 
-    assert_eq!(pos, Position::from_u64(repr));
+    assert!(!index.has_positions());
+    assert_eq!(index.kmer_count(), 2);
+    assert_eq!(index.get_count(0, KMer::new(3)), Some(1));
+    assert_eq!(index.get_count(0, KMer::new(17)), Some(2));
+    assert_eq!(index.get_count(0, KMer::new(16)), None);
+    assert_eq!(index.get_positions(KMer::new(17)), None);
 }
 
 #[test]
-fn test_position_to_u64_forward_neg_pos_and_ref() {
-    let pos = Position::forward(17, -7913);
-    let repr = pos.to_u64();
+fn test_index_positions_round_trip() {
+    let mut kmers = KMerMap::new(1, 4, true);
+    kmers.add(Position::forward(0, 10).unwrap(), KMer::new(17));
+    kmers.add(Position::reverse(1, 20).unwrap(), KMer::new(17));
+    kmers.add(Position::forward(1, 30).unwrap(), KMer::new(3));
+
+    let index = write_and_read_index(kmers);
 
-    assert_eq!(pos, Position::from_u64(repr));
+    assert!(index.has_positions());
+    assert_eq!(index.refseqs(), &["chr1", "chr2"]);
+    assert_eq!(index.kmer_count(), 2);
+    assert_eq!(index.get_count(0, KMer::new(17)), Some(2));
+    assert_eq!(
+        index.get_positions(KMer::new(17)),
+        Some(
+            &[
+                Position::forward(0, 10).unwrap(),
+                Position::reverse(1, 20).unwrap()
+            ][..]
+        )
+    );
+    assert_eq!(
+        index.get_positions(KMer::new(3)),
+        Some(&[Position::forward(1, 30).unwrap()][..])
+    );
+    assert_eq!(index.get_positions(KMer::new(16)), None);
 }
 
 #[test]
-fn test_position_to_u64_reverse_neg_pos_and_ref() {
-    let pos = Position::reverse(17, -7913);
-    let repr = pos.to_u64();
+fn test_index_large_positions_round_trip() {
+    let mut kmers = KMerMap::new(1, 4, true);
+    kmers.add(Position::forward(0, 3_000_000_000).unwrap(), KMer::new(17));
+    kmers.add(Position::reverse(1, 5_000_000_000).unwrap(), KMer::new(17));
+
+    let index = write_and_read_index(kmers);
+
+    assert_eq!(
+        index.get_positions(KMer::new(17)),
+        Some(
+            &[
+                Position::forward(0, 3_000_000_000).unwrap(),
+                Position::reverse(1, 5_000_000_000).unwrap()
+            ][..]
+        )
+    );
+}
+
+#[test]
+fn test_position_table() {
+    let mut table = PositionTable::new();
+    table.add(Position::reverse(1, 20).unwrap(), KMer::new(17));
+    table.add(Position::forward(0, 10).unwrap(), KMer::new(17));
+    table.add(Position::forward(1, 30).unwrap(), KMer::new(3));
+
+    // Positions are not available until the table is finished
+    assert_eq!(table.len(), 0);
+    assert!(table.is_empty());
+    assert_eq!(table.get(KMer::new(3)), None);
+
+    table.finish();
+
+    assert_eq!(table.len(), 2);
+    assert!(!table.is_empty());
+    assert_eq!(
+        table.get(KMer::new(3)),
+        Some(&[Position::forward(1, 30).unwrap()][..])
+    );
+    assert_eq!(
+        table.get(KMer::new(17)),
+        Some(
+            &[
+                Position::forward(0, 10).unwrap(),
+                Position::reverse(1, 20).unwrap()
+            ][..]
+        )
+    );
+    assert_eq!(table.get(KMer::new(16)), None);
+}
+
+#[test]
+fn test_position_table_incremental() {
+    let mut table = PositionTable::new();
+    table.add(Position::forward(0, 10).unwrap(), KMer::new(17));
+    table.finish();
+    table.add(Position::forward(0, 5).unwrap(), KMer::new(17));
+    table.add(Position::reverse(0, 7).unwrap(), KMer::new(4));
+    table.finish();
+
+    let kmers: Vec<_> = table.iter().collect();
+    assert_eq!(
+        kmers,
+        vec![
+            (KMer::new(4), &[Position::reverse(0, 7).unwrap()][..]),
+            (
+                KMer::new(17),
+                &[
+                    Position::forward(0, 5).unwrap(),
+                    Position::forward(0, 10).unwrap()
+                ][..]
+            ),
+        ]
+    );
+}
+
+fn new_index(refseqs: &[&str], sites: &[(Position, u64)], positions: bool) -> KMerIndex {
+    let enzyme = Enzyme::cas9().with_kmer_len(4);
+    let mut kmers = KMerMap::new(1, 4, positions);
+    for (position, kmer) in sites {
+        kmers.add(*position, KMer::new(*kmer));
+    }
+
+    let refseqs = refseqs.iter().map(|name| name.to_string()).collect();
+    KMerIndex::new(&enzyme, refseqs, kmers)
+}
+
+#[test]
+fn test_index_merge_counts() {
+    let index_1 = new_index(&["chr1"], &[(Position::forward(0, 10).unwrap(), 17)], false);
+    let index_2 = new_index(
+        &["chr2"],
+        &[
+            (Position::forward(0, 7).unwrap(), 17),
+            (Position::reverse(0, 9).unwrap(), 3),
+        ],
+        false,
+    );
+
+    let merged = KMerIndex::merge(&[&index_1, &index_2]).unwrap();
+
+    assert_eq!(merged.refseqs(), &["chr1", "chr2"]);
+    assert_eq!(merged.kmer_count(), 2);
+    assert_eq!(merged.get_count(0, KMer::new(17)), Some(2));
+    assert_eq!(merged.get_count(0, KMer::new(3)), Some(1));
+}
+
+#[test]
+fn test_index_merge_positions() {
+    let index_1 = new_index(&["chr1"], &[(Position::forward(0, 10).unwrap(), 17)], true);
+    let index_2 = new_index(
+        &["chr2", "chr3"],
+        &[
+            (Position::forward(1, 7).unwrap(), 17),
+            (Position::reverse(0, 9).unwrap(), 3),
+        ],
+        true,
+    );
+
+    let merged = KMerIndex::merge(&[&index_1, &index_2]).unwrap();
+
+    assert_eq!(merged.refseqs(), &["chr1", "chr2", "chr3"]);
+    assert_eq!(
+        merged.get_positions(KMer::new(17)),
+        Some(
+            &[
+                Position::forward(0, 10).unwrap(),
+                Position::forward(2, 7).unwrap()
+            ][..]
+        )
+    );
+    assert_eq!(
+        merged.get_positions(KMer::new(3)),
+        Some(&[Position::reverse(1, 9).unwrap()][..])
+    );
+}
+
+#[test]
+fn test_index_merge_duplicate_refseqs() {
+    let index_1 = new_index(&["chr1"], &[], true);
+    let index_2 = new_index(&["chr2", "chr1"], &[], true);
+
+    assert!(KMerIndex::merge(&[&index_1, &index_2]).is_err());
+}
+
+#[test]
+fn test_index_merge_incompatible() {
+    let index_1 = new_index(&["chr1"], &[], true);
+    let index_2 = new_index(&["chr2"], &[], false);
+    let index_3 = KMerIndex::new(
+        &Enzyme::cas9(),
+        vec!["chr3".to_string()],
+        KMerMap::new(1, 4, true),
+    );
+
+    assert!(KMerIndex::merge(&[&index_1, &index_2]).is_err());
+    assert!(KMerIndex::merge(&[&index_1, &index_3]).is_err());
+}
+
+#[test]
+fn test_index_masked_excluded_round_trip() {
+    let dir = TempDir::new();
+    let filename = dir.join("masked_excluded.idx");
+    let index = new_index(&["chr1"], &[(Position::forward(0, 10).unwrap(), 17)], true);
+    assert!(!index.masked_excluded());
+
+    index.with_masked_excluded(true).write(&filename).unwrap();
+    let index = KMerIndex::read(&filename).unwrap();
+
+    assert!(index.masked_excluded());
+    assert!(index.has_positions());
+}
+
+#[test]
+fn test_index_merge_masked_excluded() {
+    let index_1 = new_index(&["chr1"], &[], false).with_masked_excluded(true);
+    let index_2 = new_index(&["chr2"], &[], false).with_masked_excluded(true);
+    let index_3 = new_index(&["chr3"], &[], false);
+
+    assert!(KMerIndex::merge(&[&index_1, &index_2])
+        .unwrap()
+        .masked_excluded());
+    assert!(KMerIndex::merge(&[&index_1, &index_3]).is_err());
+}
+
+fn two_bit_sequences(sequences: &[&[u8]]) -> TwoBitSequences {
+    let mut packed = TwoBitSequences::default();
+    for sequence in sequences {
+        let (record, data) = TwoBitRecord::pack(sequence);
+        packed.push(record, &data);
+    }
+
+    packed
+}
+
+#[test]
+fn test_index_sequences_round_trip() {
+    let dir = TempDir::new();
+    let filename = dir.join("sequences.idx");
+    let index = new_index(
+        &["chr1", "chr2"],
+        &[(Position::forward(1, 3).unwrap(), 17)],
+        true,
+    );
+    assert!(!index.has_sequences());
+
+    let sequences = two_bit_sequences(&[b"ACGTNNACGTA", b"GATTACA"]);
+    index.with_sequences(sequences).write(&filename).unwrap();
+    let index = KMerIndex::read(&filename).unwrap();
+
+    assert!(index.has_sequences());
+    assert_eq!(index.sequence_len(0), Some(11));
+    assert_eq!(index.sequence_len(1), Some(7));
+    assert_eq!(index.fetch_sequence(0, 2, 8), Some(b"GTNNAC".to_vec()));
+    assert_eq!(index.fetch_sequence(1, 0, 7), Some(b"GATTACA".to_vec()));
+    assert_eq!(
+        index.get_positions(KMer::new(17)),
+        Some(&[Position::forward(1, 3).unwrap()][..])
+    );
+}
+
+#[test]
+fn test_index_merge_sequences() {
+    let index_1 = new_index(&["chr1"], &[], true).with_sequences(two_bit_sequences(&[b"ACGT"]));
+    let index_2 = new_index(&["chr2"], &[], true).with_sequences(two_bit_sequences(&[b"TTGCA"]));
+    let index_3 = new_index(&["chr3"], &[], true);
+
+    let merged = KMerIndex::merge(&[&index_1, &index_2]).unwrap();
+    assert_eq!(merged.fetch_sequence(0, 0, 4), Some(b"ACGT".to_vec()));
+    assert_eq!(merged.fetch_sequence(1, 0, 5), Some(b"TTGCA".to_vec()));
+
+    assert!(KMerIndex::merge(&[&index_1, &index_3]).is_err());
+    assert!(KMerIndex::merge(&[&index_3, &index_1]).is_err());
+}
+
+fn sequence_info(len: u64, fill: u8) -> SequenceInfo {
+    SequenceInfo {
+        len,
+        md5: [fill; 16],
+    }
+}
+
+#[test]
+fn test_index_provenance_round_trip() {
+    let provenance = Provenance {
+        sources: vec![Source {
+            fasta: "/data/genome.fasta".to_string(),
+            version: "0.2.0".to_string(),
+            command: "crispyr index /data/genome.fasta".to_string(),
+            filter: SequenceFilter::default(),
+        }],
+        sequences: vec![sequence_info(100, 1), sequence_info(200, 2)],
+    };
+
+    let dir = TempDir::new();
+    let filename = dir.join("provenance.idx");
+    new_index(
+        &["chr1", "chr2"],
+        &[(Position::forward(0, 10).unwrap(), 17)],
+        false,
+    )
+    .with_provenance(provenance.clone())
+    .unwrap()
+    .write(&filename)
+    .unwrap();
+    let index = KMerIndex::read(&filename).unwrap();
+
+    assert_eq!(index.provenance(), &provenance);
+    assert_eq!(index.get_count(0, KMer::new(17)), Some(1));
+}
+
+#[test]
+fn test_index_filter_round_trip() {
+    let filter = SequenceFilter {
+        include: vec!["chr1".to_string(), "chr2".to_string()],
+        exclude: vec!["chrM".to_string()],
+        include_regex: vec![SequenceFilter::regex("^chr[0-9]+$").unwrap()],
+        exclude_regex: vec![SequenceFilter::regex("_alt$").unwrap()],
+        bed: Some("/data/regions.bed".to_string()),
+        bed_md5: Some([7; 16]),
+        regions: vec![("chr1".to_string(), vec![(10, 20)])]
+            .into_iter()
+            .collect(),
+    };
+
+    let provenance = Provenance {
+        sources: vec![Source {
+            fasta: "/data/genome.fasta".to_string(),
+            version: "0.2.0".to_string(),
+            command: "crispyr index --include chr1,chr2 /data/genome.fasta".to_string(),
+            filter,
+        }],
+        sequences: vec![],
+    };
+
+    let dir = TempDir::new();
+    let filename = dir.join("filter.idx");
+    new_index(&["chr1", "chr2"], &[], false)
+        .with_provenance(provenance.clone())
+        .unwrap()
+        .write(&filename)
+        .unwrap();
+    let index = KMerIndex::read(&filename).unwrap();
+
+    assert_eq!(index.provenance(), &provenance);
+    assert_eq!(index.summarize_filters().len(), 1);
+    // Only the path and digest of the BED file are recorded
+    assert!(index.provenance().sources[0].filter.regions.is_empty());
+
+    // Previous versions recorded the regions instead of the digest
+    let mut data = std::fs::read(&filename).unwrap();
+    let start = (0..data.len() - 16)
+        .find(|&idx| data[idx..idx + 16] == [7; 16])
+        .unwrap();
+    let mut regions = 1u64.to_le_bytes().to_vec();
+    regions.extend(&4u32.to_le_bytes());
+    regions.extend(b"chr1");
+    regions.extend(&10u64.to_le_bytes());
+    regions.extend(&20u64.to_le_bytes());
+    data.splice(start..start + 16, regions);
+    data[INDEX_HEADER.len()] = BED_MD5_INDEX_VERSION - 1;
+    std::fs::write(&filename, data).unwrap();
+
+    let index = KMerIndex::read(&filename).unwrap();
+    let filter = &index.provenance().sources[0].filter;
+    assert_eq!(filter.bed.as_deref(), Some("/data/regions.bed"));
+    assert_eq!(filter.bed_md5, None);
+    assert!(filter.regions.is_empty());
+}
+
+#[test]
+fn test_index_merge_provenance() {
+    let source = |fasta: &str| Source {
+        fasta: fasta.to_string(),
+        version: "0.2.0".to_string(),
+        command: String::new(),
+        filter: SequenceFilter::default(),
+    };
+
+    let index_1 = new_index(&["chr1"], &[], false)
+        .with_provenance(Provenance {
+            sources: vec![source("a.fasta")],
+            sequences: vec![sequence_info(100, 1)],
+        })
+        .unwrap();
+    let index_2 = new_index(&["chr2", "chr3"], &[], false)
+        .with_provenance(Provenance {
+            sources: vec![source("b.fasta")],
+            sequences: vec![sequence_info(200, 2), sequence_info(300, 3)],
+        })
+        .unwrap();
+    let index_3 = new_index(&["chr4"], &[], false);
+
+    let merged = KMerIndex::merge(&[&index_1, &index_2]).unwrap();
+    assert_eq!(
+        merged.provenance(),
+        &Provenance {
+            sources: vec![source("a.fasta"), source("b.fasta")],
+            sequences: vec![
+                sequence_info(100, 1),
+                sequence_info(200, 2),
+                sequence_info(300, 3)
+            ],
+        }
+    );
+
+    // Checksums are dropped if not available for all sequences
+    let merged = KMerIndex::merge(&[&index_1, &index_3]).unwrap();
+    assert_eq!(merged.provenance().sources, vec![source("a.fasta")]);
+    assert!(merged.provenance().sequences.is_empty());
+}
+
+#[test]
+fn test_index_provenance_sequence_count() {
+    let provenance = Provenance {
+        sources: vec![],
+        sequences: vec![sequence_info(100, 1)],
+    };
+
+    let index = new_index(&["chr1", "chr2"], &[], false);
+    assert!(index.with_provenance(provenance).is_err());
+}
+
+/// Writes an index in the format used by CRISPyR v0.2.x (version 4)
+fn write_legacy_index(dir: &TempDir, positions: bool, kmers: &[(u32, Vec<u64>)]) -> PathBuf {
+    let mut data = b"CRISPyR".to_vec();
+    data.push(4);
+    data.push(4);
+    data.extend(b"Cas9");
+    data.extend(&(positions as u64).to_le_bytes());
+
+    if positions {
+        data.extend(&2u64.to_le_bytes());
+        for name in &["chr1", "chr2"] {
+            data.extend(&(name.len() as u16).to_le_bytes());
+            data.extend(name.as_bytes());
+        }
+    }
+
+    data.extend(&(kmers.len() as u64).to_le_bytes());
+    for (kmer, values) in kmers {
+        data.extend(&kmer.to_le_bytes());
+        data.extend(&(values.len() as u32).to_le_bytes());
+        if positions {
+            for value in values {
+                data.extend(&value.to_le_bytes());
+            }
+        }
+    }
+
+    let filename = dir.join("legacy.idx");
+    std::fs::write(&filename, data).unwrap();
+    filename
+}
+
+#[test]
+fn test_index_read_legacy_counts() {
+    let dir = TempDir::new();
+    let filename = write_legacy_index(&dir, false, &[(17, vec![0; 3]), (3, vec![0])]);
+    let index = KMerIndex::read(&filename).unwrap();
+
+    assert_eq!(index.version(), 4);
+    assert_eq!(index.enzyme(), &Enzyme::cas9());
+    assert!(!index.has_positions());
+    assert!(index.refseqs().is_empty());
+    assert_eq!(index.kmer_count(), 2);
+    assert_eq!(index.get_count(0, KMer::new(17)), Some(3));
+    assert_eq!(index.get_count(0, KMer::new(3)), Some(1));
+}
+
+#[test]
+fn test_index_read_legacy_positions() {
+    // Strand bit, 31 bit refseq, and 32 bit position
+    let forward = (1 << 63) | 10;
+    let reverse = (1 << 32) | 20;
+    let dir = TempDir::new();
+    let filename = write_legacy_index(&dir, true, &[(17, vec![forward, reverse])]);
+    let index = KMerIndex::read(&filename).unwrap();
+
+    assert_eq!(index.version(), 4);
+    assert!(index.has_positions());
+    assert_eq!(index.refseqs(), &["chr1", "chr2"]);
+    assert_eq!(
+        index.get_positions(KMer::new(17)),
+        Some(
+            &[
+                Position::forward(0, 10).unwrap(),
+                Position::reverse(1, 20).unwrap()
+            ][..]
+        )
+    );
+
+    // Upgrading the index
+    index.write(&filename).unwrap();
+    let index = KMerIndex::read(&filename).unwrap();
+
+    assert_eq!(index.version(), INDEX_VERSION);
+    assert_eq!(index.refseqs(), &["chr1", "chr2"]);
+    assert_eq!(
+        index.get_positions(KMer::new(17)),
+        Some(
+            &[
+                Position::forward(0, 10).unwrap(),
+                Position::reverse(1, 20).unwrap()
+            ][..]
+        )
+    );
+}
+
+#[test]
+fn test_index_read_unsupported_version() {
+    let dir = TempDir::new();
+    let filename = dir.join("unsupported.idx");
+    std::fs::write(&filename, b"CRISPyR\x03").unwrap();
+    let result = KMerIndex::read(&filename);
+
+    assert!(result.is_err());
+}
+
+/// Encodes a position using the two u64s (strand, PAM, and reference sequence, and position)
+/// used prior to `PACKED_POSITIONS_INDEX_VERSION`
+fn wide_position(position: Position) -> Vec<u8> {
+    let strand = if position.strand() == '+' { 1 << 63 } else { 0 };
+    let first = strand | ((position.pam() as u64) << 60) | u64::from(position.refseq());
+    let mut wide = first.to_le_bytes().to_vec();
+    wide.extend(&position.pos().to_le_bytes());
+    wide
+}
+
+/// Encodes a position using the single u64 (strand, PAM, 24 bit reference sequence, and 36 bit
+/// position) used prior to `WIDE_POSITIONS_INDEX_VERSION`
+fn packed_position(position: Position) -> Vec<u8> {
+    let strand = if position.strand() == '+' { 1 << 63 } else { 0 };
+    let packed = strand
+        | ((position.pam() as u64) << 60)
+        | (u64::from(position.refseq()) << 36)
+        | (position.pos() as u64 & ((1 << 36) - 1));
+    packed.to_le_bytes().to_vec()
+}
+
+/// Replaces the (consecutive) positions in an index file, including the padding following
+/// them, with positions encoded using a previous version of the index format
+fn replace_positions(data: &mut Vec<u8>, positions: &[Position], encode: fn(Position) -> Vec<u8>) {
+    let mut current = Vec::new();
+    for position in positions {
+        let (refseq, value) = position.to_raw();
+        current.extend(&refseq.to_le_bytes());
+        current.extend(&value.to_le_bytes());
+    }
+
+    let start = (0..data.len())
+        .step_by(8)
+        .find(|&idx| data[idx..].starts_with(&current))
+        .unwrap();
+    let end = start + ((current.len() + 7) & !7);
+    data.splice(start..end, positions.iter().flat_map(|v| encode(*v)));
+}
+
+#[test]
+fn test_index_read_previous_version() {
+    // Without sources, indexes differ only in the version number, the number of enzymes, and
+    // the size of positions
+    let dir = TempDir::new();
+    let filename = dir.join("previous_version.idx");
+    let position = Position::forward(0, 10).unwrap();
+    new_index(&["chr1"], &[(position, 17)], true)
+        .write(&filename)
+        .unwrap();
+
+    // Previous versions lack the number of enzymes; the byte is instead added to the padding
+    // preceding the K-mers (count, K-mer, offsets, and position; 48 bytes)
+    let mut data = std::fs::read(&filename).unwrap();
+    replace_positions(&mut data, &[position], wide_position);
+    data[INDEX_HEADER.len()] = MIN_INDEX_VERSION;
+    assert_eq!(data.remove(INDEX_HEADER.len() + 1), 1);
+    data.insert(data.len() - 48, 0);
+    std::fs::write(&filename, data).unwrap();
+    let index = KMerIndex::read(&filename).unwrap();
+
+    assert_eq!(index.version(), MIN_INDEX_VERSION);
+    assert_eq!(
+        index.get_positions(KMer::new(17)),
+        Some(&[Position::forward(0, 10).unwrap()][..])
+    );
+}
+
+/// Writes an index with positions on two sequences, replaces the positions with positions
+/// encoded using a previous version of the index format, and checks that they are converted
+fn check_index_read_previous_positions(version: u8, encode: fn(Position) -> Vec<u8>) {
+    let dir = TempDir::new();
+    let filename = dir.join("previous_positions.idx");
+    let positions = [
+        Position::forward(0, -2).unwrap(),
+        Position::reverse(1, 3).unwrap().with_pam(1).unwrap(),
+    ];
+    let index = new_index(
+        &["chr1", "chr2"],
+        &[(positions[1], 17), (positions[0], 17)],
+        true,
+    );
+    index
+        .with_sequences(two_bit_sequences(&[b"ACGTNNACGTA", b"GATTACA"]))
+        .write(&filename)
+        .unwrap();
+
+    let mut data = std::fs::read(&filename).unwrap();
+    replace_positions(&mut data, &positions, encode);
+    data[INDEX_HEADER.len()] = version;
+    std::fs::write(&filename, data).unwrap();
+
+    let index = KMerIndex::read(&filename).unwrap();
+    assert_eq!(index.version(), version);
+    assert_eq!(index.get_positions(KMer::new(17)), Some(&positions[..]));
+    assert_eq!(index.fetch_sequence(1, 0, 7), Some(b"GATTACA".to_vec()));
+
+    // Upgrading the index
+    index.write(&filename).unwrap();
+    let index = KMerIndex::read(&filename).unwrap();
+    assert_eq!(index.version(), INDEX_VERSION);
+    assert_eq!(index.get_positions(KMer::new(17)), Some(&positions[..]));
+    assert_eq!(index.fetch_sequence(1, 0, 7), Some(b"GATTACA".to_vec()));
+}
+
+#[test]
+fn test_index_read_wide_positions() {
+    check_index_read_previous_positions(PACKED_POSITIONS_INDEX_VERSION - 1, wide_position);
+}
+
+#[test]
+fn test_index_read_packed_positions() {
+    check_index_read_previous_positions(WIDE_POSITIONS_INDEX_VERSION - 1, packed_position);
+}
+
+/// Writes an index with a single K-mer and position, returning the file and its contents; the
+/// file ends with the K-mer count, K-mer, offsets, and padded position (48 bytes)
+fn write_corruptible_index(dir: &TempDir) -> (PathBuf, Vec<u8>) {
+    let filename = dir.join("index.idx");
+    new_index(&["chr1"], &[(Position::forward(0, 10).unwrap(), 17)], true)
+        .write(&filename)
+        .unwrap();
+    let data = std::fs::read(&filename).unwrap();
+
+    (filename, data)
+}
+
+#[test]
+fn test_index_read_truncated() {
+    let dir = TempDir::new();
+    let (filename, data) = write_corruptible_index(&dir);
+    for len in (data.len() - 48..data.len()).step_by(8) {
+        std::fs::write(&filename, &data[..len]).unwrap();
+        assert!(KMerIndex::read(&filename).is_err(), "{} bytes", len);
+    }
+}
+
+#[test]
+fn test_index_read_corrupt_counts() {
+    let dir = TempDir::new();
+    let (filename, mut data) = write_corruptible_index(&dir);
+    let kmer_count = data.len() - 48;
+    data[kmer_count..kmer_count + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+    std::fs::write(&filename, &data).unwrap();
+    assert!(KMerIndex::read(&filename).is_err());
+
+    let (_, mut data) = write_corruptible_index(&dir);
+    let position_count = data.len() - 24;
+    data[position_count..position_count + 8].copy_from_slice(&(u64::MAX / 2).to_le_bytes());
+    std::fs::write(&filename, &data).unwrap();
+    assert!(KMerIndex::read(&filename).is_err());
+}
+
+#[test]
+fn test_index_read_corrupt_offsets() {
+    let dir = TempDir::new();
+    let (filename, mut data) = write_corruptible_index(&dir);
+    let first_offset = data.len() - 32;
+    data[first_offset] = 2;
+    std::fs::write(&filename, &data).unwrap();
+
+    assert!(KMerIndex::read(&filename).is_err());
+}
+
+/// Builds an index of counts for the given enzyme, with sites on a single sequence
+fn new_enzyme_index(enzyme: &Enzyme, kmers: &[u64]) -> KMerIndex {
+    let enzyme = enzyme.clone().with_kmer_len(4);
+    let mut map = KMerMap::new(enzyme.pams.pams().len(), 4, false);
+    for kmer in kmers {
+        map.add(Position::forward(0, 10).unwrap(), KMer::new(*kmer));
+    }
+
+    KMerIndex::new(&enzyme, vec!["chr1".to_string()], map)
+}
+
+#[test]
+fn test_index_sections_round_trip() {
+    let dir = TempDir::new();
+    let filename = dir.join("sections.idx");
+    let cas9 = Enzyme::cas9();
+    let cas12a = Enzyme::get("AsCas12a").unwrap();
+    let index_1 = new_enzyme_index(&cas9, &[17, 3, 17]);
+    let index_2 = new_enzyme_index(&cas12a, &[5]);
+    KMerIndex::write_sections(&filename, &[&index_1, &index_2]).unwrap();
+
+    let enzymes = KMerIndex::read_enzymes(&filename).unwrap();
+    let index_1 = KMerIndex::read_section(&filename, Some(&cas9)).unwrap();
+    let index_2 = KMerIndex::read_section(&filename, Some(&cas12a)).unwrap();
+
+    assert_eq!(
+        enzymes,
+        vec![cas9.with_kmer_len(4), cas12a.with_kmer_len(4)]
+    );
+    assert_eq!(index_1.enzyme(), &enzymes[0]);
+    assert_eq!(index_1.refseqs(), &["chr1"]);
+    assert_eq!(index_1.kmer_count(), 2);
+    assert_eq!(index_1.get_count(0, KMer::new(17)), Some(2));
+    assert_eq!(index_1.get_count(0, KMer::new(5)), None);
+    assert_eq!(index_2.enzyme(), &enzymes[1]);
+    assert_eq!(index_2.kmer_count(), 1);
+    assert_eq!(index_2.get_count(0, KMer::new(5)), Some(1));
+    assert_eq!(index_2.get_count(0, KMer::new(17)), None);
+}
+
+#[test]
+fn test_index_read_sections() {
+    let dir = TempDir::new();
+    let filename = dir.join("read_sections.idx");
+    let cas9 = Enzyme::cas9();
+    let cas12a = Enzyme::get("AsCas12a").unwrap();
+    let index_1 = new_enzyme_index(&cas9, &[17, 3, 17]);
+    let index_2 = new_enzyme_index(&cas12a, &[5]);
+    KMerIndex::write_sections(&filename, &[&index_1, &index_2]).unwrap();
+
+    let sections = KMerIndex::read_sections(&filename).unwrap();
+    assert_eq!(sections.len(), 2);
+    assert_eq!(sections[0].enzyme(), &cas9.clone().with_kmer_len(4));
+    assert_eq!(sections[0].get_count(0, KMer::new(17)), Some(2));
+    assert_eq!(sections[1].enzyme(), &cas12a.clone().with_kmer_len(4));
+    assert_eq!(sections[1].get_count(0, KMer::new(5)), Some(1));
+
+    let (index, enzymes) = KMerIndex::read_section_with_enzymes(&filename, Some(&cas12a)).unwrap();
+    assert_eq!(index.enzyme(), &enzymes[1]);
+    assert_eq!(
+        enzymes,
+        vec![cas9.with_kmer_len(4), cas12a.with_kmer_len(4)]
+    );
+}
+
+#[test]
+fn test_index_sections_selection() {
+    let dir = TempDir::new();
+    let filename = dir.join("sections_selection.idx");
+    let cas9 = Enzyme::cas9();
+    let cas12a = Enzyme::get("AsCas12a").unwrap();
+    let index = new_enzyme_index(&cas9, &[17]);
+    KMerIndex::write_sections(&filename, &[&index]).unwrap();
+
+    // A single section is selected by default
+    assert!(KMerIndex::read(&filename).is_ok());
+    assert!(KMerIndex::read_section(&filename, Some(&cas9)).is_ok());
+    assert!(KMerIndex::read_section(&filename, Some(&cas12a)).is_err());
+
+    let index_2 = new_enzyme_index(&cas12a, &[5]);
+    KMerIndex::write_sections(&filename, &[&index, &index_2]).unwrap();
+    let result = KMerIndex::read(&filename);
+
+    // An enzyme must be selected if the index contains multiple enzymes
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_index_sections_incompatible() {
+    let dir = TempDir::new();
+    let filename = dir.join("sections_incompatible.idx");
+    let cas9 = Enzyme::cas9();
+    let cas12a = Enzyme::get("AsCas12a").unwrap();
+    let index_1 = new_enzyme_index(&cas9, &[17]);
+    let index_2 = new_enzyme_index(&cas9, &[5]);
+    let index_3 = KMerIndex::new(
+        &cas12a.with_kmer_len(4),
+        vec!["chr2".to_string()],
+        KMerMap::new(1, 4, false),
+    );
+
+    assert!(KMerIndex::write_sections(&filename, &[]).is_err());
+    assert!(KMerIndex::write_sections(&filename, &[&index_1, &index_2]).is_err());
+    assert!(KMerIndex::write_sections(&filename, &[&index_1, &index_3]).is_err());
+    assert!(!filename.exists());
+}
+
+#[test]
+fn test_index_sections_shared_sequences() {
+    let dir = TempDir::new();
+    let filename = dir.join("sections_sequences.idx");
+    let cas12a = Enzyme::get("AsCas12a").unwrap().with_kmer_len(4);
+    let mut kmers = KMerMap::new(1, 4, true);
+    kmers.add(Position::reverse(0, 2).unwrap(), KMer::new(5));
+
+    let sequences = two_bit_sequences(&[b"ACGTNNACGTA"]);
+    let index_1 = new_index(&["chr1"], &[(Position::forward(0, 3).unwrap(), 17)], true)
+        .with_sequences(sequences.clone());
+    let index_2 =
+        KMerIndex::new(&cas12a, vec!["chr1".to_string()], kmers).with_sequences(sequences);
+    KMerIndex::write_sections(&filename, &[&index_1, &index_2]).unwrap();
+
+    let index_1 = KMerIndex::read_section(&filename, Some(index_1.enzyme())).unwrap();
+    let index_2 = KMerIndex::read_section(&filename, Some(&cas12a)).unwrap();
+
+    for index in &[&index_1, &index_2] {
+        assert!(index.has_sequences());
+        assert_eq!(index.fetch_sequence(0, 2, 8), Some(b"GTNNAC".to_vec()));
+    }
 
-    assert_eq!(pos, Position::from_u64(repr));
+    assert_eq!(
+        index_1.get_positions(KMer::new(17)),
+        Some(&[Position::forward(0, 3).unwrap()][..])
+    );
+    assert_eq!(
+        index_2.get_positions(KMer::new(5)),
+        Some(&[Position::reverse(0, 2).unwrap()][..])
+    );
 }
@@ -0,0 +1,120 @@
+extern crate crispyr;
+
+use crispyr::api::{IndexBuilder, IndexOptions};
+use crispyr::commands::inspect::{histogram_bin, lookup, parse_query, report};
+use crispyr::common::encode_dna;
+use crispyr::enzyme::Enzyme;
+use crispyr::index::KMerIndex;
+
+mod fixtures;
+use fixtures::GUIDE;
+
+const KMER: &str = "GATCAGTACTGAT";
+
+fn build_index(positions: bool) -> KMerIndex {
+    let options = IndexOptions {
+        positions,
+        ..Default::default()
+    };
+
+    fixtures::build_index(&[GUIDE, GUIDE], options)
+}
+
+fn write_lookup(index: &KMerIndex, query: &str) -> String {
+    let queries = [(query, parse_query(index, query).unwrap())];
+    let mut out = Vec::new();
+    lookup(index, &queries, &mut out).unwrap();
+
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn test_histogram_bin() {
+    assert_eq!(histogram_bin(0), (0, 0));
+    assert_eq!(histogram_bin(9), (9, 9));
+    assert_eq!(histogram_bin(10), (10, 99));
+    assert_eq!(histogram_bin(99), (10, 99));
+    assert_eq!(histogram_bin(100), (100, 999));
+    assert_eq!(histogram_bin(12345), (10000, 99999));
+}
+
+#[test]
+fn test_parse_query() {
+    let index = build_index(false);
+    let kmer = encode_dna(KMER.as_bytes());
+
+    assert_eq!(parse_query(&index, KMER).ok(), kmer);
+    assert_eq!(parse_query(&index, "GATCACTGATCAGTACTGAT").ok(), kmer);
+    assert_eq!(parse_query(&index, "gatcactgatcagtactgattgg").ok(), kmer);
+    assert_eq!(parse_query(&index, "GATCACTGATCAGTACTGATTGG").ok(), kmer);
+
+    assert!(parse_query(&index, "GATCACTGATCAGTACTGATTAA").is_err());
+    assert!(parse_query(&index, "GATCAGTACTGA").is_err());
+    assert!(parse_query(&index, "GATCANTACTGAT").is_err());
+}
+
+#[test]
+fn test_parse_query_head_pam() {
+    let index = IndexBuilder::new(&Enzyme::ascas12a()).build().unwrap();
+    let kmer_len = index.enzyme().kmer_len();
+    let spacer = "AATCACTGATCAGTACTGATTGC";
+    let kmer = encode_dna(&spacer.as_bytes()[..kmer_len]);
+
+    assert_eq!(parse_query(&index, spacer).ok(), kmer);
+    assert_eq!(parse_query(&index, &format!("TTTA{}", spacer)).ok(), kmer);
+}
+
+#[test]
+fn test_lookup_positions() {
+    let index = build_index(true);
+
+    assert_eq!(
+        write_lookup(&index, "GATCACTGATCAGTACTGAT"),
+        format!(
+            "Query\tKMer\tPAM\tContig\tCutsite\tStrand\n\
+             GATCACTGATCAGTACTGAT\t{0}\tNGG\tchr1\t24\t+\n\
+             GATCACTGATCAGTACTGAT\t{0}\tNGG\tchr2\t24\t+\n",
+            KMER
+        )
+    );
+    assert_eq!(
+        write_lookup(&index, "AAAAAAAAAAAAA"),
+        "Query\tKMer\tPAM\tContig\tCutsite\tStrand\n\
+         AAAAAAAAAAAAA\tAAAAAAAAAAAAA\tNA\tNA\tNA\tNA\n"
+    );
+}
+
+#[test]
+fn test_lookup_counts() {
+    let index = build_index(false);
+
+    assert_eq!(
+        write_lookup(&index, KMER),
+        format!("Query\tKMer\tPAM\tSites\n{0}\t{0}\tNGG\t2\n", KMER)
+    );
+}
+
+#[test]
+fn test_report() {
+    let index = build_index(true);
+    let mut out = Vec::new();
+    report(&index, &[Enzyme::cas9()], 1, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+
+    assert!(out.starts_with("# Index\nKey\tValue\n"));
+    assert!(out.contains("\nEnzyme\tCas9\n"));
+    assert!(out.contains("\nPositions\tyes\n"));
+    assert!(out.contains("\nKMers\t1\n"));
+    assert!(out.contains("\nSites\t2\nForwardSites\t2\nReverseSites\t0\n"));
+    assert!(out.contains("\n# Sites per PAM\nPAM\tSites\nNGG\t2\n"));
+    let md5 = index.provenance().sequences[0].md5_hex();
+    assert!(out.contains(&format!(
+        "\nchr1\t35\t{0}\t1\t1\t0\nchr2\t35\t{0}\t1\t1\t0\n",
+        md5
+    )));
+    assert!(out.contains("\n# K-mer multiplicities\nMinSites\tMaxSites\tKMers\n2\t2\t1\n"));
+    assert!(out.ends_with(&format!(
+        "\n# Most repeated K-mers\nKMer\tSites\n{}\t2\n",
+        KMER
+    )));
+}
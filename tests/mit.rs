@@ -0,0 +1,45 @@
+extern crate crispyr;
+
+use crispyr::enzyme::Enzyme;
+use crispyr::mit::MIT;
+
+#[test]
+fn test_mit_requires_3prime_pam() {
+    assert!(MIT::new(&Enzyme::cas9()).is_ok());
+    assert!(MIT::new(&Enzyme::sacas9()).is_ok());
+    assert!(MIT::new(&Enzyme::ascas12a()).is_err());
+    assert!(MIT::new(&Enzyme::cas9().with_kmer_len(20)).is_ok());
+}
+
+#[test]
+fn test_mit_perfect_match() {
+    let mit = MIT::new(&Enzyme::cas9()).unwrap();
+
+    assert_eq!(mit.score(0), 100.0);
+}
+
+#[test]
+fn test_mit_single_mismatch() {
+    let mit = MIT::new(&Enzyme::cas9()).unwrap();
+
+    // Position 20 (adjacent to the PAM)
+    assert!((mit.score(0b1) - 41.7).abs() < 1e-9);
+    // Position 8 (last nucleotide of 13 bp K-mer) is fully tolerated
+    assert!((mit.score(1 << 12) - 100.0).abs() < 1e-9);
+}
+
+#[test]
+fn test_mit_multiple_mismatches() {
+    let mit = MIT::new(&Enzyme::cas9()).unwrap();
+
+    // Positions 19 and 20; mean pairwise distance of 1
+    let expected = 100.0 * (1.0 - 0.685) * (1.0 - 0.583) / ((18.0 / 19.0) * 4.0 + 1.0) / 4.0;
+    assert!((mit.score(0b11) - expected).abs() < 1e-9);
+}
+
+#[test]
+fn test_mit_ignores_mismatches_outside_kmer() {
+    let mit = MIT::new(&Enzyme::cas9()).unwrap();
+
+    assert_eq!(mit.score(1 << 13), 100.0);
+}
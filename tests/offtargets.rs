@@ -0,0 +1,116 @@
+extern crate crispyr;
+
+use crispyr::api::IndexOptions;
+use crispyr::args::OffTargetsArgs;
+use crispyr::commands::offtargets;
+use crispyr::score::{Model, ScoringProfile};
+
+mod fixtures;
+mod util;
+use fixtures::{build_index, GUIDE, VARIANT, VARIANT_2};
+use util::TempDir;
+
+/// Writes an index with off-targets of `GUIDE` with 0, 1, and 2 mismatches outside the K-mer,
+/// along with a table of queries, and returns arguments for the 'offtargets' command
+fn setup(dir: &TempDir, queries: &[&[u8]]) -> OffTargetsArgs {
+    let options = IndexOptions {
+        positions: true,
+        embed_sequences: true,
+        ..Default::default()
+    };
+    let index = dir.join("offtargets.idx");
+    build_index(&[GUIDE, VARIANT, VARIANT_2], options)
+        .write(&index)
+        .unwrap();
+    let table = dir.join("queries.tsv");
+    std::fs::write(&table, queries.join(&b'\n')).unwrap();
+
+    OffTargetsArgs {
+        index: index.to_str().unwrap().to_string(),
+        enzyme: None,
+        models: vec![Model::CRISPy],
+        profile: ScoringProfile::default(),
+        table: table.to_str().unwrap().to_string(),
+        fasta: None,
+        verify_fasta: false,
+        output: Some(dir.join("output.tsv").to_str().unwrap().to_string()),
+        min_score: 0,
+        verify: true,
+        max_mismatches: 4,
+    }
+}
+
+/// Runs the 'offtargets' command, returning the rows of the output (excluding the header)
+fn run(args: &OffTargetsArgs) -> Vec<Vec<String>> {
+    offtargets::main(args).unwrap();
+
+    let output = std::fs::read_to_string(args.output.as_ref().unwrap()).unwrap();
+    output
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .skip(1)
+        .map(|line| line.split('\t').map(|v| v.to_string()).collect())
+        .collect()
+}
+
+/// Returns the name and the number of mismatches of each off-target
+fn mismatches(rows: &[Vec<String>]) -> Vec<(&str, &str)> {
+    let mut values: Vec<_> = rows
+        .iter()
+        .map(|row| (row[2].as_str(), row[row.len() - 2].as_str()))
+        .collect();
+    values.sort_unstable();
+    values
+}
+
+#[test]
+fn test_offtargets_verify() {
+    let dir = TempDir::new();
+    let args = setup(&dir, &[GUIDE]);
+
+    assert_eq!(
+        mismatches(&run(&args)),
+        vec![("chr1", "0"), ("chr2", "1"), ("chr3", "2")]
+    );
+}
+
+#[test]
+fn test_offtargets_verify_max_mismatches() {
+    let dir = TempDir::new();
+    let mut args = setup(&dir, &[GUIDE]);
+
+    args.max_mismatches = 1;
+    assert_eq!(mismatches(&run(&args)), vec![("chr1", "0"), ("chr2", "1")]);
+    args.max_mismatches = 0;
+    assert_eq!(mismatches(&run(&args)), vec![("chr1", "0")]);
+
+    // The spacer of Cas9 gRNAs is 20 bp long
+    args.max_mismatches = 20;
+    assert_eq!(run(&args).len(), 3);
+    args.max_mismatches = 21;
+    assert!(offtargets::main(&args).is_err());
+}
+
+#[test]
+fn test_offtargets_verify_short_query() {
+    let dir = TempDir::new();
+    // Queries must include the full spacer for mismatches to be verified
+    let args = setup(&dir, &[&GUIDE[2..]]);
+
+    assert_eq!(
+        mismatches(&run(&args)),
+        vec![("chr1", "NA"), ("chr2", "NA"), ("chr3", "NA")]
+    );
+}
+
+#[test]
+fn test_offtargets_without_verify() {
+    let dir = TempDir::new();
+    let mut args = setup(&dir, &[GUIDE]);
+    args.verify = false;
+    args.max_mismatches = 0;
+
+    let rows = run(&args);
+    assert_eq!(rows.len(), 3);
+    assert!(rows.iter().all(|row| row.len() == 10));
+}
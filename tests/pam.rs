@@ -1,7 +1,7 @@
 extern crate crispyr;
 
 use crispyr::common::encode_dna;
-use crispyr::pam::{Position, PAM};
+use crispyr::pam::{PAMSet, Position, PAM};
 
 #[test]
 fn test_pam_lengths() {
@@ -166,3 +166,57 @@ fn test_enzyme_kmer_mut_mad7() {
         b"cTTactaTccaCa"
     );
 }
+
+#[test]
+fn test_pam_set_parse() {
+    let pams = PAMSet::parse("ngg, NAG:0.25", Position::Tail).unwrap();
+
+    assert_eq!(pams.pams(), &[PAM::tail(b"NGG"), PAM::tail(b"NAG")]);
+    assert_eq!(pams.weights(), &[1.0, 0.25]);
+    assert_eq!(pams.to_string(), "NGG,NAG:0.25");
+}
+
+#[test]
+fn test_pam_set_find() {
+    let pams = PAMSet::new(PAM::tail(b"NGG")).with_pam(PAM::tail(b"NAG"), 0.25);
+
+    assert_eq!(pams.find(b"ACTGG"), Some(0));
+    assert_eq!(pams.find(b"ACTAG"), Some(1));
+    assert_eq!(pams.find(b"ACTGA"), None);
+    assert!(pams.matches(b"ACTAG"));
+    assert!(!pams.matches(b"ACTGA"));
+}
+
+#[test]
+fn test_pam_set_kmer() {
+    let pams = PAMSet::new(PAM::tail(b"NGG")).with_pam(PAM::tail(b"NAG"), 0.25);
+    let kmer = encode_dna(b"ACTGAGTCAGATA").unwrap();
+
+    assert_eq!(pams.kmer(b"ACTGAGTCAGATATGG"), Some((0, 13, kmer)));
+    assert_eq!(pams.kmer(b"ACTGAGTCAGATATAG"), Some((1, 13, kmer)));
+    assert_eq!(pams.kmer(b"ACTGAGTCAGATATGA"), None);
+}
+
+#[test]
+fn test_pam_custom_kmer_len() {
+    let pam = PAM::tail(b"NGG").with_kmer_len(20);
+    let kmer = encode_dna(b"ACTGAGTCAGATAGCTAGCT").unwrap();
+
+    assert_eq!(pam.kmer_len(), 20);
+    assert_eq!(pam.kmer(b"ACTGAGTCAGATAGCTAGCTAGG"), Some((20, kmer)));
+    assert_eq!(pam.kmer(b"CTGAGTCAGATAGCTAGCTAGG"), None);
+    assert_eq!(
+        pam.kmer_slice(b"TTACTGAGTCAGATAGCTAGCTAGG"),
+        b"ACTGAGTCAGATAGCTAGCT"
+    );
+}
+
+#[test]
+fn test_pam_set_custom_kmer_len() {
+    let pams = PAMSet::new(PAM::tail(b"NGG"))
+        .with_kmer_len(16)
+        .with_pam(PAM::tail(b"NAG"), 0.25);
+
+    assert_eq!(pams.kmer_len(), 16);
+    assert!(pams.pams().iter().all(|pam| pam.kmer_len() == 16));
+}
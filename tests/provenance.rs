@@ -0,0 +1,101 @@
+extern crate crispyr;
+
+use std::path::PathBuf;
+
+use crispyr::fasta::open_indexed;
+use crispyr::provenance::{Provenance, SequenceInfo};
+
+mod util;
+use util::TempDir;
+
+#[test]
+fn test_sequence_info() {
+    let info = SequenceInfo::new(b"");
+    assert_eq!(info.len, 0);
+    assert_eq!(info.md5_hex(), "d41d8cd98f00b204e9800998ecf8427e");
+
+    let info = SequenceInfo::new(b"ACGT");
+    assert_eq!(info.len, 4);
+    assert_eq!(info.md5_hex(), "f1f8f4bf413b16ad135722aa4591043e");
+}
+
+/// Writes a FASTA file with a single sequence per line, along with a FASTA index
+fn write_fasta(dir: &TempDir, records: &[(&str, &str)]) -> PathBuf {
+    let filename = dir.join("sequences.fasta");
+
+    let mut fasta = String::new();
+    let mut fai = String::new();
+    for (name, sequence) in records {
+        fasta.push_str(&format!(">{}\n", name));
+        fai.push_str(&format!(
+            "{}\t{}\t{}\t{}\t{}\n",
+            name,
+            sequence.len(),
+            fasta.len(),
+            sequence.len(),
+            sequence.len() + 1
+        ));
+        fasta.push_str(&format!("{}\n", sequence));
+    }
+
+    std::fs::write(&filename, fasta).unwrap();
+    std::fs::write(filename.with_extension("fasta.fai"), fai).unwrap();
+
+    filename
+}
+
+fn verify(records: &[(&str, &str)], checksums: bool) -> crispyr::errors::Result<usize> {
+    let refseqs = vec!["chr1".to_string(), "chr2".to_string()];
+    let provenance = Provenance {
+        sources: vec![],
+        sequences: vec![
+            SequenceInfo::new(b"ACGTACGT"),
+            SequenceInfo::new(b"TTTTGGGGCCCC"),
+        ],
+    };
+
+    let dir = TempDir::new();
+    let filename = write_fasta(&dir, records);
+    let mut reader = open_indexed(filename.to_str().unwrap()).unwrap();
+
+    provenance.verify(&refseqs, &mut reader, "test.fasta", checksums)
+}
+
+#[test]
+fn test_provenance_verify_matching() {
+    let records = [("chr1", "ACGTACGT"), ("chr2", "ttttGGGGcccc")];
+    assert_eq!(verify(&records, true).unwrap(), 2);
+    assert_eq!(verify(&records, false).unwrap(), 2);
+}
+
+#[test]
+fn test_provenance_verify_subset() {
+    let records = [("chr2", "TTTTGGGGCCCC"), ("chr3", "A")];
+    assert_eq!(verify(&records, true).unwrap(), 1);
+}
+
+#[test]
+fn test_provenance_verify_disjoint() {
+    let records = [("chr3", "ACGTACGT")];
+    assert!(verify(&records, false).is_err());
+}
+
+#[test]
+fn test_provenance_verify_mismatch() {
+    assert!(verify(&[("chr1", "ACGTACG")], false).is_err());
+    assert!(verify(&[("chr1", "ACGTACGA")], true).is_err());
+
+    // Only lengths are compared unless checksums are requested
+    assert_eq!(verify(&[("chr1", "ACGTACGA")], false).unwrap(), 1);
+}
+
+#[test]
+fn test_provenance_verify_without_checksums() {
+    let refseqs = vec!["chr1".to_string()];
+    let dir = TempDir::new();
+    let filename = write_fasta(&dir, &[("chr1", "ACGT")]);
+    let mut reader = open_indexed(filename.to_str().unwrap()).unwrap();
+
+    let result = Provenance::default().verify(&refseqs, &mut reader, "test.fasta", true);
+    assert_eq!(result.unwrap(), 0);
+}
@@ -0,0 +1,113 @@
+extern crate crispyr;
+
+use crispyr::api::IndexBuilder;
+use crispyr::enzyme::Enzyme;
+use crispyr::score::{Model, Scorer, ScoringProfile};
+
+#[test]
+fn test_model_parse() {
+    assert_eq!(Model::parse("crispy").unwrap(), Model::CRISPy);
+    assert_eq!(Model::parse("CFD").unwrap(), Model::CFD);
+    assert_eq!(Model::parse("mit").unwrap(), Model::MIT);
+    assert!(Model::parse("foo").is_err());
+}
+
+#[test]
+fn test_profile_default() {
+    let profile = ScoringProfile::default();
+
+    assert_eq!(profile.seed_size, 5);
+    assert_eq!(profile.max_mutations, 3);
+    assert_eq!(profile.max_seed_mutations, 2);
+    assert_eq!(profile.matrix[0], vec![500, 100, 50, 20, 3]);
+    assert!(profile.validate().is_ok());
+}
+
+#[test]
+fn test_profile_parse() {
+    let profile = ScoringProfile::parse(
+        "# comment\nseed_size = 8\nmax_mutations = 2\nmax_seed_mutations = 1\nmatrix = 1,2,3; 4,5\n",
+    )
+    .unwrap();
+
+    assert_eq!(profile.seed_size, 8);
+    assert_eq!(profile.max_mutations, 2);
+    assert_eq!(profile.max_seed_mutations, 1);
+    assert_eq!(profile.matrix, vec![vec![1, 2, 3], vec![4, 5]]);
+}
+
+#[test]
+fn test_profile_parse_defaults() {
+    let profile = ScoringProfile::parse("seed_size = 8\n").unwrap();
+
+    assert_eq!(
+        profile,
+        ScoringProfile {
+            seed_size: 8,
+            ..ScoringProfile::default()
+        }
+    );
+}
+
+#[test]
+fn test_profile_parse_invalid() {
+    assert!(ScoringProfile::parse("seed_size 8\n").is_err());
+    assert!(ScoringProfile::parse("seed_size = -1\n").is_err());
+    assert!(ScoringProfile::parse("foo = 1\n").is_err());
+    assert!(ScoringProfile::parse("matrix = 1,2,x\n").is_err());
+}
+
+#[test]
+fn test_profile_validate() {
+    // Too few rows for 2 seed mismatches
+    assert!(ScoringProfile::parse("matrix = 1,2,3,4; 1,2,3\n").is_err());
+    // Too few columns for 3 mismatches
+    assert!(ScoringProfile::parse("matrix = 1,2,3; 1,2,3; 1,2\n").is_err());
+    // More seed mismatches than mismatches
+    assert!(ScoringProfile::parse("max_mutations = 1\n").is_err());
+    assert!(ScoringProfile::parse("max_mutations = 4\n").is_ok());
+    assert!(ScoringProfile::parse("max_mutations = 5\n").is_err());
+    // Too many mismatches to enumerate
+    let matrix = "matrix = 1,2,3,4,5,6,7; 1,2,3,4,5,6; 1,2,3,4,5\n";
+    assert!(ScoringProfile::parse(&format!("max_mutations = 5\n{}", matrix)).is_ok());
+    assert!(ScoringProfile::parse(&format!("max_mutations = 6\n{}", matrix)).is_err());
+}
+
+#[test]
+fn test_profile_to_string_roundtrip() {
+    let profile = ScoringProfile::parse("seed_size = 8\nmatrix = 1,2,3,4; 4,5,6; 7,8\n").unwrap();
+
+    assert_eq!(
+        ScoringProfile::parse(&profile.to_string()).unwrap(),
+        profile
+    );
+    assert_eq!(
+        ScoringProfile::parse(&profile.to_header()).unwrap(),
+        ScoringProfile::default()
+    );
+}
+
+#[test]
+fn test_profile_default_header() {
+    assert_eq!(ScoringProfile::default().to_header(), "");
+
+    let profile = ScoringProfile::parse("seed_size = 8\n").unwrap();
+    assert_eq!(profile.to_header().lines().count(), 4);
+    assert!(profile
+        .to_header()
+        .lines()
+        .all(|line| line.starts_with("# ")));
+}
+
+#[test]
+fn test_scorer_max_mutations_limited_by_kmer_len() {
+    let index = IndexBuilder::new(&Enzyme::cas9().with_kmer_len(4))
+        .build()
+        .unwrap();
+    let profile =
+        ScoringProfile::parse("max_mutations = 5\nmatrix = 1,2,3,4,5,6; 1,2,3,4,5; 1,2,3,4\n")
+            .unwrap();
+
+    assert!(Scorer::new(&index, &profile, &[Model::CRISPy]).is_err());
+    assert!(Scorer::new(&index, &ScoringProfile::default(), &[Model::CRISPy]).is_ok());
+}
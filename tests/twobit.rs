@@ -0,0 +1,70 @@
+extern crate crispyr;
+
+use crispyr::twobit::{packed_len, TwoBitRecord, TwoBitSequences};
+
+#[test]
+fn test_packed_len() {
+    assert_eq!(packed_len(0), 0);
+    assert_eq!(packed_len(1), 1);
+    assert_eq!(packed_len(4), 1);
+    assert_eq!(packed_len(5), 2);
+}
+
+#[test]
+fn test_pack() {
+    let (record, packed) = TwoBitRecord::pack(b"ACGTacgtA");
+
+    assert_eq!(record.len, 9);
+    assert_eq!(record.runs, vec![]);
+    assert_eq!(record.offset, 0);
+    assert_eq!(packed, vec![0b0001_1011, 0b0001_1011, 0b0000_0000]);
+}
+
+#[test]
+fn test_pack_runs() {
+    let (record, _) = TwoBitRecord::pack(b"NNACGNNNTRN");
+
+    assert_eq!(record.runs, vec![(0, 2), (5, 3), (9, 2)]);
+}
+
+#[test]
+fn test_fetch() {
+    let sequence = b"ACGTTGCAacgtNNNNGATTACAn";
+    let (record, packed) = TwoBitRecord::pack(sequence);
+
+    assert_eq!(
+        record.fetch(&packed, 0, sequence.len() as u64),
+        b"ACGTTGCAACGTNNNNGATTACAN".to_vec()
+    );
+    assert_eq!(record.fetch(&packed, 3, 7), b"TTGC".to_vec());
+    assert_eq!(record.fetch(&packed, 10, 14), b"GTNN".to_vec());
+    assert_eq!(record.fetch(&packed, 13, 18), b"NNNGA".to_vec());
+    assert_eq!(record.fetch(&packed, 16, 23), b"GATTACA".to_vec());
+    assert_eq!(record.fetch(&packed, 5, 5), b"".to_vec());
+}
+
+#[test]
+#[should_panic]
+fn test_fetch_past_end() {
+    let (record, packed) = TwoBitRecord::pack(b"ACGT");
+
+    record.fetch(&packed, 2, 5);
+}
+
+#[test]
+fn test_sequences_push() {
+    let mut sequences = TwoBitSequences::default();
+
+    let (record1, packed1) = TwoBitRecord::pack(b"ACGTA");
+    let (record2, packed2) = TwoBitRecord::pack(b"TTNNG");
+    sequences.push(record1, &packed1);
+    sequences.push(record2, &packed2);
+
+    assert_eq!(sequences.records[0].offset, 0);
+    assert_eq!(sequences.records[1].offset, 2);
+    assert_eq!(sequences.data.len(), 4);
+
+    let data = &sequences.data;
+    assert_eq!(sequences.records[0].fetch(data, 0, 5), b"ACGTA".to_vec());
+    assert_eq!(sequences.records[1].fetch(data, 0, 5), b"TTNNG".to_vec());
+}